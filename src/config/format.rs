@@ -1,6 +1,5 @@
 use std::path::Path;
-use std::fs::File;
-use std::io::{Error as IoError, Read};
+use std::io::Error as IoError;
 
 use serde::Deserialize;
 use serde_yaml::Error as YamlError;
@@ -8,6 +7,7 @@ use serde_json::Error as JsonError;
 use strum::{EnumString, EnumIter, AsRefStr};
 use thiserror::Error;
 
+use crate::fs::{DiskFs, Fs};
 use crate::metadata::{Arity, Schema, SchemaRepr};
 
 #[derive(Debug, Error)]
@@ -55,13 +55,31 @@ impl Format {
     }
 
     pub fn read_schema_path(&self, path: &Path, arity: &Arity) -> Result<Schema, Error> {
-        let mut f = File::open(path).map_err(Error::CannotOpenFile)?;
+        self.read_schema_path_in(&DiskFs, path, arity)
+    }
+
+    /// Similar to `read_schema_path`, but reads from a given filesystem.
+    pub fn read_schema_path_in(&self, fs: &dyn Fs, path: &Path, arity: &Arity) -> Result<Schema, Error> {
+        let mut f = fs.open(path).map_err(Error::CannotOpenFile)?;
 
         let mut buffer = String::new();
         f.read_to_string(&mut buffer).map_err(Error::CannotReadFile)?;
 
         self.read_schema_str(&buffer, arity)
     }
+
+    /// Async version of `read_schema_path`.
+    #[cfg(feature = "async")]
+    pub async fn read_schema_path_async(&self, path: &Path, arity: &Arity) -> Result<Schema, Error> {
+        let mut f = tokio::fs::File::open(path).await.map_err(Error::CannotOpenFile)?;
+
+        let mut buffer = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut f, &mut buffer)
+            .await
+            .map_err(Error::CannotReadFile)?;
+
+        self.read_schema_str(&buffer, arity)
+    }
 }
 
 #[cfg(test)]
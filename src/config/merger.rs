@@ -0,0 +1,440 @@
+//! Defines how metadata blocks from multiple sources are combined.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::types::{Block, Value};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(
+        r#"key "{key}" was made final by "{}" and cannot be changed by "{}""#,
+        .final_origin.display(),
+        .origin.display(),
+    )]
+    FinalKey { key: String, origin: PathBuf, final_origin: PathBuf },
+}
+
+/// Records which origins have made top-level keys final or deleted them while
+/// merging the blocks for a single item.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Directives {
+    /// Keys that have been made final, along with the origin that did so.
+    pub final_keys: HashMap<String, PathBuf>,
+
+    /// Keys that have been deleted by a tombstone, along with the origin of
+    /// the tombstone. A key is removed from here if it is set again later.
+    pub deleted_keys: HashMap<String, PathBuf>,
+}
+
+/// Represents the ways that a value from a later source can be combined with
+/// an existing value from an earlier source.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// The later value overwrites the earlier one.
+    #[default]
+    Replace,
+
+    /// If both values are mappings, their keys are merged recursively.
+    /// Otherwise, the later value overwrites the earlier one.
+    Deep,
+
+    /// If both values are sequences, the later one is appended to the earlier
+    /// one. Otherwise, the later value overwrites the earlier one.
+    Append,
+
+    /// Similar to `Append`, but skips any elements of the later sequence that
+    /// are already present in the earlier one.
+    Union,
+
+    /// The earlier value is kept, and the later value is discarded.
+    KeepFirst,
+}
+
+impl MergeStrategy {
+    /// Combines an incoming value into an existing one using this strategy.
+    pub fn merge_values(&self, existing: &mut Value, incoming: Value) {
+        match (self, existing, incoming) {
+            (Self::KeepFirst, _, _) => {},
+            (Self::Deep, Value::Mapping(ex_block), Value::Mapping(in_block)) => {
+                for (key, in_val) in in_block {
+                    Self::Deep.merge_entry(ex_block, key, in_val);
+                }
+            },
+            (Self::Append, Value::Sequence(ex_seq), Value::Sequence(in_seq)) => {
+                ex_seq.extend(in_seq);
+            },
+            (Self::Union, Value::Sequence(ex_seq), Value::Sequence(in_seq)) => {
+                for in_val in in_seq {
+                    if !ex_seq.contains(&in_val) {
+                        ex_seq.push(in_val);
+                    }
+                }
+            },
+            (_, existing, incoming) => { *existing = incoming; },
+        }
+    }
+
+    /// Combines an incoming key-value pair into a block using this strategy.
+    fn merge_entry(&self, block: &mut Block, key: String, incoming: Value) {
+        match block.get_mut(&key) {
+            Some(existing) => self.merge_values(existing, incoming),
+            None => { block.insert(key, incoming); },
+        }
+    }
+}
+
+/// A struct that contains all of the information needed to combine metadata
+/// blocks from multiple sources for a single item.
+///
+/// Tombstones and final keys are only interpreted for top-level keys. Values
+/// nested inside of mappings, including ones combined with the `Deep`
+/// strategy, are merged as-is.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Merger {
+    /// The strategy used for keys that do not have their own strategy.
+    pub strategy: MergeStrategy,
+
+    /// Strategies for specific top-level keys, overriding the default one.
+    pub keys: HashMap<String, MergeStrategy>,
+
+    /// If true, a meta file that is found for an item file but does not
+    /// provide a block for it is treated as contributing nothing, instead of
+    /// being an error.
+    pub skip_missing: bool,
+
+    /// A string value that, when used as the value of a top-level key, deletes
+    /// that key instead of setting it, such as `"!delete"`. This is disabled
+    /// by default, with an empty string.
+    pub tombstone: String,
+
+    /// A suffix that, when added to a top-level key name, marks that key as
+    /// final, so that later sources cannot change it, such as `"!"`. The
+    /// suffix is removed from the key name. This is disabled by default, with
+    /// an empty string.
+    pub final_suffix: String,
+}
+
+impl Merger {
+    /// Returns the strategy to use for a given top-level key.
+    pub fn strategy_for(&self, key: &str) -> MergeStrategy {
+        self.keys.get(key).copied().unwrap_or(self.strategy)
+    }
+
+    /// Combines an incoming block into an existing one. Keys in the incoming
+    /// block are merged into the existing block using their configured strategy.
+    /// Tombstones and final keys are not interpreted, see `merge_directed`.
+    pub fn merge(&self, existing: &mut Block, incoming: Block) {
+        for (key, in_val) in incoming {
+            let strategy = self.strategy_for(&key);
+            strategy.merge_entry(existing, key, in_val);
+        }
+    }
+
+    /// Similar to `merge`, but also interprets tombstones and final keys in
+    /// the incoming block. `directives` records the keys that have been made
+    /// final or deleted so far, along with their origins, and should be reused
+    /// across all of the blocks merged for an item. Setting a final key to the
+    /// value it already has is allowed; any other change is an error.
+    pub fn merge_directed(
+        &self,
+        existing: &mut Block,
+        incoming: Block,
+        origin: &Path,
+        directives: &mut Directives,
+    ) -> Result<(), Error> {
+        for (raw_key, in_val) in incoming {
+            let (key, is_final) = match self.strip_final_suffix(&raw_key) {
+                Some(key) => (key.to_string(), true),
+                None => (raw_key, false),
+            };
+
+            let is_tombstone = self.is_tombstone(&in_val);
+
+            if let Some(final_origin) = directives.final_keys.get(&key) {
+                let is_unchanged = match existing.get(&key) {
+                    Some(ex_val) => !is_tombstone && ex_val == &in_val,
+                    None => is_tombstone,
+                };
+
+                if is_unchanged {
+                    continue;
+                }
+
+                return Err(Error::FinalKey {
+                    key,
+                    origin: origin.to_path_buf(),
+                    final_origin: final_origin.clone(),
+                });
+            }
+
+            if is_tombstone {
+                existing.remove(&key);
+                directives.deleted_keys.insert(key.clone(), origin.to_path_buf());
+            } else {
+                directives.deleted_keys.remove(&key);
+                self.strategy_for(&key).merge_entry(existing, key.clone(), in_val);
+            }
+
+            if is_final {
+                directives.final_keys.insert(key, origin.to_path_buf());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn strip_final_suffix<'k>(&self, key: &'k str) -> Option<&'k str> {
+        if self.final_suffix.is_empty() {
+            None
+        } else {
+            key.strip_suffix(self.final_suffix.as_str())
+        }
+    }
+
+    fn is_tombstone(&self, value: &Value) -> bool {
+        match value {
+            Value::String(s) => !self.tombstone.is_empty() && s == &self.tombstone,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::{btreemap, hashmap};
+    use str_macro::str;
+
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn deserialization() {
+        let text = r#"
+            strategy = "deep"
+            skip_missing = true
+            [keys]
+            genres = "union"
+            title = "keep_first"
+        "#;
+        let merger: Merger = toml::from_str(text).unwrap();
+
+        assert_eq!(merger.strategy, MergeStrategy::Deep);
+        assert!(merger.skip_missing);
+        assert_eq!(merger.tombstone, "");
+        assert_eq!(merger.final_suffix, "");
+        assert_eq!(
+            merger.keys,
+            hashmap![
+                str!("genres") => MergeStrategy::Union,
+                str!("title") => MergeStrategy::KeepFirst,
+            ],
+        );
+
+        let merger: Merger = toml::from_str("").unwrap();
+        assert_eq!(merger, Merger::default());
+
+        let merger: Merger = toml::from_str("tombstone = '!delete'\nfinal_suffix = '!'").unwrap();
+        assert_eq!(merger.tombstone, "!delete");
+        assert_eq!(merger.final_suffix, "!");
+    }
+
+    #[test]
+    fn merge_values() {
+        let map_a = Value::Mapping(Block(btreemap![
+            str!("key_a") => TU::s("val_a"),
+            str!("key_b") => Value::Mapping(Block(btreemap![
+                str!("sub_key_a") => TU::s("sub_val_a"),
+            ])),
+        ]));
+        let map_b = Value::Mapping(Block(btreemap![
+            str!("key_b") => Value::Mapping(Block(btreemap![
+                str!("sub_key_b") => TU::s("sub_val_b"),
+            ])),
+            str!("key_c") => TU::s("val_c"),
+        ]));
+        let seq_a = Value::Sequence(vec![TU::s("val_a"), TU::s("val_b")]);
+        let seq_b = Value::Sequence(vec![TU::s("val_b"), TU::s("val_c")]);
+
+        let inputs_and_expected = vec![
+            ((MergeStrategy::Replace, &map_a, &map_b), map_b.clone()),
+            ((MergeStrategy::KeepFirst, &map_a, &map_b), map_a.clone()),
+            (
+                (MergeStrategy::Deep, &map_a, &map_b),
+                Value::Mapping(Block(btreemap![
+                    str!("key_a") => TU::s("val_a"),
+                    str!("key_b") => Value::Mapping(Block(btreemap![
+                        str!("sub_key_a") => TU::s("sub_val_a"),
+                        str!("sub_key_b") => TU::s("sub_val_b"),
+                    ])),
+                    str!("key_c") => TU::s("val_c"),
+                ])),
+            ),
+            ((MergeStrategy::Deep, &map_a, &seq_b), seq_b.clone()),
+            ((MergeStrategy::Replace, &seq_a, &seq_b), seq_b.clone()),
+            (
+                (MergeStrategy::Append, &seq_a, &seq_b),
+                Value::Sequence(vec![
+                    TU::s("val_a"), TU::s("val_b"), TU::s("val_b"), TU::s("val_c"),
+                ]),
+            ),
+            (
+                (MergeStrategy::Union, &seq_a, &seq_b),
+                Value::Sequence(vec![TU::s("val_a"), TU::s("val_b"), TU::s("val_c")]),
+            ),
+            ((MergeStrategy::Append, &seq_a, &map_b), map_b.clone()),
+        ];
+
+        for (input, expected) in inputs_and_expected {
+            let (strategy, existing, incoming) = input;
+
+            let mut produced = existing.clone();
+            strategy.merge_values(&mut produced, incoming.clone());
+            assert_eq!(expected, produced);
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let merger = Merger {
+            strategy: MergeStrategy::Replace,
+            keys: hashmap![
+                str!("credits") => MergeStrategy::Deep,
+                str!("title") => MergeStrategy::KeepFirst,
+            ],
+            ..Default::default()
+        };
+
+        let mut existing = Block(btreemap![
+            str!("title") => TU::s("title_a"),
+            str!("artist") => TU::s("artist_a"),
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("mixing") => TU::s("person_a"),
+            ])),
+        ]);
+        let incoming = Block(btreemap![
+            str!("title") => TU::s("title_b"),
+            str!("artist") => TU::s("artist_b"),
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("mastering") => TU::s("person_b"),
+            ])),
+            str!("year") => TU::i(1999),
+        ]);
+
+        merger.merge(&mut existing, incoming);
+
+        let expected = Block(btreemap![
+            str!("title") => TU::s("title_a"),
+            str!("artist") => TU::s("artist_b"),
+            str!("credits") => Value::Mapping(Block(btreemap![
+                str!("mixing") => TU::s("person_a"),
+                str!("mastering") => TU::s("person_b"),
+            ])),
+            str!("year") => TU::i(1999),
+        ]);
+        assert_eq!(expected, existing);
+    }
+
+    #[test]
+    fn merge_directed() {
+        let merger = Merger {
+            tombstone: str!("!delete"),
+            final_suffix: str!("!"),
+            ..Default::default()
+        };
+        let mut directives = Directives::default();
+        let origin_a = Path::new("a.json");
+        let origin_b = Path::new("b.json");
+
+        let mut existing = Block::new();
+        let incoming = Block(btreemap![
+            str!("title!") => TU::s("title_a"),
+            str!("artist") => TU::s("artist_a"),
+            str!("genre") => TU::s("genre_a"),
+        ]);
+        merger.merge_directed(&mut existing, incoming, origin_a, &mut directives).unwrap();
+
+        assert_eq!(
+            existing,
+            Block(btreemap![
+                str!("title") => TU::s("title_a"),
+                str!("artist") => TU::s("artist_a"),
+                str!("genre") => TU::s("genre_a"),
+            ]),
+        );
+        assert_eq!(directives.final_keys, hashmap![str!("title") => origin_a.to_path_buf()]);
+
+        // Tombstones delete keys, and final keys can be set to the same value.
+        let incoming = Block(btreemap![
+            str!("title") => TU::s("title_a"),
+            str!("artist") => TU::s("!delete"),
+            str!("genre!") => TU::s("!delete"),
+        ]);
+        merger.merge_directed(&mut existing, incoming, origin_b, &mut directives).unwrap();
+
+        assert_eq!(existing, Block(btreemap![str!("title") => TU::s("title_a")]));
+        assert_eq!(
+            directives.deleted_keys,
+            hashmap![
+                str!("artist") => origin_b.to_path_buf(),
+                str!("genre") => origin_b.to_path_buf(),
+            ],
+        );
+
+        // Final keys cannot be changed, even by being deleted or re-added.
+        for (key, val) in [
+            ("title", TU::s("title_b")),
+            ("title", TU::s("!delete")),
+            ("genre", TU::s("genre_b")),
+        ] {
+            let incoming = Block(btreemap![str!(key) => val]);
+            match merger.merge_directed(&mut existing.clone(), incoming, origin_a, &mut directives) {
+                Err(Error::FinalKey { key: k, origin, final_origin }) => {
+                    assert_eq!(k, key);
+                    assert_eq!(origin, origin_a);
+                    assert_eq!(final_origin, directives.final_keys[key]);
+                },
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+
+        // Setting a deleted key again clears its deletion.
+        let incoming = Block(btreemap![str!("artist") => TU::s("artist_b")]);
+        merger.merge_directed(&mut existing, incoming, origin_a, &mut directives).unwrap();
+        assert_eq!(directives.deleted_keys, hashmap![str!("genre") => origin_b.to_path_buf()]);
+
+        // Directives are only interpreted for top-level keys.
+        let merger = Merger { strategy: MergeStrategy::Deep, ..merger };
+        let mut existing = Block(btreemap![
+            str!("credits") => Value::Mapping(Block(btreemap![str!("mixing") => TU::s("person_a")])),
+        ]);
+        let incoming = Block(btreemap![
+            str!("credits") => Value::Mapping(Block(btreemap![str!("mixing!") => TU::s("!delete")])),
+        ]);
+        merger.merge_directed(&mut existing, incoming, origin_a, &mut Directives::default()).unwrap();
+
+        assert_eq!(
+            existing,
+            Block(btreemap![
+                str!("credits") => Value::Mapping(Block(btreemap![
+                    str!("mixing") => TU::s("person_a"),
+                    str!("mixing!") => TU::s("!delete"),
+                ])),
+            ]),
+        );
+
+        // Directives are disabled by default.
+        let merger = Merger::default();
+        let mut existing = Block::new();
+        let incoming = Block(btreemap![str!("title!") => TU::s("!delete")]);
+        merger.merge_directed(&mut existing, incoming, origin_a, &mut Directives::default()).unwrap();
+
+        assert_eq!(existing, Block(btreemap![str!("title!") => TU::s("!delete")]));
+    }
+}
@@ -1,22 +1,27 @@
 //! Provides configuration options for a library, both programmatically and via config files.
 
 pub mod format;
+pub mod merger;
 pub mod selection;
 pub mod sorter;
+pub mod virtual_fields;
 
 pub use self::format::{Format, Error as FormatError};
+pub use self::merger::Merger;
 pub use self::selection::Selection;
 pub use self::sorter::Sorter;
+pub use self::virtual_fields::VirtualFields;
 
+use std::borrow::Cow;
 use std::convert::{TryFrom, TryInto};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 use thiserror::Error;
 
 use self::selection::{SelectionRepr, MatcherError};
 
-use crate::sources::{Anchor, Source, Sourcer, CreateError as SourceCreateError};
+use crate::sources::{Anchor, Normalization, Overlay, SidecarMode, Source, Sourcer, CreateError as SourceCreateError};
 
 const DEFAULT_INTERNAL_STUB: &str = "album";
 const DEFAULT_EXTERNAL_STUB: &str = "track";
@@ -27,6 +32,8 @@ pub enum Error {
     Matcher(#[from] MatcherError),
     #[error("error deserializing source: {0}")]
     Source(#[from] SourceCreateError),
+    #[error("filtering rules cannot be combined with include or exclude patterns")]
+    RulesWithPatterns,
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,6 +43,15 @@ pub struct SourcesRepr {
     external: Vec<String>,
     #[serde(rename = "album")]
     internal: Vec<String>,
+    sidecar: Vec<String>,
+    sidecar_stem: Vec<String>,
+    root: Vec<String>,
+    meta_dir: Option<String>,
+    /// How item and meta file paths are normalized. Defaults to `none`, which
+    /// uses paths exactly as given; set this to `lexical` or `canonical` for
+    /// relative, dotted, or symlinked item paths to find their metadata.
+    normalization: Normalization,
+    overlay: Vec<OverlayRepr>,
 }
 
 impl Default for SourcesRepr {
@@ -46,10 +62,51 @@ impl Default for SourcesRepr {
         let external = vec![format!("{}.{}", DEFAULT_EXTERNAL_STUB, default_ext)];
         let internal = vec![format!("{}.{}", DEFAULT_INTERNAL_STUB, default_ext)];
 
-        Self { external, internal, }
+        Self {
+            external,
+            internal,
+            sidecar: Vec::new(),
+            sidecar_stem: Vec::new(),
+            root: Vec::new(),
+            meta_dir: None,
+            normalization: Normalization::default(),
+            overlay: Vec::new(),
+        }
     }
 }
 
+/// Determines whether the sources of an overlay take precedence over the
+/// sources inside of the library tree when merging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayPriority {
+    /// Overlay sources are merged before the in-tree sources.
+    Low,
+
+    /// Overlay sources are merged after the in-tree sources.
+    #[default]
+    High,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayRepr {
+    tree: PathBuf,
+    path: PathBuf,
+    #[serde(default)]
+    priority: OverlayPriority,
+    #[serde(default, rename = "track")]
+    external: Vec<String>,
+    #[serde(default, rename = "album")]
+    internal: Vec<String>,
+    #[serde(default)]
+    sidecar: Vec<String>,
+    #[serde(default)]
+    sidecar_stem: Vec<String>,
+    #[serde(default)]
+    root: Vec<String>,
+}
+
 #[derive(Deserialize, Default)]
 #[serde(default, deny_unknown_fields)]
 pub(crate) struct ConfigRepr {
@@ -59,55 +116,155 @@ pub(crate) struct ConfigRepr {
     pub sorter_repr: Sorter,
     #[serde(rename = "sourcing")]
     pub sources_repr: SourcesRepr,
+    #[serde(rename = "merging")]
+    pub merger_repr: Merger,
+    #[serde(rename = "virtual_fields")]
+    pub virtual_fields_repr: VirtualFields,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 #[serde(try_from = "ConfigRepr")]
 pub struct Config {
     pub selection: Selection,
     pub sorter: Sorter,
     pub sourcer: Sourcer,
+    pub merger: Merger,
+    pub virtual_fields: VirtualFields,
 }
 
 impl TryFrom<ConfigRepr> for Config {
     type Error = Error;
 
     fn try_from(value: ConfigRepr) -> Result<Self, Self::Error> {
-        let mut sources = Vec::new();
+        let sources_repr = value.sources_repr;
+        let normalization = sources_repr.normalization;
 
         let mut selection_repr = value.selection_repr;
 
-        for name in value.sources_repr.external {
-            let src = Source::from_name(name, Anchor::External)?;
-            sources.push(src);
+        // Rules replace the include and exclude patterns, so only one can be used.
+        if !selection_repr.rules.is_empty() && !selection_repr.has_default_patterns() {
+            return Err(Error::RulesWithPatterns);
         }
 
-        for name in value.sources_repr.internal {
-            let src = Source::from_name(name, Anchor::Internal)?;
-            sources.push(src);
+        let mut sources = create_sources(
+            sources_repr.root,
+            sources_repr.external,
+            sources_repr.internal,
+            sources_repr.sidecar,
+            sources_repr.sidecar_stem,
+        )?;
+
+        // All in-tree sources keep their meta files in the meta directory, if given.
+        if let Some(meta_dir) = sources_repr.meta_dir {
+            sources = sources
+                .into_iter()
+                .map(|src| src.with_meta_dir(meta_dir.clone()))
+                .collect::<Result<_, _>>()?;
+
+            // The meta directory never contains item files.
+            let pattern = format!("**/{}", meta_dir);
+            selection_repr.auto_exclude_dirs.add_pattern(&pattern).map_err(Into::<MatcherError>::into)?;
         }
 
         if selection_repr.exclude_sources {
             // Add sources to the list of excluded files.
             for source in sources.iter() {
-                let pattern = &source.name;
-                selection_repr.exclude_files.add_pattern(pattern).map_err(Into::<MatcherError>::into)?;
+                match source.anchor {
+                    // Sidecar meta files are named after their item files, so
+                    // whether a file is one depends on its siblings.
+                    Anchor::Sidecar(mode) => {
+                        selection_repr.auto_exclude_sidecars.push((source.name.clone(), mode));
+                    },
+                    Anchor::External | Anchor::Internal | Anchor::Root => {
+                        // Meta files can be nested when matching relative paths.
+                        let pattern = format!("**/{}", source.name);
+                        selection_repr.auto_exclude_files.add_pattern(&pattern).map_err(Into::<MatcherError>::into)?;
+                    },
+                }
+            }
+        }
+
+        // Overlay meta files live outside of the library tree, so they do not
+        // need to be excluded.
+        let mut low_sources = Vec::new();
+        let mut high_sources = Vec::new();
+
+        // Overlay roots need to line up with normalized item paths.
+        let normalize = |path: PathBuf| normalization.normalize(&path).map(Cow::into_owned).unwrap_or(path);
+
+        for overlay_repr in sources_repr.overlay {
+            let overlay = Overlay::new(normalize(overlay_repr.tree), normalize(overlay_repr.path));
+
+            let overlay_sources = create_sources(
+                overlay_repr.root,
+                overlay_repr.external,
+                overlay_repr.internal,
+                overlay_repr.sidecar,
+                overlay_repr.sidecar_stem,
+            )?
+            .into_iter()
+            .map(|src| src.with_overlay(overlay.clone()));
+
+            match overlay_repr.priority {
+                OverlayPriority::Low => low_sources.extend(overlay_sources),
+                OverlayPriority::High => high_sources.extend(overlay_sources),
             }
         }
 
+        low_sources.append(&mut sources);
+        low_sources.append(&mut high_sources);
+        let sources = low_sources;
+
         // Manually convert `SelectionRepr` into `Selection`.
         let selection = selection_repr.try_into()?;
 
-        let sourcer = sources.into();
+        let mut sourcer = Sourcer::from(sources);
+        sourcer.normalize_with(normalization);
 
         Ok(Self {
             selection,
             sorter: value.sorter_repr,
             sourcer,
+            merger: value.merger_repr,
+            virtual_fields: value.virtual_fields_repr,
         })
     }
 }
 
+/// Creates sources for each of the given meta file names, in merge order.
+fn create_sources(
+    root: Vec<String>,
+    external: Vec<String>,
+    internal: Vec<String>,
+    sidecar: Vec<String>,
+    sidecar_stem: Vec<String>,
+) -> Result<Vec<Source>, SourceCreateError> {
+    let mut sources = Vec::new();
+
+    // Root sources are the least specific, so they are merged first.
+    for name in root {
+        sources.push(Source::from_name(name, Anchor::Root)?);
+    }
+
+    for name in external {
+        sources.push(Source::from_name(name, Anchor::External)?);
+    }
+
+    for name in internal {
+        sources.push(Source::from_name(name, Anchor::Internal)?);
+    }
+
+    for name in sidecar {
+        sources.push(Source::from_name(name, Anchor::Sidecar(SidecarMode::FullName))?);
+    }
+
+    for name in sidecar_stem {
+        sources.push(Source::from_name(name, Anchor::Sidecar(SidecarMode::Stem))?);
+    }
+
+    Ok(sources)
+}
+
 impl Default for Config {
     fn default() -> Self {
         // NOTE: This is expected to never fail.
@@ -131,7 +288,9 @@ impl Config {
 mod tests {
     use super::*;
 
+    use crate::config::merger::MergeStrategy;
     use crate::config::sorter::sort_by::SortBy;
+    use crate::fs::MemoryFs;
 
     use str_macro::str;
 
@@ -226,4 +385,208 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn merging() {
+        let config: Config = toml::from_str("").unwrap();
+
+        assert_eq!(config.merger, Merger::default());
+
+        let text_config = r#"
+            [merging]
+            strategy = "keep_first"
+            [merging.keys]
+            credits = "deep"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(config.merger.strategy_for("title"), MergeStrategy::KeepFirst);
+        assert_eq!(config.merger.strategy_for("credits"), MergeStrategy::Deep);
+        assert!(!config.virtual_fields.enabled);
+    }
+
+    #[test]
+    fn virtual_fields() {
+        let text_config = r#"
+            [virtual_fields]
+            enabled = true
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert!(config.virtual_fields.enabled);
+        assert_eq!(config.virtual_fields.namespace, "_file");
+    }
+
+    #[test]
+    fn sidecar_and_root_sources() {
+        let text_config = r#"
+            [sourcing]
+            sidecar = ["yml"]
+            sidecar_stem = ["meta.json"]
+            root = ["library.yml"]
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("library.yml"), Anchor::Root).unwrap(),
+                Source::from_name(str!("track.json"), Anchor::External).unwrap(),
+                Source::from_name(str!("album.json"), Anchor::Internal).unwrap(),
+                Source::from_name(str!("yml"), Anchor::Sidecar(SidecarMode::FullName)).unwrap(),
+                Source::from_name(str!("meta.json"), Anchor::Sidecar(SidecarMode::Stem)).unwrap(),
+            ]
+        );
+        assert!(config.selection.is_file_pattern_match(&"music.flac"));
+        assert!(!config.selection.is_file_pattern_match(&"library.yml"));
+    }
+
+    #[test]
+    fn sidecar_exclusion() {
+        let text_config = r#"
+            [sourcing]
+            sidecar = ["json"]
+            sidecar_stem = ["meta.yml"]
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        // Item files can have the same extension as sidecar meta files.
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/lib/data.json", "{}").unwrap()
+            .add_file("/lib/data.json.json", "{}").unwrap()
+            .add_file("/lib/notes.json", "{}").unwrap()
+            .add_file("/lib/music.flac", "0").unwrap()
+            .add_file("/lib/music.meta.yml", "{}").unwrap()
+            .add_file("/lib/setlist.meta.yml", "0").unwrap();
+
+        let is_selected = |path: &str| config.selection.is_selected_in(&fs, &path).unwrap();
+
+        assert!(is_selected("/lib/data.json"));
+        assert!(!is_selected("/lib/data.json.json"));
+        assert!(is_selected("/lib/notes.json"));
+        assert!(is_selected("/lib/music.flac"));
+        assert!(!is_selected("/lib/music.meta.yml"));
+        assert!(is_selected("/lib/setlist.meta.yml"));
+    }
+
+    #[test]
+    fn normalization() {
+        let config: Config = toml::from_str("").unwrap();
+
+        assert_eq!(config.sourcer.normalization(), Normalization::None);
+
+        let text_config = r#"
+            [sourcing]
+            normalization = "canonical"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(config.sourcer.normalization(), Normalization::Canonical);
+    }
+
+    #[test]
+    fn meta_dir() {
+        let text_config = r#"
+            [sourcing]
+            album = ["album.yml"]
+            track = ["track.yml"]
+            meta_dir = ".anagma"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("track.yml"), Anchor::External).unwrap()
+                    .with_meta_dir(str!(".anagma")).unwrap(),
+                Source::from_name(str!("album.yml"), Anchor::Internal).unwrap()
+                    .with_meta_dir(str!(".anagma")).unwrap(),
+            ]
+        );
+        assert!(!config.selection.is_dir_pattern_match(&".anagma"));
+        assert!(config.selection.is_dir_pattern_match(&"DISC_01"));
+
+        let text_config = r#"
+            [sourcing]
+            meta_dir = ".."
+        "#;
+
+        assert!(toml::from_str::<Config>(text_config).is_err());
+    }
+
+    #[test]
+    fn overlay() {
+        let text_config = r#"
+            [sourcing]
+            album = ["album.json"]
+            track = []
+
+            [[sourcing.overlay]]
+            tree = "/media"
+            path = "/overlay"
+            track = ["track.yml"]
+
+            [[sourcing.overlay]]
+            tree = "/media"
+            path = "/defaults"
+            priority = "low"
+            album = ["album.yml"]
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(
+            config.sourcer.as_sources(),
+            vec![
+                Source::from_name(str!("album.yml"), Anchor::Internal).unwrap()
+                    .with_overlay(Overlay::new("/media", "/defaults")),
+                Source::from_name(str!("album.json"), Anchor::Internal).unwrap(),
+                Source::from_name(str!("track.yml"), Anchor::External).unwrap()
+                    .with_overlay(Overlay::new("/media", "/overlay")),
+            ]
+        );
+    }
+
+    #[test]
+    fn rules() {
+        let text_config = r#"
+            [filtering]
+            rule_order = "first_match"
+
+            [[filtering.rules]]
+            include = "keep.log"
+
+            [[filtering.rules]]
+            exclude = ["*.log", "*.json"]
+            kind = "file"
+        "#;
+
+        let config: Config = toml::from_str(text_config).unwrap();
+
+        assert_eq!(config.selection.rules().map(|r| r.as_rules().len()), Some(2));
+        assert!(config.selection.is_file_pattern_match(&"keep.log"));
+        assert!(!config.selection.is_file_pattern_match(&"rip.log"));
+        assert!(config.selection.is_file_pattern_match(&"music.flac"));
+        assert!(config.selection.is_dir_pattern_match(&"rip.log"));
+
+        // Meta files are still excluded.
+        assert!(!config.selection.is_file_pattern_match(&"item.json"));
+
+        let text_config = r#"
+            [filtering]
+            exclude_files = "*.log"
+
+            [[filtering.rules]]
+            include = "keep.log"
+        "#;
+
+        assert!(toml::from_str::<Config>(text_config).is_err());
+    }
 }
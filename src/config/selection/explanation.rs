@@ -0,0 +1,250 @@
+//! Explanations of why item paths are or are not selected.
+
+use std::io::Result as IoResult;
+use std::path::Path;
+
+use crate::fs::{DiskFs, Fs};
+
+use super::{FileOrDir, Matcher, Pattern, RuleAction, Selection};
+
+/// The outcome of selecting a path, along with the first reason that it was
+/// not selected, if any. Reasons are checked in the order listed here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Decision {
+    /// The path is selected.
+    Selected,
+
+    /// The path is neither a file nor a directory.
+    NotFileOrDir,
+
+    /// The path is excluded by an ignore file.
+    Ignored,
+
+    /// The path does not pass the filesystem filters.
+    Filtered,
+
+    /// The path is excluded by patterns that `Config` adds automatically, such
+    /// as for meta files when `exclude_sources` is enabled.
+    AutoExcluded,
+
+    /// The path matches an exclude pattern, or an exclude rule decides it.
+    Excluded,
+
+    /// The path does not match any include pattern.
+    NotIncluded,
+}
+
+/// Describes how a `Selection` decides whether a path is selected, for
+/// debugging missing or unexpected item files. Matching patterns are listed
+/// even if they do not end up deciding the outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub decision: Decision,
+
+    /// Include patterns that match the path. If rules are used, these are the
+    /// patterns of the deciding rule, if it is an include rule.
+    pub included_by: Vec<Pattern>,
+
+    /// Exclude patterns that match the path. If rules are used, this also
+    /// contains the patterns of the deciding rule, if it is an exclude rule.
+    pub excluded_by: Vec<Pattern>,
+
+    /// Automatically added exclude patterns that match the path.
+    pub auto_excluded_by: Vec<Pattern>,
+
+    /// The index of the rule that decides the outcome, if rules are used and
+    /// any of them match.
+    pub rule: Option<usize>,
+}
+
+impl Explanation {
+    pub fn is_selected(&self) -> bool {
+        self.decision == Decision::Selected
+    }
+}
+
+impl Selection {
+    /// Explains whether a path is selected, and which patterns match it.
+    /// This accesses the filesystem in the same way as `is_selected`.
+    pub fn explain<P: AsRef<Path>>(&self, path: &P) -> IoResult<Explanation> {
+        self.explain_in(&DiskFs, path)
+    }
+
+    /// Similar to `explain`, but accesses a given filesystem.
+    pub fn explain_in<P: AsRef<Path>>(&self, fs: &dyn Fs, path: &P) -> IoResult<Explanation> {
+        self.explain_opt_from_in(fs, None, path.as_ref())
+    }
+
+    /// Similar to `explain`, but in the same way as `is_selected_from`.
+    pub fn explain_from<P: AsRef<Path>>(&self, base_dir_path: &Path, path: &P) -> IoResult<Explanation> {
+        self.explain_from_in(&DiskFs, base_dir_path, path)
+    }
+
+    /// Similar to `explain_from`, but accesses a given filesystem.
+    pub fn explain_from_in<P: AsRef<Path>>(&self, fs: &dyn Fs, base_dir_path: &Path, path: &P) -> IoResult<Explanation> {
+        self.explain_opt_from_in(fs, Some(base_dir_path), path.as_ref())
+    }
+
+    fn explain_opt_from_in(&self, fs: &dyn Fs, base_dir_path: Option<&Path>, path: &Path) -> IoResult<Explanation> {
+        let file_info = fs.metadata(path)?;
+
+        let fod = if file_info.is_dir() { FileOrDir::Dir } else { FileOrDir::File };
+        let mut explanation = self.explain_patterns(base_dir_path, path, fod);
+
+        // Mirror the order of checks in `is_selected`.
+        if !file_info.is_file() && !file_info.is_dir() {
+            explanation.decision = Decision::NotFileOrDir;
+        } else if self.is_ignored(fs, base_dir_path, path, file_info.is_dir())? {
+            explanation.decision = Decision::Ignored;
+        } else if !self.filters.is_match_in(fs, path, &file_info, self.root.as_deref().or(base_dir_path))? {
+            explanation.decision = Decision::Filtered;
+        } else if file_info.is_file() && self.is_auto_excluded_sidecar(fs, path)? {
+            explanation.decision = Decision::AutoExcluded;
+        }
+
+        Ok(explanation)
+    }
+
+    /// Explains the outcome of matching patterns against a path, without
+    /// accessing the filesystem.
+    fn explain_patterns(&self, base_dir_path: Option<&Path>, path: &Path, fod: FileOrDir) -> Explanation {
+        let (inc, exc, auto_exc) = self.matchers(&fod);
+        let rel_path = self.pattern_rel_path(base_dir_path, path);
+
+        let matching = |matcher: &Matcher| match rel_path {
+            Some(rel_path) => matcher.matching_path_patterns(&rel_path),
+            None => matcher.matching_patterns(&path),
+        };
+
+        let mut included_by = Vec::new();
+        let mut excluded_by = Vec::new();
+        let mut rule = None;
+
+        let is_included = match &self.rules {
+            Some(rules) => {
+                rule = rules.deciding_rule(&fod, |m| !matching(m).is_empty());
+
+                match rule.map(|i| &rules.as_rules()[i]) {
+                    Some(r) if r.action() == RuleAction::Exclude => {
+                        excluded_by = matching(r.matcher());
+                        false
+                    },
+                    Some(r) => {
+                        included_by = matching(r.matcher());
+                        true
+                    },
+                    None => true,
+                }
+            },
+            None => {
+                included_by = matching(inc);
+                !included_by.is_empty()
+            },
+        };
+
+        excluded_by.extend(matching(exc));
+        let auto_excluded_by = matching(auto_exc);
+
+        let decision = if !auto_excluded_by.is_empty() {
+            Decision::AutoExcluded
+        } else if !excluded_by.is_empty() {
+            Decision::Excluded
+        } else if !is_included {
+            Decision::NotIncluded
+        } else {
+            Decision::Selected
+        };
+
+        Explanation { decision, included_by, excluded_by, auto_excluded_by, rule, }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    use crate::config::Config;
+    use crate::config::selection::{Rule, RuleOrder, Rules, SelectionRepr};
+    use crate::fs::MemoryFs;
+
+    fn glob(s: &str) -> Pattern {
+        Pattern::Glob(String::from(s))
+    }
+
+    #[test]
+    fn explain_in() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/lib/ALBUM/track.json", "[]").unwrap()
+            .add_file("/lib/ALBUM/TRACK_01.flac", "0").unwrap()
+            .add_file("/lib/ALBUM/TRACK_02.flac", "").unwrap()
+            .add_file("/lib/ALBUM/cover.png", "0").unwrap()
+            .add_file("/lib/ALBUM/rip.log", "0").unwrap()
+            .add_file("/lib/ALBUM/keep.log", "0").unwrap();
+
+        let config: Config = toml::from_str(r#"
+            [filtering]
+            include_files = ["*.flac", "*.json", "*.log", "TRACK_*"]
+            exclude_files = "*.log"
+            min_size = 1
+        "#).unwrap();
+        let selection = config.selection;
+
+        let explain = |path: &str| selection.explain_in(&fs, &path).unwrap();
+
+        let explanation = explain("/lib/ALBUM/TRACK_01.flac");
+        assert!(explanation.is_selected());
+        assert_eq!(explanation.included_by, vec![glob("*.flac"), glob("TRACK_*")]);
+        assert_eq!(explanation.excluded_by, vec![]);
+
+        let explanation = explain("/lib/ALBUM/track.json");
+        assert_eq!(explanation.decision, Decision::AutoExcluded);
+        assert_eq!(explanation.included_by, vec![glob("*.json")]);
+        assert_eq!(explanation.auto_excluded_by, vec![glob("**/track.json")]);
+
+        let explanation = explain("/lib/ALBUM/rip.log");
+        assert_eq!(explanation.decision, Decision::Excluded);
+        assert_eq!(explanation.excluded_by, vec![glob("*.log")]);
+
+        assert_eq!(explain("/lib/ALBUM/cover.png").decision, Decision::NotIncluded);
+        assert_eq!(explain("/lib/ALBUM/TRACK_02.flac").decision, Decision::Filtered);
+        assert!(!selection.is_selected_in(&fs, &"/lib/ALBUM/TRACK_02.flac").unwrap());
+
+        // Rules report the deciding rule.
+        let selection = Selection::default()
+            .with_rules(Rules::new(
+                vec![
+                    Rule::include(Matcher::build(&["keep.log"]).unwrap()),
+                    Rule::exclude(Matcher::build(&["*.log", "rip.*"]).unwrap()),
+                ],
+                RuleOrder::FirstMatch,
+            ));
+        let explain = |path: &str| selection.explain_in(&fs, &path).unwrap();
+
+        let explanation = explain("/lib/ALBUM/keep.log");
+        assert!(explanation.is_selected());
+        assert_eq!((explanation.rule, explanation.included_by), (Some(0), vec![glob("keep.log")]));
+
+        let explanation = explain("/lib/ALBUM/rip.log");
+        assert_eq!(explanation.decision, Decision::Excluded);
+        assert_eq!((explanation.rule, explanation.excluded_by), (Some(1), vec![glob("*.log"), glob("rip.*")]));
+
+        let explanation = explain("/lib/ALBUM/cover.png");
+        assert!(explanation.is_selected());
+        assert_eq!((explanation.rule, explanation.included_by), (None, vec![]));
+
+        // Explanations agree with `is_selected`.
+        let selection: Selection = toml::from_str::<SelectionRepr>("include_files = '*.flac'")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        for path in ["/lib/ALBUM", "/lib/ALBUM/TRACK_01.flac", "/lib/ALBUM/cover.png"] {
+            assert_eq!(
+                selection.explain_in(&fs, &path).unwrap().is_selected(),
+                selection.is_selected_in(&fs, &path).unwrap(),
+            );
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Filters on the filesystem properties of item paths, as opposed to their
+//! names.
+
+use std::convert::TryFrom;
+use std::io::Result as IoResult;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::fs::{Fs, FsMetadata};
+
+/// Filters that item paths need to pass in order to be selected, in addition
+/// to matching patterns. Unset filters always pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsFilters {
+    /// Files smaller than this many bytes are not selected.
+    pub min_size: Option<u64>,
+
+    /// Files larger than this many bytes are not selected.
+    pub max_size: Option<u64>,
+
+    /// Files last modified before this time are not selected.
+    pub modified_after: Option<SystemTime>,
+
+    /// Files last modified after this time are not selected.
+    pub modified_before: Option<SystemTime>,
+
+    /// Directories without any entries are not selected.
+    pub exclude_empty_dirs: bool,
+
+    /// Symlinks are not selected, regardless of what they point to.
+    pub exclude_symlinks: bool,
+
+    /// Paths nested more than this many levels below the library root are
+    /// not selected, where children of the root are at depth 1. Without a
+    /// library root, depth is measured from the origin of the tree walk, or
+    /// from the directory of the meta file being plexed. Paths selected
+    /// without either, or outside of them, are not filtered by depth.
+    pub max_depth: Option<usize>,
+}
+
+impl FsFilters {
+    /// Returns true if a path passes all of the filters. File info for the
+    /// path is expected to have already been fetched. Depth is measured from
+    /// the given root directory, if any.
+    pub(crate) fn is_match_in(
+        &self,
+        fs: &dyn Fs,
+        path: &Path,
+        file_info: &FsMetadata,
+        root: Option<&Path>,
+    ) -> IoResult<bool> {
+        if !self.is_info_match(path, file_info, root) {
+            return Ok(false);
+        }
+
+        if self.exclude_empty_dirs && file_info.is_dir() && fs.read_dir(path)?.next().is_none() {
+            return Ok(false);
+        }
+
+        if self.exclude_symlinks && fs.is_symlink(path)? {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Async version of `is_match_in`, which only accesses the local disk.
+    #[cfg(feature = "async")]
+    pub(crate) async fn is_match_async(
+        &self,
+        path: &Path,
+        file_info: &FsMetadata,
+        root: Option<&Path>,
+    ) -> IoResult<bool> {
+        if !self.is_info_match(path, file_info, root) {
+            return Ok(false);
+        }
+
+        if self.exclude_empty_dirs && file_info.is_dir() && tokio::fs::read_dir(path).await?.next_entry().await?.is_none() {
+            return Ok(false);
+        }
+
+        if self.exclude_symlinks && tokio::fs::symlink_metadata(path).await?.file_type().is_symlink() {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Checks the filters that only need the path and its file info.
+    fn is_info_match(&self, path: &Path, file_info: &FsMetadata, root: Option<&Path>) -> bool {
+        if let (Some(max_depth), Some(root)) = (self.max_depth, root) {
+            if let Ok(rel_path) = path.strip_prefix(root) {
+                if rel_path.components().count() > max_depth {
+                    return false;
+                }
+            }
+        }
+
+        if file_info.is_file() {
+            if self.min_size.is_some_and(|min| file_info.len < min)
+                || self.max_size.is_some_and(|max| file_info.len > max)
+            {
+                return false;
+            }
+
+            // Files without a known mod time are not filtered out.
+            if let Some(modified) = file_info.modified {
+                if self.modified_after.is_some_and(|after| modified < after)
+                    || self.modified_before.is_some_and(|before| modified > before)
+                {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// A point in time as written in a config file, either as a number of seconds
+/// since the Unix epoch, or as an RFC 3339 timestamp or date. Timestamps are
+/// always in UTC, and may not have any other offset.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "TimestampRepr")]
+pub(crate) struct Timestamp(pub SystemTime);
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum TimestampRepr {
+    Secs(u64),
+    Datetime(toml::value::Datetime),
+    Text(String),
+}
+
+impl TryFrom<TimestampRepr> for Timestamp {
+    type Error = String;
+
+    fn try_from(value: TimestampRepr) -> Result<Self, Self::Error> {
+        let text = match value {
+            TimestampRepr::Secs(secs) => return Ok(Self(UNIX_EPOCH + Duration::from_secs(secs))),
+            TimestampRepr::Datetime(dt) => dt.to_string(),
+            TimestampRepr::Text(text) => text,
+        };
+
+        // Plain dates are taken to be at midnight.
+        let parsed = if text.contains(['T', 't', ' ']) {
+            humantime::parse_rfc3339_weak(&text)
+        } else {
+            humantime::parse_rfc3339_weak(&format!("{} 00:00:00", text))
+        };
+
+        parsed
+            .map(Self)
+            .map_err(|err| format!("invalid timestamp \"{}\": {}", text, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fs::MemoryFs;
+
+    #[test]
+    fn is_match_in() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/lib/.DS_Store", "").unwrap()
+            .add_file("/lib/ALBUM/TRACK_01.flac", "0123456789").unwrap()
+            .add_file("/lib/ALBUM/DISC_01/TRACK_01.flac", "0123456789").unwrap()
+            .add_dir("/lib/EMPTY").unwrap();
+        fs.set_modified("/lib/.DS_Store", UNIX_EPOCH + Duration::from_secs(500)).unwrap();
+        fs.set_modified("/lib/ALBUM/TRACK_01.flac", UNIX_EPOCH + Duration::from_secs(1000)).unwrap();
+        fs.set_modified("/lib/ALBUM/DISC_01/TRACK_01.flac", UNIX_EPOCH + Duration::from_secs(2000)).unwrap();
+
+        let is_match = |filters: &FsFilters, path: &str, root: Option<&str>| {
+            let path = Path::new(path);
+            let file_info = fs.metadata(path).unwrap();
+            filters.is_match_in(&fs, path, &file_info, root.map(Path::new)).unwrap()
+        };
+
+        let filters = FsFilters::default();
+        assert!(is_match(&filters, "/lib/.DS_Store", None));
+        assert!(is_match(&filters, "/lib/EMPTY", None));
+        assert!(is_match(&filters, "/lib/ALBUM/DISC_01/TRACK_01.flac", Some("/lib")));
+
+        let filters = FsFilters { min_size: Some(1), max_size: Some(10), ..Default::default() };
+        assert!(!is_match(&filters, "/lib/.DS_Store", None));
+        assert!(is_match(&filters, "/lib/ALBUM/TRACK_01.flac", None));
+        assert!(is_match(&filters, "/lib/EMPTY", None));
+
+        let filters = FsFilters { max_size: Some(9), ..Default::default() };
+        assert!(!is_match(&filters, "/lib/ALBUM/TRACK_01.flac", None));
+
+        let after = UNIX_EPOCH + Duration::from_secs(1000);
+        let filters = FsFilters { modified_after: Some(after), ..Default::default() };
+        assert!(is_match(&filters, "/lib/ALBUM/TRACK_01.flac", None));
+        assert!(!is_match(&filters, "/lib/.DS_Store", None));
+
+        let filters = FsFilters { modified_before: Some(after), ..Default::default() };
+        assert!(is_match(&filters, "/lib/ALBUM/TRACK_01.flac", None));
+        assert!(!is_match(&filters, "/lib/ALBUM/DISC_01/TRACK_01.flac", None));
+
+        let filters = FsFilters { exclude_empty_dirs: true, ..Default::default() };
+        assert!(!is_match(&filters, "/lib/EMPTY", None));
+        assert!(is_match(&filters, "/lib/ALBUM", None));
+
+        let filters = FsFilters { max_depth: Some(2), ..Default::default() };
+        assert!(is_match(&filters, "/lib/ALBUM/TRACK_01.flac", Some("/lib")));
+        assert!(!is_match(&filters, "/lib/ALBUM/DISC_01/TRACK_01.flac", Some("/lib")));
+        assert!(is_match(&filters, "/lib/ALBUM/DISC_01/TRACK_01.flac", Some("/lib/ALBUM")));
+
+        // Without a root to measure from, depth is not filtered.
+        assert!(is_match(&filters, "/lib/ALBUM/DISC_01/TRACK_01.flac", None));
+    }
+
+    #[test]
+    fn timestamp() {
+        let parse = |text: &str| Timestamp::try_from(TimestampRepr::Text(text.to_string())).map(|t| t.0);
+        let t = |secs: u64| Ok(UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!(Timestamp::try_from(TimestampRepr::Secs(61)).map(|t| t.0), t(61));
+        assert_eq!(parse("1970-01-01"), t(0));
+        assert_eq!(parse("2000-03-01"), t(951_868_800));
+        assert_eq!(parse("2000-03-01T00:01:01Z"), t(951_868_861));
+        assert_eq!(parse("2000-03-01 00:01:01.250"), t(951_868_861).map(|t| t + Duration::from_millis(250)));
+
+        assert!(parse("").is_err());
+        assert!(parse("2000-13-01").is_err());
+        assert!(parse("2000-03-01T01:01:01+01:00").is_err());
+        assert!(parse("yesterday").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exclude_symlinks() {
+        use crate::fs::DiskFs;
+
+        let temp_dir = tempfile::Builder::new().suffix("exclude_symlinks").tempdir().unwrap();
+        let path = temp_dir.path();
+
+        std::fs::write(path.join("TRACK_01.flac"), "").unwrap();
+        std::os::unix::fs::symlink(path.join("TRACK_01.flac"), path.join("LINK.flac")).unwrap();
+
+        let filters = FsFilters { exclude_symlinks: true, ..Default::default() };
+        let is_match = |p: &Path| filters.is_match_in(&DiskFs, p, &DiskFs.metadata(p).unwrap(), None).unwrap();
+
+        assert!(is_match(&path.join("TRACK_01.flac")));
+        assert!(!is_match(&path.join("LINK.flac")));
+    }
+}
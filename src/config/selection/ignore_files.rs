@@ -0,0 +1,215 @@
+//! Support for gitignore-style ignore files, which exclude item paths from
+//! selection on a per-directory basis.
+
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::fs::Fs;
+
+/// The file name of ignore files.
+pub const IGNORE_FILE_NAME: &str = ".anagmaignore";
+
+// A parsed ignore file, along with the mod time it was read at.
+type CachedIgnore = (Option<SystemTime>, Arc<Gitignore>);
+
+/// Finds and caches the ignore files that apply to item paths. An ignore file
+/// applies to all paths inside of its directory, and ignore files in nested
+/// directories take precedence over ones in their ancestor directories.
+#[derive(Debug, Default)]
+pub(crate) struct IgnoreFiles {
+    // Parsed ignore files, keyed by path. Mod times are used to detect when
+    // they need to be read again.
+    cache: Mutex<HashMap<PathBuf, CachedIgnore>>,
+}
+
+impl IgnoreFiles {
+    /// Returns true if a path is ignored by an ignore file in any of its
+    /// ancestor directories, up to and including the stop directory. If the
+    /// stop directory is not an ancestor of the path, only the ignore file in
+    /// the parent directory of the path is used.
+    pub fn is_ignored(&self, fs: &dyn Fs, path: &Path, is_dir: bool, stop_dir_path: &Path) -> IoResult<bool> {
+        for dir_path in Self::dir_paths(path, stop_dir_path) {
+            if let Some(gitignore) = self.load(fs, dir_path)? {
+                match gitignore.matched_path_or_any_parents(path, is_dir) {
+                    Match::Ignore(..) => return Ok(true),
+                    Match::Whitelist(..) => return Ok(false),
+                    Match::None => {},
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Async version of `is_ignored`, which only accesses the local disk.
+    #[cfg(feature = "async")]
+    pub async fn is_ignored_async(&self, path: &Path, is_dir: bool, stop_dir_path: &Path) -> IoResult<bool> {
+        for dir_path in Self::dir_paths(path, stop_dir_path) {
+            if let Some(gitignore) = self.load_async(dir_path).await? {
+                match gitignore.matched_path_or_any_parents(path, is_dir) {
+                    Match::Ignore(..) => return Ok(true),
+                    Match::Whitelist(..) => return Ok(false),
+                    Match::None => {},
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the directories to look for ignore files in, from nearest to
+    /// farthest.
+    pub fn dir_paths<'p>(path: &'p Path, stop_dir_path: &'p Path) -> impl Iterator<Item = &'p Path> {
+        let stop_dir_path = Some(stop_dir_path).filter(|s| path.starts_with(s)).or_else(|| path.parent());
+        let mut reached_stop = false;
+
+        path.ancestors().skip(1).take_while(move |dir_path| {
+            let is_before_stop = !reached_stop;
+            reached_stop = reached_stop || Some(*dir_path) == stop_dir_path;
+            is_before_stop
+        })
+    }
+
+    /// Reads the ignore file in a directory, if there is one.
+    fn load(&self, fs: &dyn Fs, dir_path: &Path) -> IoResult<Option<Arc<Gitignore>>> {
+        let ignore_path = dir_path.join(IGNORE_FILE_NAME);
+
+        let fs_stat = match fs.metadata(&ignore_path) {
+            Ok(fs_stat) if fs_stat.is_file() => fs_stat,
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == IoErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        if let Some(gitignore) = self.cached(&ignore_path, fs_stat.modified) {
+            return Ok(Some(gitignore));
+        }
+
+        let mut contents = String::new();
+        fs.open(&ignore_path)?.read_to_string(&mut contents)?;
+
+        self.parse(dir_path, ignore_path, fs_stat.modified, &contents).map(Some)
+    }
+
+    /// Async version of `load`.
+    #[cfg(feature = "async")]
+    async fn load_async(&self, dir_path: &Path) -> IoResult<Option<Arc<Gitignore>>> {
+        let ignore_path = dir_path.join(IGNORE_FILE_NAME);
+
+        let fs_stat = match tokio::fs::metadata(&ignore_path).await {
+            Ok(fs_stat) if fs_stat.is_file() => fs_stat,
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == IoErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let modified = fs_stat.modified().ok();
+
+        if let Some(gitignore) = self.cached(&ignore_path, modified) {
+            return Ok(Some(gitignore));
+        }
+
+        let contents = tokio::fs::read_to_string(&ignore_path).await?;
+
+        self.parse(dir_path, ignore_path, modified, &contents).map(Some)
+    }
+
+    /// Returns a cached ignore file, if it has not changed since it was read.
+    fn cached(&self, ignore_path: &Path, modified: Option<SystemTime>) -> Option<Arc<Gitignore>> {
+        // NOTE: Not holding the lock while reading, multiple threads may end
+        //       up reading the same ignore file, which is harmless.
+        let cache = self.cache.lock().unwrap();
+        let (cached_modified, gitignore) = cache.get(ignore_path)?;
+
+        if *cached_modified == modified && modified.is_some() {
+            Some(Arc::clone(gitignore))
+        } else {
+            None
+        }
+    }
+
+    /// Parses the contents of an ignore file, and caches the result.
+    fn parse(
+        &self,
+        dir_path: &Path,
+        ignore_path: PathBuf,
+        modified: Option<SystemTime>,
+        contents: &str,
+    ) -> IoResult<Arc<Gitignore>> {
+        let mut builder = GitignoreBuilder::new(dir_path);
+        for line in contents.lines() {
+            builder
+                .add_line(Some(ignore_path.clone()), line)
+                .map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+        }
+
+        let gitignore = Arc::new(
+            builder.build().map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?
+        );
+
+        self.cache.lock().unwrap().insert(ignore_path, (modified, Arc::clone(&gitignore)));
+
+        Ok(gitignore)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fs::MemoryFs;
+
+    #[test]
+    fn is_ignored() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/lib/.anagmaignore", "*.log\n# Comment\n/junk/\nkeep*\n").unwrap()
+            .add_file("/lib/ALBUM/.anagmaignore", "!keep.flac\n*.tmp\n").unwrap()
+            .add_file("/lib/ALBUM/keep.flac", "").unwrap()
+            .add_file("/lib/ALBUM/keep.wav", "").unwrap()
+            .add_file("/lib/ALBUM/rip.log", "").unwrap()
+            .add_file("/lib/ALBUM/track.tmp", "").unwrap()
+            .add_file("/lib/ALBUM/track.flac", "").unwrap()
+            .add_file("/lib/junk/track.flac", "").unwrap()
+            .add_file("/lib/ALBUM/junk/track.flac", "").unwrap()
+            .add_file("/lib/track.tmp", "").unwrap();
+
+        let ignore_files = IgnoreFiles::default();
+        let is_ignored = |fs: &MemoryFs, path: &str, is_dir: bool| {
+            ignore_files.is_ignored(fs, Path::new(path), is_dir, Path::new("/")).unwrap()
+        };
+
+        assert!(!is_ignored(&fs, "/lib/ALBUM", true));
+        assert!(!is_ignored(&fs, "/lib/ALBUM/track.flac", false));
+        assert!(!is_ignored(&fs, "/lib/track.tmp", false));
+        assert!(!is_ignored(&fs, "/lib/ALBUM/junk", true));
+        assert!(!is_ignored(&fs, "/lib/ALBUM/junk/track.flac", false));
+
+        assert!(is_ignored(&fs, "/lib/ALBUM/rip.log", false));
+        assert!(is_ignored(&fs, "/lib/ALBUM/track.tmp", false));
+        assert!(is_ignored(&fs, "/lib/ALBUM/keep.wav", false));
+        assert!(is_ignored(&fs, "/lib/junk", true));
+        assert!(is_ignored(&fs, "/lib/junk/track.flac", false));
+
+        // Negations in nested ignore files take precedence.
+        assert!(!is_ignored(&fs, "/lib/ALBUM/keep.flac", false));
+
+        // Ignore files above the stop directory are not used. If the stop
+        // directory is not an ancestor, only the parent directory is used.
+        let log_path = Path::new("/lib/ALBUM/rip.log");
+        assert!(!ignore_files.is_ignored(&fs, log_path, false, Path::new("/lib/ALBUM")).unwrap());
+        assert!(ignore_files.is_ignored(&fs, log_path, false, Path::new("/lib")).unwrap());
+        assert!(!ignore_files.is_ignored(&fs, log_path, false, Path::new("/other")).unwrap());
+        assert!(ignore_files.is_ignored(&fs, Path::new("/lib/track.log"), false, Path::new("/other")).unwrap());
+
+        // Changes to ignore files are picked up.
+        fs.add_file("/lib/ALBUM/.anagmaignore", "!keep.flac\n!*.log\n").unwrap();
+        assert!(!is_ignored(&fs, "/lib/ALBUM/rip.log", false));
+        assert!(!is_ignored(&fs, "/lib/ALBUM/track.tmp", false));
+    }
+}
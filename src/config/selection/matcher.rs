@@ -3,20 +3,32 @@ use std::path::Path;
 
 use globset::Error as GlobError;
 use globset::Glob;
+use globset::GlobBuilder;
 use globset::GlobSet;
 use globset::GlobSetBuilder;
+use regex::Error as RegexError;
+use regex::Regex;
+use regex::RegexSet;
 use serde::Deserialize;
 use thiserror::Error;
 
 use crate::util::ooms::Ooms;
 
 #[derive(Error, Debug)]
-#[error("invalid pattern: {0}")]
-pub struct PatternError(#[from] GlobError);
+pub enum PatternError {
+    #[error("invalid pattern: {0}")]
+    Glob(#[from] GlobError),
+    #[error("invalid regex: {0}")]
+    Regex(#[from] RegexError),
+}
 
 #[derive(Error, Debug)]
-#[error("cannot build matcher: {0}")]
-pub struct BuildError(#[from] GlobError);
+pub enum BuildError {
+    #[error("cannot build matcher: {0}")]
+    Glob(#[from] GlobError),
+    #[error("cannot build regex matcher: {0}")]
+    Regex(#[from] RegexError),
+}
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -26,39 +38,96 @@ pub enum Error {
     Build(#[from] BuildError),
 }
 
+/// A single pattern as written in a config file, either a glob string or a
+/// table with a regex, e.g. `{ regex = "^TRACK_\\d{2}\\.flac$" }`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum PatternRepr {
+    Glob(String),
+    Regex(RegexRepr),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RegexRepr {
+    regex: String,
+}
+
+/// Creates a glob from a pattern. Wildcards do not match path separators, so
+/// that patterns can be matched against relative paths as well as file names.
+fn new_glob(pattern: &str) -> Result<Glob, GlobError> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+/// A single pattern of a `Matcher`, as originally written.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    Glob(String),
+    Regex(String),
+}
+
 #[derive(Debug)]
-pub(crate) struct MatcherBuilder(GlobSetBuilder);
+pub(crate) struct MatcherBuilder {
+    globs: GlobSetBuilder,
+    glob_strs: Vec<String>,
+    regexes: Vec<String>,
+}
 
 impl MatcherBuilder {
     pub fn new() -> Self {
-        Self(GlobSetBuilder::new())
+        Self { globs: GlobSetBuilder::new(), glob_strs: Vec::new(), regexes: Vec::new() }
     }
 
     pub fn add_pattern<S: AsRef<str>>(&mut self, pattern: &S) -> Result<(), PatternError> {
-        self.add_glob(Glob::new(pattern.as_ref())?);
+        self.add_glob(new_glob(pattern.as_ref())?);
         Ok(())
     }
 
     pub fn add_glob(&mut self, glob: Glob) {
-        self.0.add(glob);
+        self.glob_strs.push(glob.glob().to_string());
+        self.globs.add(glob);
+    }
+
+    pub fn add_regex(&mut self, regex: Regex) {
+        self.regexes.push(regex.as_str().to_string());
+    }
+
+    pub fn add_pattern_repr(&mut self, pattern_repr: &PatternRepr) -> Result<(), PatternError> {
+        match pattern_repr {
+            PatternRepr::Glob(pattern) => self.add_pattern(pattern),
+            PatternRepr::Regex(RegexRepr { regex }) => {
+                self.add_regex(Regex::new(regex)?);
+                Ok(())
+            },
+        }
     }
 
     pub fn build(self) -> Result<Matcher, BuildError> {
-        Ok(Matcher(self.0.build()?))
+        Ok(Matcher {
+            globs: self.globs.build()?,
+            glob_strs: self.glob_strs,
+            regexes: RegexSet::new(&self.regexes)?,
+        })
     }
 }
 
-/// Filter for file paths that uses zero or more glob patterns to perform matching.
+/// Filter for file paths that uses zero or more glob or regex patterns to
+/// perform matching. A path matches if any of the patterns match.
 #[derive(Debug, Deserialize)]
 #[serde(try_from = "MatcherRepr")]
-pub struct Matcher(GlobSet);
+pub struct Matcher {
+    globs: GlobSet,
+    // The original glob strings, in the same order as in the glob set.
+    glob_strs: Vec<String>,
+    regexes: RegexSet,
+}
 
 impl Matcher {
     /// Attempts to build a matcher out of an iterable of string-likes.
-    pub fn build<'a, I, S: 'a>(pattern_strs: I) -> Result<Self, Error>
+    pub fn build<'a, I, S>(pattern_strs: I) -> Result<Self, Error>
     where
         I: IntoIterator<Item = &'a S>,
-        S: AsRef<str>,
+        S: AsRef<str> + 'a,
     {
         let mut builder = MatcherBuilder::new();
 
@@ -69,30 +138,99 @@ impl Matcher {
         Ok(builder.build()?)
     }
 
+    /// Similar to `build`, but treats each string-like as a regex instead of
+    /// a glob. Regexes are not implicitly anchored, so `^` and `$` need to be
+    /// used in order to match an entire file name.
+    pub fn build_regex<'a, I, S>(regex_strs: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = &'a S>,
+        S: AsRef<str> + 'a,
+    {
+        let mut builder = MatcherBuilder::new();
+
+        for regex_str in regex_strs {
+            builder.add_regex(Regex::new(regex_str.as_ref()).map_err(PatternError::from)?);
+        }
+
+        Ok(builder.build()?)
+    }
+
     /// Matches a path based on its file name. If the path does not have a file
-    /// name (e.g. '/' on Unix systems), returns `false`.
+    /// name (e.g. '/' on Unix systems), returns `false`. Regexes only match
+    /// file names that are valid UTF-8.
     pub fn is_match<P: AsRef<Path>>(&self, path: &P) -> bool {
         // Matching on only file name is needed for patterns such as "self*".
         path.as_ref()
             .file_name()
-            .map(|f| self.0.is_match(f))
+            .map(|f| {
+                self.globs.is_match(f)
+                || f.to_str().map(|s| self.regexes.is_match(s)).unwrap_or(false)
+            })
             .unwrap_or(false)
     }
 
+    /// Matches a relative path as a whole, e.g. `DISC_01/TRACK_01.flac` is
+    /// matched by `DISC_*/*.flac`. Regexes are matched against the path with
+    /// `/` as the separator.
+    pub fn is_path_match<P: AsRef<Path>>(&self, rel_path: &P) -> bool {
+        let rel_path = rel_path.as_ref();
+
+        self.globs.is_match(rel_path)
+        || Self::join_path(rel_path).map(|s| self.regexes.is_match(&s)).unwrap_or(false)
+    }
+
+    /// Similar to `is_match`, but returns all of the patterns that match.
+    pub fn matching_patterns<P: AsRef<Path>>(&self, path: &P) -> Vec<Pattern> {
+        match path.as_ref().file_name() {
+            Some(f) => self.collect_matching(Path::new(f), f.to_str()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Similar to `is_path_match`, but returns all of the patterns that match.
+    pub fn matching_path_patterns<P: AsRef<Path>>(&self, rel_path: &P) -> Vec<Pattern> {
+        let rel_path = rel_path.as_ref();
+        self.collect_matching(rel_path, Self::join_path(rel_path).as_deref())
+    }
+
+    fn collect_matching(&self, glob_target: &Path, regex_target: Option<&str>) -> Vec<Pattern> {
+        let globs = self.globs
+            .matches(glob_target)
+            .into_iter()
+            .map(|i| Pattern::Glob(self.glob_strs[i].clone()));
+
+        let regexes = regex_target
+            .map(|s| self.regexes.matches(s).into_iter().collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|i| Pattern::Regex(self.regexes.patterns()[i].clone()));
+
+        globs.chain(regexes).collect()
+    }
+
+    // Joins the components of a path with `/`, if they are all valid UTF-8.
+    fn join_path(rel_path: &Path) -> Option<String> {
+        rel_path
+            .iter()
+            .map(|c| c.to_str())
+            .collect::<Option<Vec<_>>>()
+            .map(|cs| cs.join("/"))
+    }
+
     /// Returns a matcher that matches any path that has a file name.
     pub fn any() -> Self {
         // Assume that this is a universal pattern, and will not fail.
-        Self::build(&["*"]).unwrap()
+        Self::build(&["**"]).unwrap()
     }
 
     /// Returns a matcher that matches no paths.
     pub fn empty() -> Self {
-        Self(GlobSet::empty())
+        Self { globs: GlobSet::empty(), glob_strs: Vec::new(), regexes: RegexSet::empty() }
     }
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(try_from = "Ooms")]
+#[serde(try_from = "Ooms<PatternRepr>")]
 pub(crate) enum MatcherRepr {
     Any,
     Empty,
@@ -102,40 +240,43 @@ pub(crate) enum MatcherRepr {
 impl MatcherRepr {
     pub fn add_pattern<S: AsRef<str>>(&mut self, pattern: &S) -> Result<(), PatternError> {
         // Always verify that the pattern is valid.
-        let glob = Glob::new(pattern.as_ref())?;
+        let glob = new_glob(pattern.as_ref())?;
         self.add_glob(glob);
         Ok(())
     }
 
     pub fn add_glob(&mut self, glob: Glob) {
+        if let Some(builder) = self.builder_mut() {
+            builder.add_glob(glob);
+        }
+    }
+
+    /// Returns the builder that new patterns should be added to, if any.
+    fn builder_mut(&mut self) -> Option<&mut MatcherBuilder> {
         match self {
             // No-op, all patterns are already included.
-            Self::Any => {}
+            Self::Any => None,
 
             // Redefine as a custom variant.
             Self::Empty => {
-                let mut builder = MatcherBuilder::new();
-                builder.add_glob(glob);
-
-                *self = Self::Custom(builder);
+                *self = Self::Custom(MatcherBuilder::new());
+                self.builder_mut()
             }
 
             // Add the pattern to the existing ones.
-            Self::Custom(ref mut builder) => {
-                builder.add_glob(glob);
-            }
-        };
+            Self::Custom(ref mut builder) => Some(builder),
+        }
     }
 }
 
-impl TryFrom<Ooms> for MatcherRepr {
+impl TryFrom<Ooms<PatternRepr>> for MatcherRepr {
     type Error = PatternError;
 
-    fn try_from(value: Ooms) -> Result<Self, Self::Error> {
+    fn try_from(value: Ooms<PatternRepr>) -> Result<Self, Self::Error> {
         let mut builder = MatcherBuilder::new();
 
-        for pattern in value.iter() {
-            builder.add_pattern(&pattern)?;
+        for pattern_repr in value.iter() {
+            builder.add_pattern_repr(pattern_repr)?;
         }
 
         Ok(MatcherRepr::Custom(builder))
@@ -154,10 +295,10 @@ impl TryFrom<MatcherRepr> for Matcher {
     }
 }
 
-impl TryFrom<Ooms> for Matcher {
+impl TryFrom<Ooms<PatternRepr>> for Matcher {
     type Error = Error;
 
-    fn try_from(value: Ooms) -> Result<Self, Self::Error> {
+    fn try_from(value: Ooms<PatternRepr>) -> Result<Self, Self::Error> {
         let mr = TryInto::<MatcherRepr>::try_into(value)?;
         Ok(mr.try_into()?)
     }
@@ -184,6 +325,91 @@ mod tests {
         assert_eq!(matcher.is_match(&"photo.png"), false);
     }
 
+    #[test]
+    fn deserialization_regex() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            patterns: Matcher,
+        }
+
+        let text = r#"
+            - '*.flac'
+            - regex: '^TRACK_\d{2}\.(flac|opus)$'
+        "#;
+        let matcher: Matcher = serde_yaml::from_str(text).unwrap();
+
+        assert!(matcher.is_match(&"music.flac"));
+        assert!(matcher.is_match(&"TRACK_01.opus"));
+        assert!(!matcher.is_match(&"TRACK_1.opus"));
+        assert!(!matcher.is_match(&"TRACK_01.opus.bak"));
+
+        let text = "regex: '^self'";
+        let matcher: Matcher = serde_yaml::from_str(text).unwrap();
+
+        assert!(matcher.is_match(&"self.json"));
+        assert!(!matcher.is_match(&"myself.json"));
+
+        // Invalid regexes are reported.
+        let text = "- regex: '(unclosed'";
+        assert!(serde_yaml::from_str::<Matcher>(text).is_err());
+
+        let text = "- regex: '.*'\n  glob: '*'";
+        assert!(serde_yaml::from_str::<Matcher>(text).is_err());
+
+        let text = r#"
+            patterns = ["*.flac", { regex = '^TRACK_\d{2}\.opus$' }]
+        "#;
+        let wrapper: Wrapper = toml::from_str(text).unwrap();
+
+        assert!(wrapper.patterns.is_match(&"music.flac"));
+        assert!(wrapper.patterns.is_match(&"TRACK_02.opus"));
+        assert!(!wrapper.patterns.is_match(&"music.opus"));
+    }
+
+    #[test]
+    fn matching_patterns() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            patterns: Matcher,
+        }
+
+        let text = r#"
+            patterns = ["*.flac", "TRACK_*", "DISC_*/*.flac", { regex = '^TRACK_\d{2}' }]
+        "#;
+        let matcher = toml::from_str::<Wrapper>(text).unwrap().patterns;
+
+        assert_eq!(
+            matcher.matching_patterns(&"ALBUM/TRACK_01.flac"),
+            vec![
+                Pattern::Glob(String::from("*.flac")),
+                Pattern::Glob(String::from("TRACK_*")),
+                Pattern::Regex(String::from(r"^TRACK_\d{2}")),
+            ],
+        );
+        assert_eq!(matcher.matching_patterns(&"cover.png"), vec![]);
+        assert_eq!(matcher.matching_patterns(&"/"), vec![]);
+
+        assert_eq!(
+            matcher.matching_path_patterns(&"DISC_01/TRACK_01.flac"),
+            vec![Pattern::Glob(String::from("DISC_*/*.flac"))],
+        );
+        assert_eq!(Matcher::empty().matching_patterns(&"TRACK_01.flac"), vec![]);
+        assert_eq!(Matcher::any().matching_patterns(&"TRACK_01.flac"), vec![Pattern::Glob(String::from("**"))]);
+    }
+
+    #[test]
+    fn build_regex() {
+        assert!(Matcher::build_regex(&["^a$", r"\.flac$"]).is_ok());
+        assert!(Matcher::build_regex(&[""; 0]).is_ok());
+        assert!(matches!(Matcher::build_regex(&["(a"]), Err(Error::Pattern(PatternError::Regex(..)))));
+        assert!(matches!(Matcher::build_regex(&["[z-a]"]), Err(Error::Pattern(PatternError::Regex(..)))));
+
+        let matcher = Matcher::build_regex(&[r"^TRACK_\d{2}\.flac$"]).unwrap();
+        assert!(matcher.is_match(&"extra/TRACK_01.flac"));
+        assert!(!matcher.is_match(&"extra/TRACK_01.flac/other"));
+        assert!(!matcher.is_match(&"/"));
+    }
+
     #[test]
     fn build() {
         // Positive test cases.
@@ -0,0 +1,174 @@
+//! Filters on the resolved metadata of item files, as opposed to their paths.
+
+use std::collections::BTreeMap;
+
+use crate::types::{Block, Value};
+
+/// A condition that is met when the value at a key path in a metadata block
+/// is equal to an expected value. Key paths are written with dots between
+/// keys, such as `release.status`. A key that contains a dot or a backslash
+/// needs it escaped with a backslash, such as `disc\.1.title`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaCondition {
+    key_path: Vec<String>,
+    value: Value,
+}
+
+impl MetaCondition {
+    pub fn new(key_path: &str, value: Value) -> Self {
+        let mut keys = vec![String::new()];
+        let mut chars = key_path.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => keys.last_mut().unwrap().extend(chars.next()),
+                '.' => keys.push(String::new()),
+                c => keys.last_mut().unwrap().push(c),
+            }
+        }
+
+        Self::from_key_path(keys, value)
+    }
+
+    /// Creates a condition from a key path that is already split into keys,
+    /// which are used as is.
+    pub fn from_key_path(key_path: Vec<String>, value: Value) -> Self {
+        Self { key_path, value, }
+    }
+
+    pub fn key_path(&self) -> &[String] {
+        &self.key_path
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Returns true if the value at the key path of a block is equal to the
+    /// expected value. Missing keys never match.
+    pub fn is_match(&self, block: &Block) -> bool {
+        let (first, rest) = match self.key_path.split_first() {
+            Some(split) => split,
+            None => return false,
+        };
+
+        block
+            .get(first)
+            .and_then(|v| v.get_key_path(rest))
+            .is_some_and(|v| *v == self.value)
+    }
+}
+
+/// Filters that the processed metadata of item files need to pass in order to
+/// be included in the results of a tree walk.
+///
+/// These are applied when walking with `Library::walk`, and when processing
+/// with `Library::process_tree`, `Processor::process_tree`, or its parallel
+/// version. Walking with `Library::walk` processes the metadata of each item
+/// file in order to evaluate these filters. A bare `FileWalker`, async
+/// selection, and processing impacted item files work on paths alone, and
+/// ignore these filters.
+///
+/// As metadata is only known after meta files are plexed, and plexing needs
+/// to know which item files are selected, these filters are never used when
+/// plexing. This means that items removed by these filters still take up
+/// their positions in sequence meta files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetaFilters {
+    /// Conditions that all need to be met for an item to be included.
+    pub include: Vec<MetaCondition>,
+
+    /// Conditions that exclude an item if any are met. Excluded directories
+    /// are not walked into, so their descendants are excluded as well.
+    pub exclude: Vec<MetaCondition>,
+}
+
+impl MetaFilters {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Returns true if a block meets all of the include conditions.
+    pub fn is_included(&self, block: &Block) -> bool {
+        self.include.iter().all(|c| c.is_match(block))
+    }
+
+    /// Returns true if a block meets any of the exclude conditions.
+    pub fn is_excluded(&self, block: &Block) -> bool {
+        self.exclude.iter().any(|c| c.is_match(block))
+    }
+
+    /// Returns true if a block is included and not excluded.
+    pub fn is_match(&self, block: &Block) -> bool {
+        self.is_included(block) && !self.is_excluded(block)
+    }
+
+    pub(crate) fn from_reprs(include: BTreeMap<String, Value>, exclude: BTreeMap<String, Value>) -> Self {
+        let to_conditions = |m: BTreeMap<String, Value>| {
+            m.into_iter().map(|(k, v)| MetaCondition::new(&k, v)).collect()
+        };
+
+        Self { include: to_conditions(include), exclude: to_conditions(exclude), }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn is_match() {
+        let block = Block(btreemap![
+            str!("hidden") => Value::Boolean(true),
+            str!("status") => TU::s("final"),
+            str!("release") => Value::Mapping(Block(btreemap![
+                str!("year") => TU::i(1999),
+            ])),
+        ]);
+
+        assert!(MetaCondition::new("hidden", Value::Boolean(true)).is_match(&block));
+        assert!(MetaCondition::new("release.year", TU::i(1999)).is_match(&block));
+        assert!(!MetaCondition::new("status", TU::s("draft")).is_match(&block));
+        assert!(!MetaCondition::new("release", TU::i(1999)).is_match(&block));
+        assert!(!MetaCondition::new("missing", Value::Null).is_match(&block));
+        assert!(!MetaCondition::new("status.sub", TU::s("final")).is_match(&block));
+
+        let escaped_block = Block(btreemap![
+            str!("disc.1") => Value::Mapping(Block(btreemap![
+                str!("a\\b") => TU::s("title"),
+            ])),
+        ]);
+
+        let condition = MetaCondition::new("disc\\.1.a\\\\b", TU::s("title"));
+        assert_eq!(condition.key_path(), &["disc.1", "a\\b"]);
+        assert!(condition.is_match(&escaped_block));
+        assert_eq!(
+            condition,
+            MetaCondition::from_key_path(vec![str!("disc.1"), str!("a\\b")], TU::s("title")),
+        );
+        assert!(!MetaCondition::new("disc.1.a\\\\b", TU::s("title")).is_match(&escaped_block));
+
+        let filters = MetaFilters::default();
+        assert!(filters.is_empty());
+        assert!(filters.is_match(&block));
+        assert!(filters.is_match(&Block::new()));
+
+        let filters = MetaFilters::from_reprs(
+            btreemap![str!("status") => TU::s("final")],
+            btreemap![str!("hidden") => Value::Boolean(true)],
+        );
+        assert!(!filters.is_empty());
+        assert!(filters.is_included(&block));
+        assert!(filters.is_excluded(&block));
+        assert!(!filters.is_match(&block));
+        assert!(!filters.is_match(&Block::new()));
+
+        let block = Block(btreemap![str!("status") => TU::s("final")]);
+        assert!(filters.is_match(&block));
+    }
+}
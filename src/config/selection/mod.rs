@@ -1,7 +1,13 @@
+mod explanation;
+mod fs_filters;
+mod ignore_files;
 mod matcher;
+mod meta_filters;
+mod rules;
 
+use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
-use std::fs::ReadDir;
+use std::ffi::OsStr;
 use std::io::Result as IoResult;
 use std::path::Path;
 use std::path::PathBuf;
@@ -9,8 +15,20 @@ use std::path::PathBuf;
 use serde::Deserialize;
 
 use crate::config::Sorter;
+use crate::config::sorter::SortBy;
+use crate::fs::{DirEntries, DiskFs, Fs, FsMetadata};
+use crate::sources::SidecarMode;
+use crate::types::{Block, Value};
 
-pub use self::matcher::{Error as MatcherError, Matcher};
+use self::fs_filters::Timestamp;
+use self::ignore_files::IgnoreFiles;
+
+pub use self::explanation::{Decision, Explanation};
+pub use self::fs_filters::FsFilters;
+pub use self::ignore_files::IGNORE_FILE_NAME;
+pub use self::matcher::{Error as MatcherError, Matcher, Pattern};
+pub use self::meta_filters::{MetaCondition, MetaFilters};
+pub use self::rules::{Rule, RuleAction, RuleKind, RuleOrder, Rules};
 pub(crate) use self::matcher::MatcherRepr;
 
 enum FileOrDir {
@@ -18,6 +36,37 @@ enum FileOrDir {
     Dir,
 }
 
+/// How a path needs to be checked against ignore files.
+enum IgnoreCheck<'a> {
+    /// Ignore files are not used for the path.
+    Skip,
+    /// The path is an ignore file itself, which is always ignored.
+    IgnoreFile,
+    /// The ignore files in the directories up to a stop directory are used.
+    Lookup(&'a IgnoreFiles, &'a Path),
+}
+
+/// Represents which part of a path the patterns of a `Selection` are matched
+/// against. When matching against a relative path, globs such as `*` do not
+/// match across path separators, so `**/*.flac` is needed to match nested
+/// files. Paths that cannot be made relative are matched by file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternScope {
+    /// Patterns are matched against the file name of a path.
+    #[default]
+    Name,
+
+    /// Patterns are matched against the path relative to the directory of the
+    /// meta file that is being plexed. This only differs from `Name` for root
+    /// meta files, whose keys can refer to nested item paths.
+    MetaDir,
+
+    /// Patterns are matched against the path relative to the library root.
+    /// If no library root is set, this behaves like `MetaDir`.
+    Root,
+}
+
 /// A type that represents included and excluded item files and directories.
 #[derive(Debug)]
 pub struct Selection {
@@ -25,6 +74,17 @@ pub struct Selection {
     exclude_files: Matcher,
     include_dirs: Matcher,
     exclude_dirs: Matcher,
+    // Exclusions added automatically by `Config`, such as for meta files.
+    auto_exclude_files: Matcher,
+    auto_exclude_dirs: Matcher,
+    // Sidecar meta file suffixes, excluded only when their item file exists.
+    auto_exclude_sidecars: Vec<(String, SidecarMode)>,
+    scope: PatternScope,
+    root: Option<PathBuf>,
+    ignore_files: Option<IgnoreFiles>,
+    filters: FsFilters,
+    meta_filters: MetaFilters,
+    rules: Option<Rules>,
 }
 
 impl Default for Selection {
@@ -50,9 +110,99 @@ impl Selection {
             exclude_files,
             include_dirs,
             exclude_dirs,
+            auto_exclude_files: Matcher::empty(),
+            auto_exclude_dirs: Matcher::empty(),
+            auto_exclude_sidecars: Vec::new(),
+            scope: PatternScope::default(),
+            root: None,
+            ignore_files: None,
+            filters: FsFilters::default(),
+            meta_filters: MetaFilters::default(),
+            rules: None,
         }
     }
 
+    /// Enables or disables the use of ignore files (`.anagmaignore`). These
+    /// use gitignore syntax, and apply to all paths inside of the directory
+    /// that contains them, unless overridden by an ignore file in a nested
+    /// directory. Ignore files are looked for in ancestor directories up to
+    /// the library root if set, or else up to the origin of the tree walk or
+    /// the directory of the meta file being plexed. Without any of these, only
+    /// the ignore file in the parent directory of a path is used. Ignore files
+    /// themselves are never selected.
+    pub fn with_ignore_files(mut self, enabled: bool) -> Self {
+        self.ignore_files = if enabled { Some(IgnoreFiles::default()) } else { None };
+        self
+    }
+
+    pub fn uses_ignore_files(&self) -> bool {
+        self.ignore_files.is_some()
+    }
+
+    /// Sets filters on the size, mod time, and other filesystem properties
+    /// of item paths. Paths need to pass these in addition to matching
+    /// patterns in order to be selected.
+    pub fn with_filters(mut self, filters: FsFilters) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn filters(&self) -> &FsFilters {
+        &self.filters
+    }
+
+    /// Sets filters on the processed metadata of item files. These are used
+    /// by `Library::walk`, `Library::process_tree`, `Processor::process_tree`
+    /// and its parallel version. They are not used when plexing meta files,
+    /// looking up a single item file, walking with a bare `FileWalker`,
+    /// selecting asynchronously, or processing impacted item files.
+    pub fn with_meta_filters(mut self, meta_filters: MetaFilters) -> Self {
+        self.meta_filters = meta_filters;
+        self
+    }
+
+    pub fn meta_filters(&self) -> &MetaFilters {
+        &self.meta_filters
+    }
+
+    /// Returns true if the processed metadata of an item file passes the
+    /// metadata filters of this `Selection`.
+    pub fn is_meta_selected(&self, block: &Block) -> bool {
+        self.meta_filters.is_match(block)
+    }
+
+    /// Sets an ordered list of rules to use instead of the include patterns.
+    /// Exclude patterns, as well as the exclusions that `Config` adds for meta
+    /// files, still apply after the rules.
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    pub fn rules(&self) -> Option<&Rules> {
+        self.rules.as_ref()
+    }
+
+    pub fn with_scope(mut self, scope: PatternScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    pub fn scope(&self) -> PatternScope {
+        self.scope
+    }
+
+    /// Sets the library root that paths are made relative to when using
+    /// `PatternScope::Root`. This is done automatically by `Library`.
+    pub fn set_root<P: Into<PathBuf>>(&mut self, root: P) -> &mut Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
     pub fn from_patterns<'a, IA, SA, IB, SB, IC, SC, ID, SD>(
         include_file_patterns: IA,
         exclude_file_patterns: IB,
@@ -83,17 +233,52 @@ impl Selection {
     }
 
     fn is_pattern_match<P: AsRef<Path>>(&self, path: &P, fod: FileOrDir) -> bool {
-        let (inc, exc) = match fod {
-            FileOrDir::File => (&self.include_files, &self.exclude_files),
-            FileOrDir::Dir => (&self.include_dirs, &self.exclude_dirs),
+        self.is_pattern_match_from(None, path.as_ref(), fod)
+    }
+
+    /// Returns the include, exclude, and automatic exclude matchers to use
+    /// for either files or directories.
+    fn matchers(&self, fod: &FileOrDir) -> (&Matcher, &Matcher, &Matcher) {
+        match fod {
+            FileOrDir::File => (&self.include_files, &self.exclude_files, &self.auto_exclude_files),
+            FileOrDir::Dir => (&self.include_dirs, &self.exclude_dirs, &self.auto_exclude_dirs),
+        }
+    }
+
+    /// Returns the relative path that patterns should be matched against, if
+    /// any. If there is none, patterns are matched against the file name.
+    fn pattern_rel_path<'p>(&self, base_dir_path: Option<&Path>, path: &'p Path) -> Option<&'p Path> {
+        let base_dir_path = match self.scope {
+            PatternScope::Name => None,
+            PatternScope::MetaDir => base_dir_path,
+            PatternScope::Root => self.root.as_deref().or(base_dir_path),
         };
 
-        inc.is_match(&path) && !exc.is_match(&path)
+        base_dir_path
+            .and_then(|b| path.strip_prefix(b).ok())
+            .filter(|p| !p.as_os_str().is_empty())
+    }
+
+    fn is_pattern_match_from(&self, base_dir_path: Option<&Path>, path: &Path, fod: FileOrDir) -> bool {
+        let (inc, exc, auto_exc) = self.matchers(&fod);
+        let rel_path = self.pattern_rel_path(base_dir_path, path);
+
+        let is_match = |matcher: &Matcher| match rel_path {
+            Some(rel_path) => matcher.is_path_match(&rel_path),
+            None => matcher.is_match(&path),
+        };
+
+        let is_included = match &self.rules {
+            Some(rules) => rules.is_included(&fod, is_match),
+            None => is_match(inc),
+        };
+
+        is_included && !is_match(exc) && !is_match(auto_exc)
     }
 
     /// Returns true if the path matches according to the file matcher.
     /// In order to be a pattern match, the path must match the include filter,
-    /// and must NOT match the exclude filter.
+    /// or be included by the rules if set, and must NOT match the exclude filter.
     /// Note that this method assumes the path is a file, and uses only the
     /// lexical content of the path; it does not access the filesystem.
     pub fn is_file_pattern_match<P: AsRef<Path>>(&self, path: &P) -> bool {
@@ -102,7 +287,7 @@ impl Selection {
 
     /// Returns true if the path matches according to the directory matcher.
     /// In order to be a pattern match, the path must match the include filter,
-    /// and must NOT match the exclude filter.
+    /// or be included by the rules if set, and must NOT match the exclude filter.
     /// Note that this method assumes the path is a directory, and uses only the
     /// lexical content of the path; it does not access the filesystem.
     pub fn is_dir_pattern_match<P: AsRef<Path>>(&self, path: &P) -> bool {
@@ -112,26 +297,298 @@ impl Selection {
     /// Returns true if a path is selected.
     /// This accesses the filesystem to tell if the path is a file or directory.
     pub fn is_selected<P: AsRef<Path>>(&self, path: &P) -> IoResult<bool> {
-        let file_info = std::fs::metadata(&path)?;
+        self.is_selected_in(&DiskFs, path)
+    }
+
+    /// Similar to `is_selected`, but accesses a given filesystem.
+    pub fn is_selected_in<P: AsRef<Path>>(&self, fs: &dyn Fs, path: &P) -> IoResult<bool> {
+        self.is_selected_opt_from_in(fs, None, None, path.as_ref())
+    }
+
+    /// Similar to `is_selected`, but for a path inside of the directory of
+    /// the meta file being plexed, which is used with `PatternScope::MetaDir`.
+    pub fn is_selected_from<P: AsRef<Path>>(&self, base_dir_path: &Path, path: &P) -> IoResult<bool> {
+        self.is_selected_from_in(&DiskFs, base_dir_path, path)
+    }
+
+    /// Similar to `is_selected_from`, but accesses a given filesystem.
+    pub fn is_selected_from_in<P: AsRef<Path>>(&self, fs: &dyn Fs, base_dir_path: &Path, path: &P) -> IoResult<bool> {
+        self.is_selected_opt_from_in(fs, Some(base_dir_path), Some(base_dir_path), path.as_ref())
+    }
+
+    /// Similar to `is_selected_in`, but for a path found by walking from an
+    /// origin directory. Ignore files are looked for up to the origin
+    /// directory, so that they are inherited by the walked subdirectories
+    /// even without a library root.
+    pub(crate) fn is_selected_within_in(&self, fs: &dyn Fs, origin_dir_path: &Path, path: &Path) -> IoResult<bool> {
+        self.is_selected_opt_from_in(fs, None, Some(origin_dir_path), path)
+    }
+
+    fn is_selected_opt_from_in(
+        &self,
+        fs: &dyn Fs,
+        base_dir_path: Option<&Path>,
+        origin_dir_path: Option<&Path>,
+        path: &Path,
+    ) -> IoResult<bool> {
+        let file_info = fs.metadata(path)?;
+
+        if self.is_ignored(fs, origin_dir_path, path, file_info.is_dir())? {
+            return Ok(false);
+        }
+
+        if !self.filters.is_match_in(fs, path, &file_info, self.root.as_deref().or(origin_dir_path))? {
+            return Ok(false);
+        }
+
+        if file_info.is_file() && self.is_auto_excluded_sidecar(fs, path)? {
+            return Ok(false);
+        }
+
+        Ok(self.is_info_pattern_match(base_dir_path, path, &file_info))
+    }
 
-        Ok(if file_info.is_file() {
-            self.is_file_pattern_match(path)
+    /// Returns true if a path matches the file or directory patterns, based
+    /// on what kind of entry its file info says it is. Entries that are
+    /// neither files nor directories never match.
+    fn is_info_pattern_match(&self, base_dir_path: Option<&Path>, path: &Path, file_info: &FsMetadata) -> bool {
+        if file_info.is_file() {
+            self.is_pattern_match_from(base_dir_path, path, FileOrDir::File)
         } else if file_info.is_dir() {
-            self.is_dir_pattern_match(path)
+            self.is_pattern_match_from(base_dir_path, path, FileOrDir::Dir)
         } else {
             false
-        })
+        }
+    }
+
+    /// Async version of `is_selected`.
+    #[cfg(feature = "async")]
+    pub async fn is_selected_async<P: AsRef<Path>>(&self, path: &P) -> IoResult<bool> {
+        self.is_selected_opt_from_async(None, path.as_ref()).await
+    }
+
+    /// Async version of `is_selected_from`.
+    #[cfg(feature = "async")]
+    pub async fn is_selected_from_async<P: AsRef<Path>>(&self, base_dir_path: &Path, path: &P) -> IoResult<bool> {
+        self.is_selected_opt_from_async(Some(base_dir_path), path.as_ref()).await
+    }
+
+    #[cfg(feature = "async")]
+    async fn is_selected_opt_from_async(&self, base_dir_path: Option<&Path>, path: &Path) -> IoResult<bool> {
+        let file_info = FsMetadata::from(tokio::fs::metadata(path).await?);
+
+        if self.is_ignored_async(base_dir_path, path, file_info.is_dir()).await? {
+            return Ok(false);
+        }
+
+        if !self.filters.is_match_async(path, &file_info, self.root.as_deref().or(base_dir_path)).await? {
+            return Ok(false);
+        }
+
+        if file_info.is_file() && self.is_auto_excluded_sidecar_async(path).await? {
+            return Ok(false);
+        }
+
+        Ok(self.is_info_pattern_match(base_dir_path, path, &file_info))
+    }
+
+    /// Returns true if a path is excluded by ignore files, if enabled.
+    fn is_ignored(&self, fs: &dyn Fs, origin_dir_path: Option<&Path>, path: &Path, is_dir: bool) -> IoResult<bool> {
+        match self.ignore_check(origin_dir_path, path, is_dir) {
+            IgnoreCheck::Skip => Ok(false),
+            IgnoreCheck::IgnoreFile => Ok(true),
+            IgnoreCheck::Lookup(ignore_files, stop_dir_path) => ignore_files.is_ignored(fs, path, is_dir, stop_dir_path),
+        }
+    }
+
+    /// Async version of `is_ignored`.
+    #[cfg(feature = "async")]
+    async fn is_ignored_async(&self, origin_dir_path: Option<&Path>, path: &Path, is_dir: bool) -> IoResult<bool> {
+        match self.ignore_check(origin_dir_path, path, is_dir) {
+            IgnoreCheck::Skip => Ok(false),
+            IgnoreCheck::IgnoreFile => Ok(true),
+            IgnoreCheck::Lookup(ignore_files, stop_dir_path) => {
+                ignore_files.is_ignored_async(path, is_dir, stop_dir_path).await
+            },
+        }
+    }
+
+    /// Decides how to check a path against ignore files, without accessing
+    /// the filesystem.
+    fn ignore_check<'p>(&'p self, origin_dir_path: Option<&'p Path>, path: &'p Path, is_dir: bool) -> IgnoreCheck<'p> {
+        let ignore_files = match &self.ignore_files {
+            Some(ignore_files) => ignore_files,
+            None => return IgnoreCheck::Skip,
+        };
+
+        if Self::is_ignore_file(path, is_dir) {
+            return IgnoreCheck::IgnoreFile;
+        }
+
+        match self.ignore_stop_dir(origin_dir_path, path) {
+            Some(stop_dir_path) => IgnoreCheck::Lookup(ignore_files, stop_dir_path),
+            None => IgnoreCheck::Skip,
+        }
+    }
+
+    /// Returns true if selecting and sorting paths with a sorter depends on
+    /// the mod times or sizes of the paths, which can change without changing
+    /// the mod time of their parent directory.
+    pub(crate) fn uses_item_stats(&self, sorter: &Sorter) -> bool {
+        sorter.sort_by == SortBy::ModTime || self.filters != FsFilters::default()
+    }
+
+    /// Returns the paths of the ignore files that could affect the selection
+    /// of paths in a directory, when selected without an origin directory.
+    /// This is empty if ignore files are not used.
+    pub(crate) fn ignore_file_paths(&self, dir_path: &Path) -> Vec<PathBuf> {
+        if self.ignore_files.is_none() {
+            return Vec::new();
+        }
+
+        // Any path in the directory has the same ignore files, so use the
+        // path of the directory's own ignore file.
+        let path = dir_path.join(IGNORE_FILE_NAME);
+
+        match self.ignore_stop_dir(None, &path) {
+            Some(stop_dir_path) => IgnoreFiles::dir_paths(&path, stop_dir_path)
+                .map(|p| p.join(IGNORE_FILE_NAME))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn is_ignore_file(path: &Path, is_dir: bool) -> bool {
+        !is_dir && path.file_name() == Some(IGNORE_FILE_NAME.as_ref())
+    }
+
+    /// Returns the farthest directory to look for ignore files in: the
+    /// library root if set, or else the origin directory of the walk or plex.
+    /// Without either, or if neither contains the path, only the parent
+    /// directory of a path is used.
+    fn ignore_stop_dir<'p>(&'p self, origin_dir_path: Option<&'p Path>, path: &'p Path) -> Option<&'p Path> {
+        let parent_dir_path = path.parent()?;
+
+        self.root.as_deref()
+            .or(origin_dir_path)
+            .filter(|p| parent_dir_path.starts_with(p))
+            .or(Some(parent_dir_path))
+    }
+
+    /// Returns true if a file is a sidecar meta file that `Config` excludes
+    /// automatically. Sidecar meta files are named after their item files,
+    /// so a file only counts as one if its item file exists next to it.
+    fn is_auto_excluded_sidecar(&self, fs: &dyn Fs, path: &Path) -> IoResult<bool> {
+        for (dir_path, item_name, mode) in self.sidecar_item_names(path) {
+            let has_item = match mode {
+                SidecarMode::FullName => fs.metadata(&dir_path.join(item_name)).is_ok(),
+                SidecarMode::Stem => {
+                    fs.read_dir(dir_path)?
+                        .filter_map(Result::ok)
+                        .any(|p| Self::is_stem_item(path, &p, item_name))
+                },
+            };
+
+            if has_item {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Async version of `is_auto_excluded_sidecar`.
+    #[cfg(feature = "async")]
+    async fn is_auto_excluded_sidecar_async(&self, path: &Path) -> IoResult<bool> {
+        for (dir_path, item_name, mode) in self.sidecar_item_names(path) {
+            let has_item = match mode {
+                SidecarMode::FullName => tokio::fs::metadata(dir_path.join(item_name)).await.is_ok(),
+                SidecarMode::Stem => {
+                    let mut dir_reader = tokio::fs::read_dir(dir_path).await?;
+                    let mut has_item = false;
+
+                    while let Some(dir_entry) = dir_reader.next_entry().await? {
+                        if Self::is_stem_item(path, &dir_entry.path(), item_name) {
+                            has_item = true;
+                            break;
+                        }
+                    }
+
+                    has_item
+                },
+            };
+
+            if has_item {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the directory and the names of the item files that a file
+    /// would be a sidecar meta file for, one for each automatically excluded
+    /// sidecar suffix that its name ends with.
+    fn sidecar_item_names<'p>(&'p self, path: &'p Path) -> impl Iterator<Item = (&'p Path, &'p str, SidecarMode)> {
+        let dir_path_and_name = match (path.parent(), path.file_name().and_then(OsStr::to_str)) {
+            (Some(dir_path), Some(file_name)) => Some((dir_path, file_name)),
+            _ => None,
+        };
+
+        dir_path_and_name
+            .into_iter()
+            .flat_map(move |(dir_path, file_name)| {
+                self.auto_exclude_sidecars.iter().filter_map(move |(suffix, mode)| {
+                    let item_name = file_name
+                        .strip_suffix(suffix.as_str())
+                        .and_then(|n| n.strip_suffix('.'))
+                        .filter(|n| !n.is_empty())?;
+
+                    Some((dir_path, item_name, *mode))
+                })
+            })
+    }
+
+    /// Returns true if a sibling of a stem sidecar meta file is an item file
+    /// for it.
+    fn is_stem_item(path: &Path, sibling_path: &Path, item_name: &str) -> bool {
+        sibling_path != path && sibling_path.file_stem() == Some(OsStr::new(item_name))
     }
 
     /// Selects paths inside a directory that match this `Selection`.
     // NOTE: This returns two "levels" of `Error`, a top-level one for any error
     //       relating to accessing the passed-in directory path, and a `Vec` of
     //       `Result`s for errors encountered when iterating over sub-paths.
-    pub fn select_in_dir(&self, dir_path: &Path) -> IoResult<SelectedSubPaths> {
+    pub fn select_in_dir(&self, dir_path: &Path) -> IoResult<SelectedSubPaths<'_>> {
+        self.select_in_dir_in(&DiskFs, dir_path)
+    }
+
+    /// Similar to `select_in_dir`, but accesses a given filesystem.
+    pub fn select_in_dir_in<'a>(&'a self, fs: &'a dyn Fs, dir_path: &Path) -> IoResult<SelectedSubPaths<'a>> {
+        self.select_in_dir_opt_within_in(fs, None, dir_path)
+    }
+
+    /// Similar to `select_in_dir_in`, but for a directory found by walking
+    /// from an origin directory, see `is_selected_within_in`.
+    pub(crate) fn select_in_dir_within_in<'a>(
+        &'a self,
+        fs: &'a dyn Fs,
+        origin_dir_path: &'a Path,
+        dir_path: &Path,
+    ) -> IoResult<SelectedSubPaths<'a>> {
+        self.select_in_dir_opt_within_in(fs, Some(origin_dir_path), dir_path)
+    }
+
+    fn select_in_dir_opt_within_in<'a>(
+        &'a self,
+        fs: &'a dyn Fs,
+        origin_dir_path: Option<&'a Path>,
+        dir_path: &Path,
+    ) -> IoResult<SelectedSubPaths<'a>> {
         // Try to open the path as a directory, handle the error as appropriate.
-        let dir_reader = dir_path.read_dir()?;
+        let dir_reader = fs.read_dir(dir_path)?;
 
-        Ok(SelectedSubPaths(dir_reader, &self))
+        Ok(SelectedSubPaths(dir_reader, self, fs, origin_dir_path))
     }
 
     /// Selects paths inside a directory that match this `Selection`, and sorts them.
@@ -140,9 +597,62 @@ impl Selection {
         dir_path: &Path,
         sorter: &Sorter,
     ) -> IoResult<Vec<IoResult<PathBuf>>> {
-        let mut res_paths = self.select_in_dir(dir_path)?.collect::<Vec<_>>();
+        self.select_in_dir_sorted_in(&DiskFs, dir_path, sorter)
+    }
 
-        sorter.sort_path_results(&mut res_paths);
+    /// Similar to `select_in_dir_sorted`, but accesses a given filesystem.
+    pub fn select_in_dir_sorted_in(
+        &self,
+        fs: &dyn Fs,
+        dir_path: &Path,
+        sorter: &Sorter,
+    ) -> IoResult<Vec<IoResult<PathBuf>>> {
+        let mut res_paths = self.select_in_dir_in(fs, dir_path)?.collect::<Vec<_>>();
+
+        sorter.sort_path_results_in(fs, &mut res_paths);
+
+        Ok(res_paths)
+    }
+
+    /// Similar to `select_in_dir_sorted_in`, but for a directory found by
+    /// walking from an origin directory, see `is_selected_within_in`.
+    pub(crate) fn select_in_dir_sorted_within_in(
+        &self,
+        fs: &dyn Fs,
+        origin_dir_path: &Path,
+        dir_path: &Path,
+        sorter: &Sorter,
+    ) -> IoResult<Vec<IoResult<PathBuf>>> {
+        let mut res_paths = self.select_in_dir_within_in(fs, origin_dir_path, dir_path)?.collect::<Vec<_>>();
+
+        sorter.sort_path_results_in(fs, &mut res_paths);
+
+        Ok(res_paths)
+    }
+
+    /// Async version of `select_in_dir`. As the directory needs to be read
+    /// eagerly, the selected paths are returned in a `Vec`.
+    #[cfg(feature = "async")]
+    pub async fn select_in_dir_async(&self, dir_path: &Path) -> IoResult<Vec<IoResult<PathBuf>>> {
+        let mut dir_reader = tokio::fs::read_dir(dir_path).await?;
+        let mut res_paths = Vec::new();
+
+        loop {
+            let sub_path = match dir_reader.next_entry().await {
+                Ok(Some(dir_entry)) => dir_entry.path(),
+                Ok(None) => break,
+                Err(err) => {
+                    res_paths.push(Err(err));
+                    break;
+                },
+            };
+
+            match self.is_selected_async(&sub_path).await {
+                Ok(true) => res_paths.push(Ok(sub_path)),
+                Ok(false) => {},
+                Err(err) => res_paths.push(Err(err)),
+            }
+        }
 
         Ok(res_paths)
     }
@@ -156,6 +666,36 @@ pub(crate) struct SelectionRepr {
     pub exclude_files: MatcherRepr,
     pub include_dirs: MatcherRepr,
     pub exclude_dirs: MatcherRepr,
+    pub scope: PatternScope,
+    pub use_ignore_files: bool,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<Timestamp>,
+    pub modified_before: Option<Timestamp>,
+    pub exclude_empty_dirs: bool,
+    pub exclude_symlinks: bool,
+    pub max_depth: Option<usize>,
+    pub include_meta: BTreeMap<String, Value>,
+    pub exclude_meta: BTreeMap<String, Value>,
+    pub rules: Vec<Rule>,
+    pub rule_order: RuleOrder,
+    #[serde(skip)]
+    pub auto_exclude_files: MatcherRepr,
+    #[serde(skip)]
+    pub auto_exclude_dirs: MatcherRepr,
+    #[serde(skip)]
+    pub auto_exclude_sidecars: Vec<(String, SidecarMode)>,
+}
+
+impl SelectionRepr {
+    /// Returns true if none of the include or exclude patterns were given.
+    /// Rules are an alternative syntax, and cannot be mixed with patterns.
+    pub fn has_default_patterns(&self) -> bool {
+        matches!(
+            (&self.include_files, &self.exclude_files, &self.include_dirs, &self.exclude_dirs),
+            (MatcherRepr::Any, MatcherRepr::Empty, MatcherRepr::Any, MatcherRepr::Empty)
+        )
+    }
 }
 
 impl Default for SelectionRepr {
@@ -166,6 +706,22 @@ impl Default for SelectionRepr {
             exclude_files: MatcherRepr::Empty,
             include_dirs: MatcherRepr::Any,
             exclude_dirs: MatcherRepr::Empty,
+            scope: PatternScope::default(),
+            use_ignore_files: false,
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            modified_before: None,
+            exclude_empty_dirs: false,
+            exclude_symlinks: false,
+            max_depth: None,
+            include_meta: BTreeMap::new(),
+            exclude_meta: BTreeMap::new(),
+            rules: Vec::new(),
+            rule_order: RuleOrder::default(),
+            auto_exclude_files: MatcherRepr::Empty,
+            auto_exclude_dirs: MatcherRepr::Empty,
+            auto_exclude_sidecars: Vec::new(),
         }
     }
 }
@@ -179,11 +735,29 @@ impl TryFrom<SelectionRepr> for Selection {
             exclude_files: value.exclude_files.try_into()?,
             include_dirs: value.include_dirs.try_into()?,
             exclude_dirs: value.exclude_dirs.try_into()?,
-        })
+            auto_exclude_files: value.auto_exclude_files.try_into()?,
+            auto_exclude_dirs: value.auto_exclude_dirs.try_into()?,
+            auto_exclude_sidecars: value.auto_exclude_sidecars,
+            scope: value.scope,
+            root: None,
+            ignore_files: None,
+            filters: FsFilters {
+                min_size: value.min_size,
+                max_size: value.max_size,
+                modified_after: value.modified_after.map(|t| t.0),
+                modified_before: value.modified_before.map(|t| t.0),
+                exclude_empty_dirs: value.exclude_empty_dirs,
+                exclude_symlinks: value.exclude_symlinks,
+                max_depth: value.max_depth,
+            },
+            meta_filters: MetaFilters::from_reprs(value.include_meta, value.exclude_meta),
+            rules: if value.rules.is_empty() { None } else { Some(Rules::new(value.rules, value.rule_order)) },
+        }
+        .with_ignore_files(value.use_ignore_files))
     }
 }
 
-pub struct SelectedSubPaths<'a>(ReadDir, &'a Selection);
+pub struct SelectedSubPaths<'a>(DirEntries<'a>, &'a Selection, &'a dyn Fs, Option<&'a Path>);
 
 impl<'a> Iterator for SelectedSubPaths<'a> {
     type Item = IoResult<PathBuf>;
@@ -192,12 +766,13 @@ impl<'a> Iterator for SelectedSubPaths<'a> {
         // LEARN: Unable to inline these, had to use `let`, why is that?
         let read_dir = &mut self.0;
         let selection = &self.1;
+        let fs = self.2;
+        let origin_dir_path = self.3;
 
         // Get next entry from the directory reader.
         read_dir.find_map(|res| match res {
-            Ok(dir_entry) => {
-                let sub_path = dir_entry.path();
-                match selection.is_selected(&sub_path) {
+            Ok(sub_path) => {
+                match selection.is_selected_opt_from_in(fs, None, origin_dir_path, &sub_path) {
                     Ok(true) => Some(Ok(sub_path)),
                     Ok(false) => None,
                     Err(err) => Some(Err(err)),
@@ -212,9 +787,12 @@ impl<'a> Iterator for SelectedSubPaths<'a> {
 mod tests {
     use super::*;
 
+    use std::time::{Duration, UNIX_EPOCH};
+
     use maplit::hashset;
 
     use crate::config::Sorter;
+    use crate::fs::MemoryFs;
     use crate::test_util::TestUtil;
 
     const SAMPLE_FILE_NAMES: &[&str] = &[
@@ -360,6 +938,265 @@ mod tests {
         assert_eq!(selection.is_file_pattern_match(&"path/to/music.ogg"), false);
     }
 
+    #[test]
+    fn pattern_scope() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/lib/ALBUM_01/DISC_01/TRACK_01.flac", "").unwrap()
+            .add_file("/lib/ALBUM_01/TRACK_01.flac", "").unwrap()
+            .add_dir("/lib/ALBUM_03").unwrap();
+
+        let disc_track_path = Path::new("/lib/ALBUM_01/DISC_01/TRACK_01.flac");
+        let album_track_path = Path::new("/lib/ALBUM_01/TRACK_01.flac");
+        let album_path = Path::new("/lib/ALBUM_01");
+        let excluded_path = Path::new("/lib/ALBUM_03");
+
+        let text = r#"
+            include_files = ["DISC_*/**/*.flac", { regex = '^ALBUM_\d+/DISC_\d+/' }]
+            exclude_dirs = "ALBUM_03"
+            scope = "meta_dir"
+        "#;
+        let selection_repr: SelectionRepr = toml::from_str(text).unwrap();
+        let mut selection: Selection = selection_repr.try_into().unwrap();
+        assert_eq!(selection.scope(), PatternScope::MetaDir);
+
+        // Paths are matched relative to the meta file directory.
+        assert!(selection.is_selected_from_in(&fs, album_path, &disc_track_path).unwrap());
+        assert!(!selection.is_selected_from_in(&fs, album_path, &album_track_path).unwrap());
+        assert!(selection.is_selected_from_in(&fs, Path::new("/lib"), &disc_track_path).unwrap());
+        assert!(!selection.is_selected_from_in(&fs, Path::new("/lib"), &excluded_path).unwrap());
+
+        // Without a base directory, file names are matched.
+        assert!(!selection.is_selected_in(&fs, &disc_track_path).unwrap());
+        assert!(!selection.is_selected_in(&fs, &excluded_path).unwrap());
+
+        // Without a library root, the meta file directory is used.
+        selection = selection.with_scope(PatternScope::Root);
+        assert!(selection.is_selected_from_in(&fs, album_path, &disc_track_path).unwrap());
+        assert!(!selection.is_selected_in(&fs, &disc_track_path).unwrap());
+
+        // Paths are matched relative to the library root, if set.
+        selection.set_root("/lib/ALBUM_01/DISC_01");
+        assert!(!selection.is_selected_from_in(&fs, album_path, &disc_track_path).unwrap());
+
+        selection.set_root("/lib");
+        assert!(selection.is_selected_in(&fs, &disc_track_path).unwrap());
+        assert!(selection.is_selected_from_in(&fs, album_path, &disc_track_path).unwrap());
+        assert!(!selection.is_selected_in(&fs, &album_track_path).unwrap());
+        assert!(!selection.is_selected_in(&fs, &excluded_path).unwrap());
+        assert!(selection.is_selected_in(&fs, &album_path).unwrap());
+
+        // The default is to match file names only.
+        selection = selection.with_scope(PatternScope::Name);
+        assert!(!selection.is_selected_in(&fs, &disc_track_path).unwrap());
+        assert!(!selection.is_selected_from_in(&fs, album_path, &disc_track_path).unwrap());
+        assert!(!selection.is_selected_in(&fs, &excluded_path).unwrap());
+    }
+
+    #[test]
+    fn ignore_files() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/.anagmaignore", "*").unwrap()
+            .add_file("/lib/.anagmaignore", "*.log\nscans/\n").unwrap()
+            .add_file("/lib/ALBUM/.anagmaignore", "!rip.log").unwrap()
+            .add_file("/lib/ALBUM/rip.log", "").unwrap()
+            .add_file("/lib/ALBUM/scans/cover.png", "").unwrap()
+            .add_file("/lib/ALBUM/TRACK_01.flac", "").unwrap()
+            .add_file("/lib/ALBUM/TRACK_02.flac", "").unwrap()
+            .add_file("/lib/ALBUM/TRACK_02.log", "").unwrap();
+
+        let sorter = Sorter::default();
+        let select = |selection: &Selection, dir_path: &str| {
+            selection
+                .select_in_dir_sorted_in(&fs, Path::new(dir_path), &sorter)
+                .unwrap()
+                .into_iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+
+        // Ignore files are not used unless enabled.
+        let selection = Selection::default();
+        assert!(!selection.uses_ignore_files());
+        assert_eq!(select(&selection, "/lib/ALBUM").len(), 6);
+
+        // Without a library root, only ignore files in the parent directory
+        // of a path are used.
+        let mut selection = Selection::default().with_ignore_files(true);
+        assert_eq!(select(&selection, "/lib/ALBUM").len(), 5);
+        assert!(!selection.is_selected_in(&fs, &"/lib/ALBUM/.anagmaignore").unwrap());
+
+        // The directory of the meta file being plexed bounds the search.
+        let is_selected_from = |base: &str, path: &str| {
+            selection.is_selected_from_in(&fs, Path::new(base), &path).unwrap()
+        };
+        assert!(!is_selected_from("/lib", "/lib/ALBUM/TRACK_02.log"));
+        assert!(is_selected_from("/lib/ALBUM", "/lib/ALBUM/TRACK_02.log"));
+
+        // So does the origin of a tree walk, for all of its subdirectories.
+        let expected = vec![
+            PathBuf::from("/lib/ALBUM/TRACK_01.flac"),
+            PathBuf::from("/lib/ALBUM/TRACK_02.flac"),
+            PathBuf::from("/lib/ALBUM/rip.log"),
+        ];
+        let produced = selection
+            .select_in_dir_sorted_within_in(&fs, Path::new("/lib"), Path::new("/lib/ALBUM"), &sorter)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(produced, expected);
+
+        // An origin that does not contain a path only uses its parent directory.
+        assert!(selection.is_selected_within_in(&fs, Path::new("/lib/ALBUM"), Path::new("/lib/ALBUM")).unwrap());
+        assert!(selection.is_selected_within_in(&fs, Path::new("/other"), Path::new("/lib/ALBUM/TRACK_02.log")).unwrap());
+
+        selection.set_root("/lib");
+        assert_eq!(select(&selection, "/lib/ALBUM"), expected);
+        assert!(!selection.is_selected_in(&fs, &"/lib/ALBUM/scans/cover.png").unwrap());
+        assert!(!selection.is_selected_in(&fs, &"/lib/.anagmaignore").unwrap());
+
+        // Disabled by default when deserialized.
+        let selection_repr: SelectionRepr = toml::from_str("").unwrap();
+        let selection: Selection = selection_repr.try_into().unwrap();
+        assert!(!selection.uses_ignore_files());
+
+        let selection_repr: SelectionRepr = toml::from_str("use_ignore_files = true").unwrap();
+        let selection: Selection = selection_repr.try_into().unwrap();
+        assert!(selection.uses_ignore_files());
+    }
+
+    #[test]
+    fn filters() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/lib/ALBUM/.DS_Store", "").unwrap()
+            .add_file("/lib/ALBUM/TRACK_01.flac", "0123456789").unwrap()
+            .add_file("/lib/ALBUM/DISC_01/TRACK_01.flac", "0123456789").unwrap()
+            .add_dir("/lib/ALBUM/EMPTY").unwrap();
+
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        fs.set_modified("/lib/ALBUM/TRACK_01.flac", modified).unwrap();
+        fs.set_modified("/lib/ALBUM/DISC_01/TRACK_01.flac", modified).unwrap();
+
+        let sorter = Sorter::default();
+        let select = |selection: &Selection, dir_path: &str| {
+            selection
+                .select_in_dir_sorted_in(&fs, Path::new(dir_path), &sorter)
+                .unwrap()
+                .into_iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+
+        let selection = Selection::default();
+        assert_eq!(selection.filters(), &FsFilters::default());
+        assert_eq!(select(&selection, "/lib/ALBUM").len(), 4);
+
+        let selection_repr: SelectionRepr = toml::from_str(r#"
+            min_size = 1
+            exclude_empty_dirs = true
+            max_depth = 2
+            modified_after = 1970-01-02
+            modified_before = "2100-01-01T00:00:00Z"
+        "#).unwrap();
+        let mut selection: Selection = selection_repr.try_into().unwrap();
+
+        let expected = FsFilters {
+            min_size: Some(1),
+            exclude_empty_dirs: true,
+            max_depth: Some(2),
+            modified_after: Some(UNIX_EPOCH + Duration::from_secs(86400)),
+            modified_before: Some(UNIX_EPOCH + Duration::from_secs(4_102_444_800)),
+            ..Default::default()
+        };
+        assert_eq!(selection.filters(), &expected);
+
+        // Without a library root, depth is measured from the origin of a walk,
+        // and is not filtered at all without one.
+        assert!(selection.is_selected_in(&fs, &"/lib/ALBUM/DISC_01/TRACK_01.flac").unwrap());
+        assert!(selection.is_selected_within_in(&fs, Path::new("/lib/ALBUM"), Path::new("/lib/ALBUM/DISC_01/TRACK_01.flac")).unwrap());
+        assert!(!selection.is_selected_within_in(&fs, Path::new("/lib"), Path::new("/lib/ALBUM/DISC_01/TRACK_01.flac")).unwrap());
+
+        selection.set_root("/lib");
+        assert_eq!(
+            select(&selection, "/lib/ALBUM"),
+            vec![PathBuf::from("/lib/ALBUM/DISC_01"), PathBuf::from("/lib/ALBUM/TRACK_01.flac")],
+        );
+        assert!(!selection.is_selected_in(&fs, &"/lib/ALBUM/DISC_01/TRACK_01.flac").unwrap());
+        assert!(selection.is_selected_in(&fs, &"/lib/ALBUM/TRACK_01.flac").unwrap());
+
+        fs.set_modified("/lib/ALBUM/TRACK_01.flac", UNIX_EPOCH).unwrap();
+        assert!(!selection.is_selected_in(&fs, &"/lib/ALBUM/TRACK_01.flac").unwrap());
+
+        let selection_repr: SelectionRepr = toml::from_str("modified_after = 86400").unwrap();
+        let selection: Selection = selection_repr.try_into().unwrap();
+        assert_eq!(selection.filters().modified_after, Some(UNIX_EPOCH + Duration::from_secs(86400)));
+
+        assert!(toml::from_str::<SelectionRepr>(r#"modified_after = "last week""#).is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn is_selected_async() {
+        let temp_dir = tempfile::Builder::new().suffix("is_selected_async").tempdir().unwrap();
+        let path = temp_dir.path();
+
+        std::fs::create_dir_all(path.join("ALBUM").join("EMPTY")).unwrap();
+        std::fs::write(path.join(IGNORE_FILE_NAME), "*.log").unwrap();
+        std::fs::write(path.join("ALBUM").join(IGNORE_FILE_NAME), "*.tmp").unwrap();
+        for name in ["TRACK_01.flac", "TRACK_01.flac.json", "rip.log", "track.tmp"] {
+            std::fs::write(path.join("ALBUM").join(name), "0").unwrap();
+        }
+
+        let mut selection = Selection::default()
+            .with_ignore_files(true)
+            .with_filters(FsFilters { exclude_empty_dirs: true, ..Default::default() });
+        selection.auto_exclude_sidecars.push((String::from("json"), SidecarMode::FullName));
+        selection.set_root(path);
+
+        let album_path = path.join("ALBUM");
+        let mut produced = selection
+            .select_in_dir_async(&album_path)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        produced.sort();
+
+        assert_eq!(produced, vec![album_path.join("TRACK_01.flac")]);
+
+        for res in selection.select_in_dir(&album_path).unwrap() {
+            assert!(produced.contains(&res.unwrap()));
+        }
+    }
+
+    #[test]
+    fn meta_filters() {
+        let selection = Selection::default();
+        assert!(selection.meta_filters().is_empty());
+        assert!(selection.is_meta_selected(&Block::new()));
+
+        let selection_repr: SelectionRepr = toml::from_str(r#"
+            include_meta = { status = "final", "release.year" = 1999 }
+            exclude_meta = { hidden = true }
+        "#).unwrap();
+        let selection: Selection = selection_repr.try_into().unwrap();
+
+        let expected = MetaFilters {
+            include: vec![
+                MetaCondition::new("release.year", Value::Integer(1999)),
+                MetaCondition::new("status", Value::String(String::from("final"))),
+            ],
+            exclude: vec![MetaCondition::new("hidden", Value::Boolean(true))],
+        };
+        assert_eq!(selection.meta_filters(), &expected);
+        assert_eq!(selection.meta_filters().include[0].key_path(), &["release", "year"]);
+        assert!(!selection.is_meta_selected(&Block::new()));
+    }
+
     #[test]
     fn select_in_dir() {
         let temp_dir = TestUtil::create_simple_dir("select_in_dir", SAMPLE_FILE_NAMES);
@@ -0,0 +1,230 @@
+//! Ordered lists of include and exclude rules, as an alternative to separate
+//! include and exclude patterns.
+
+use std::convert::TryFrom;
+
+use serde::Deserialize;
+
+use super::{FileOrDir, Matcher};
+
+/// Whether a matching rule includes or excludes a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuleAction {
+    Include,
+    Exclude,
+}
+
+/// The kinds of paths that a rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    /// The rule applies to both files and directories.
+    #[default]
+    Any,
+
+    /// The rule only applies to files.
+    File,
+
+    /// The rule only applies to directories.
+    Dir,
+}
+
+/// Determines which rule decides the outcome when several rules match a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleOrder {
+    /// The first matching rule in the list wins.
+    #[default]
+    FirstMatch,
+
+    /// The last matching rule in the list wins.
+    LastMatch,
+}
+
+/// A single include or exclude rule, made up of glob or regex patterns.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RuleRepr")]
+pub struct Rule {
+    action: RuleAction,
+    kind: RuleKind,
+    matcher: Matcher,
+}
+
+impl Rule {
+    pub fn include(matcher: Matcher) -> Self {
+        Self { action: RuleAction::Include, kind: RuleKind::default(), matcher, }
+    }
+
+    pub fn exclude(matcher: Matcher) -> Self {
+        Self { action: RuleAction::Exclude, kind: RuleKind::default(), matcher, }
+    }
+
+    pub fn with_kind(mut self, kind: RuleKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn action(&self) -> RuleAction {
+        self.action
+    }
+
+    pub fn kind(&self) -> RuleKind {
+        self.kind
+    }
+
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    fn applies_to(&self, fod: &FileOrDir) -> bool {
+        matches!(
+            (self.kind, fod),
+            (RuleKind::Any, _) | (RuleKind::File, FileOrDir::File) | (RuleKind::Dir, FileOrDir::Dir)
+        )
+    }
+}
+
+/// An ordered list of rules, similar to rsync filters. Unlike separate
+/// include and exclude patterns, this allows for exceptions inside of an
+/// excluded set of paths to be included again, and vice versa. Paths that do
+/// not match any rule are included.
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+    order: RuleOrder,
+}
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>, order: RuleOrder) -> Self {
+        Self { rules, order, }
+    }
+
+    pub fn as_rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn order(&self) -> RuleOrder {
+        self.order
+    }
+
+    /// Returns the index of the rule that decides the outcome for a path, if
+    /// any. Matching a rule's patterns against the path is left to a callback.
+    pub(super) fn deciding_rule<F>(&self, fod: &FileOrDir, is_match: F) -> Option<usize>
+    where
+        F: Fn(&Matcher) -> bool,
+    {
+        let is_deciding = |(_, rule): &(usize, &Rule)| rule.applies_to(fod) && is_match(&rule.matcher);
+        let mut indexed = self.rules.iter().enumerate();
+
+        let found = match self.order {
+            RuleOrder::FirstMatch => indexed.find(is_deciding),
+            RuleOrder::LastMatch => indexed.rfind(is_deciding),
+        };
+
+        found.map(|(i, _)| i)
+    }
+
+    /// Returns true if a path is included by these rules.
+    pub(super) fn is_included<F>(&self, fod: &FileOrDir, is_match: F) -> bool
+    where
+        F: Fn(&Matcher) -> bool,
+    {
+        self.deciding_rule(fod, is_match).map(|i| self.rules[i].action) != Some(RuleAction::Exclude)
+    }
+}
+
+/// A rule as written in a config file, with exactly one of `include` or
+/// `exclude` set, e.g. `{ exclude = "*.log", kind = "file" }`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleRepr {
+    include: Option<Matcher>,
+    exclude: Option<Matcher>,
+    #[serde(default)]
+    kind: RuleKind,
+}
+
+impl TryFrom<RuleRepr> for Rule {
+    type Error = &'static str;
+
+    fn try_from(value: RuleRepr) -> Result<Self, Self::Error> {
+        let rule = match (value.include, value.exclude) {
+            (Some(matcher), None) => Self::include(matcher),
+            (None, Some(matcher)) => Self::exclude(matcher),
+            _ => return Err("rule needs exactly one of `include` or `exclude`"),
+        };
+
+        Ok(rule.with_kind(value.kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    #[test]
+    fn is_included() {
+        let rules = vec![
+            Rule::include(Matcher::build(&["keep.log"]).unwrap()),
+            Rule::exclude(Matcher::build(&["*.log"]).unwrap()),
+            Rule::exclude(Matcher::build(&["scans"]).unwrap()).with_kind(RuleKind::Dir),
+            Rule::include(Matcher::build(&["*"]).unwrap()).with_kind(RuleKind::File),
+        ];
+        let rules = Rules::new(rules, RuleOrder::FirstMatch);
+
+        let is_included = |name: &str, fod: FileOrDir| {
+            rules.is_included(&fod, |m| m.is_match(&Path::new(name)))
+        };
+
+        assert!(is_included("keep.log", FileOrDir::File));
+        assert!(!is_included("rip.log", FileOrDir::File));
+        assert!(!is_included("scans", FileOrDir::Dir));
+        assert!(is_included("scans", FileOrDir::File));
+        assert!(is_included("ALBUM", FileOrDir::Dir));
+        assert!(is_included("TRACK_01.flac", FileOrDir::File));
+
+        assert_eq!(rules.deciding_rule(&FileOrDir::File, |m| m.is_match(&"rip.log")), Some(1));
+        assert_eq!(rules.deciding_rule(&FileOrDir::Dir, |m| m.is_match(&"ALBUM")), None);
+
+        // With the last match winning, later rules override earlier ones.
+        let rules = vec![
+            Rule::exclude(Matcher::build(&["*.log"]).unwrap()),
+            Rule::include(Matcher::build(&["keep.*"]).unwrap()),
+        ];
+        let rules = Rules::new(rules, RuleOrder::LastMatch);
+
+        assert!(rules.is_included(&FileOrDir::File, |m| m.is_match(&"keep.log")));
+        assert!(!rules.is_included(&FileOrDir::File, |m| m.is_match(&"rip.log")));
+        assert!(rules.is_included(&FileOrDir::File, |m| m.is_match(&"TRACK_01.flac")));
+    }
+
+    #[test]
+    fn deserialization() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            rules: Vec<Rule>,
+        }
+
+        let text = r#"
+            [[rules]]
+            include = "keep.log"
+
+            [[rules]]
+            exclude = ["*.log", { regex = "^tmp_" }]
+            kind = "file"
+        "#;
+        let rules = toml::from_str::<Wrapper>(text).unwrap().rules;
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].action(), RuleAction::Include);
+        assert_eq!(rules[0].kind(), RuleKind::Any);
+        assert_eq!(rules[1].action(), RuleAction::Exclude);
+        assert_eq!(rules[1].kind(), RuleKind::File);
+        assert!(rules[1].matcher().is_match(&"tmp_01.flac"));
+
+        assert!(toml::from_str::<Wrapper>("[[rules]]\nkind = \"dir\"").is_err());
+        assert!(toml::from_str::<Wrapper>("[[rules]]\ninclude = \"*\"\nexclude = \"*\"").is_err());
+    }
+}
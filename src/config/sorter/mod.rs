@@ -3,12 +3,22 @@
 pub mod sort_by;
 
 use std::cmp::Ordering;
+#[cfg(feature = "async")]
+use std::collections::HashMap;
 use std::path::Path;
+#[cfg(feature = "async")]
+use std::path::PathBuf;
+#[cfg(feature = "async")]
+use std::time::SystemTime;
 
 use serde::Deserialize;
 
+use crate::fs::{DiskFs, Fs};
+
 pub use self::sort_by::SortBy;
 
+use self::sort_by::MtimeFn;
+
 /// Represents direction of ordering: ascending or descending.
 #[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
@@ -45,23 +55,90 @@ impl Sorter {
     where
         P: AsRef<Path>,
     {
-        self.align(self.sort_by.cmp_paths(abs_path_a, abs_path_b))
+        self.cmp_paths_in(&DiskFs, abs_path_a, abs_path_b)
+    }
+
+    /// Similar to `cmp_paths`, but reads any needed file info from a given
+    /// filesystem.
+    pub fn cmp_paths_in<P>(&self, fs: &dyn Fs, abs_path_a: &P, abs_path_b: &P) -> Ordering
+    where
+        P: AsRef<Path>,
+    {
+        self.cmp_paths_with(&|p| fs.mtime(p), abs_path_a, abs_path_b)
+    }
+
+    fn cmp_paths_with<P>(&self, mtime: MtimeFn<'_>, abs_path_a: &P, abs_path_b: &P) -> Ordering
+    where
+        P: AsRef<Path>,
+    {
+        self.align(self.sort_by.cmp_paths_with(mtime, abs_path_a, abs_path_b))
     }
 
     pub fn sort_paths<P>(&self, paths: &mut [P])
     where
         P: AsRef<Path>,
     {
-        paths.sort_by(|a, b| self.cmp_paths(a, b));
+        self.sort_paths_in(&DiskFs, paths)
+    }
+
+    pub fn sort_paths_in<P>(&self, fs: &dyn Fs, paths: &mut [P])
+    where
+        P: AsRef<Path>,
+    {
+        paths.sort_by(|a, b| self.cmp_paths_in(fs, a, b));
     }
 
     pub fn sort_path_results<P, E>(&self, res_paths: &mut [Result<P, E>])
+    where
+        P: AsRef<Path>,
+    {
+        self.sort_path_results_in(&DiskFs, res_paths)
+    }
+
+    pub fn sort_path_results_in<P, E>(&self, fs: &dyn Fs, res_paths: &mut [Result<P, E>])
+    where
+        P: AsRef<Path>,
+    {
+        self.sort_path_results_with(&|p| fs.mtime(p), res_paths)
+    }
+
+    /// Async version of `sort_path_results`. Any mod times needed for sorting
+    /// are read up front, so that sorting itself does not block.
+    #[cfg(feature = "async")]
+    pub async fn sort_path_results_async<P, E>(&self, res_paths: &mut [Result<P, E>])
+    where
+        P: AsRef<Path>,
+    {
+        let mtimes = self.mtimes_async(res_paths.iter().filter_map(|res| res.as_ref().ok()).map(AsRef::as_ref)).await;
+        self.sort_path_results_with(&|p| mtimes.get(p).copied().flatten(), res_paths);
+    }
+
+    /// Reads the mod times of paths from the local disk, if they are needed
+    /// for sorting.
+    #[cfg(feature = "async")]
+    async fn mtimes_async<'p, I>(&self, paths: I) -> HashMap<PathBuf, Option<SystemTime>>
+    where
+        I: Iterator<Item = &'p Path>,
+    {
+        let mut mtimes = HashMap::new();
+
+        if self.sort_by == SortBy::ModTime {
+            for path in paths {
+                let mtime = tokio::fs::metadata(path).await.ok().and_then(|m| m.modified().ok());
+                mtimes.insert(path.to_path_buf(), mtime);
+            }
+        }
+
+        mtimes
+    }
+
+    fn sort_path_results_with<P, E>(&self, mtime: MtimeFn<'_>, res_paths: &mut [Result<P, E>])
     where
         P: AsRef<Path>,
     {
         res_paths.sort_by(|res_a, res_b| {
             match (res_a, res_b) {
-                (Ok(a), Ok(b)) => self.cmp_paths(a, b),
+                (Ok(a), Ok(b)) => self.cmp_paths_with(mtime, a, b),
 
                 // These should ensure that errors always get sorted to the front.
                 (Err(_), Ok(_)) => Ordering::Less,
@@ -263,4 +340,48 @@ mod tests {
         sorter.sort_path_results(&mut produced);
         assert_eq!(produced, expected);
     }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn sort_path_results_async() {
+        let file_names = &["file_b", "file_e", "file_a", "file_c", "file_d"];
+        let temp_dir = TestUtil::create_simple_dir("sort_path_results_async", file_names);
+        let temp_dir_path = temp_dir.path();
+
+        // Mod times are in the reverse order of the names.
+        for (i, file_name) in ["file_e", "file_d", "file_c", "file_b", "file_a"].iter().enumerate() {
+            let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000 + i as u64);
+            std::fs::File::options()
+                .write(true)
+                .open(temp_dir_path.join(file_name))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let mut input = file_names
+            .iter()
+            .map(|n| Ok(temp_dir_path.join(n)))
+            .collect::<Vec<_>>();
+        input.push(Err(()));
+
+        for sort_by in [SortBy::Name, SortBy::ModTime, SortBy::Natural] {
+            for sort_order in [SortOrder::Ascending, SortOrder::Descending] {
+                let sorter = Sorter { sort_by, sort_order };
+
+                let mut expected = input.clone();
+                sorter.sort_path_results(&mut expected);
+
+                let mut produced = input.clone();
+                sorter.sort_path_results_async(&mut produced).await;
+
+                assert_eq!(produced, expected);
+            }
+        }
+
+        let sorter = Sorter { sort_by: SortBy::ModTime, sort_order: SortOrder::Ascending };
+        let mut produced = input.clone();
+        sorter.sort_path_results_async(&mut produced).await;
+        assert_eq!(produced[1], Ok(temp_dir_path.join("file_e")));
+    }
 }
@@ -1,19 +1,81 @@
 use std::cmp::Ordering;
+use std::ffi::OsStr;
 use std::path::Path;
+use std::time::SystemTime;
 
 use serde::Deserialize;
 
-use crate::util::Util;
+use crate::fs::{DiskFs, Fs};
 
-fn name_cmp<P: AsRef<Path>>(abs_path_a: &P, abs_path_b: &P) -> Ordering {
+/// Looks up the mod time of a path, for sorting by mod time.
+pub(crate) type MtimeFn<'a> = &'a dyn Fn(&Path) -> Option<SystemTime>;
+
+fn name_cmp<P: AsRef<Path>>(_mtime: MtimeFn<'_>, abs_path_a: &P, abs_path_b: &P) -> Ordering {
     let file_name_a = abs_path_a.as_ref().file_name();
     let file_name_b = abs_path_b.as_ref().file_name();
     file_name_a.cmp(&file_name_b)
 }
 
-fn mtime_cmp<P: AsRef<Path>>(abs_path_a: &P, abs_path_b: &P) -> Ordering {
-    let mtime_a = Util::mtime(abs_path_a.as_ref());
-    let mtime_b = Util::mtime(abs_path_b.as_ref());
+fn natural_cmp<P: AsRef<Path>>(_mtime: MtimeFn<'_>, abs_path_a: &P, abs_path_b: &P) -> Ordering {
+    match (abs_path_a.as_ref().file_name(), abs_path_b.as_ref().file_name()) {
+        (Some(file_name_a), Some(file_name_b)) => natural_cmp_names(file_name_a, file_name_b),
+        (file_name_a, file_name_b) => file_name_a.cmp(&file_name_b),
+    }
+}
+
+/// Compares two file names in natural order. Runs of ASCII digits are compared
+/// by numeric value, and ASCII letters are compared case-insensitively. Names
+/// that are otherwise equal, such as `track 01` and `Track 1`, are then
+/// ordered by their raw bytes, so that the order is always total. Non-UTF-8
+/// names are compared by their raw bytes as well.
+fn natural_cmp_names(name_a: &OsStr, name_b: &OsStr) -> Ordering {
+    let bytes_a = name_a.as_encoded_bytes();
+    let bytes_b = name_b.as_encoded_bytes();
+
+    let (mut i, mut j) = (0, 0);
+
+    while i < bytes_a.len() && j < bytes_b.len() {
+        let (byte_a, byte_b) = (bytes_a[i], bytes_b[j]);
+
+        let ord = if byte_a.is_ascii_digit() && byte_b.is_ascii_digit() {
+            let run_a = digit_run(&bytes_a[i..]);
+            let run_b = digit_run(&bytes_b[j..]);
+            i += run_a.len();
+            j += run_b.len();
+
+            // Leading zeros do not affect the numeric value. Without them,
+            // longer runs are larger numbers, and runs of equal length
+            // compare the same way as their digits.
+            let num_a = trim_leading_zeros(run_a);
+            let num_b = trim_leading_zeros(run_b);
+            num_a.len().cmp(&num_b.len()).then_with(|| num_a.cmp(num_b))
+        } else {
+            i += 1;
+            j += 1;
+            byte_a.to_ascii_lowercase().cmp(&byte_b.to_ascii_lowercase())
+        };
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+
+    (bytes_a.len() - i).cmp(&(bytes_b.len() - j)).then_with(|| bytes_a.cmp(bytes_b))
+}
+
+fn digit_run(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    &bytes[..len]
+}
+
+fn trim_leading_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|b| **b == b'0').count();
+    &digits[zeros..]
+}
+
+fn mtime_cmp<P: AsRef<Path>>(mtime: MtimeFn<'_>, abs_path_a: &P, abs_path_b: &P) -> Ordering {
+    let mtime_a = mtime(abs_path_a.as_ref());
+    let mtime_b = mtime(abs_path_b.as_ref());
     mtime_a.cmp(&mtime_b)
 }
 
@@ -23,20 +85,42 @@ fn mtime_cmp<P: AsRef<Path>>(abs_path_a: &P, abs_path_b: &P) -> Ordering {
 pub enum SortBy {
     Name,
     ModTime,
+    /// Sorts by file name, comparing numbers in names by their value, so that
+    /// `Track 2` comes before `Track 10`.
+    Natural,
 }
 
 impl SortBy {
     /// Compares two absolute item paths using this sorting criteria.
     pub fn cmp_paths<P>(&self, abs_path_a: &P, abs_path_b: &P) -> Ordering
+    where
+        P: AsRef<Path>,
+    {
+        self.cmp_paths_in(&DiskFs, abs_path_a, abs_path_b)
+    }
+
+    /// Similar to `cmp_paths`, but reads any needed file info from a given
+    /// filesystem.
+    pub fn cmp_paths_in<P>(&self, fs: &dyn Fs, abs_path_a: &P, abs_path_b: &P) -> Ordering
+    where
+        P: AsRef<Path>,
+    {
+        self.cmp_paths_with(&|p| fs.mtime(p), abs_path_a, abs_path_b)
+    }
+
+    /// Similar to `cmp_paths`, but gets mod times from a given function, so
+    /// that they can be read up front.
+    pub(crate) fn cmp_paths_with<P>(&self, mtime: MtimeFn<'_>, abs_path_a: &P, abs_path_b: &P) -> Ordering
     where
         P: AsRef<Path>,
     {
         let cmp_func = match self {
             Self::Name => name_cmp,
             Self::ModTime => mtime_cmp,
+            Self::Natural => natural_cmp,
         };
 
-        cmp_func(abs_path_a, abs_path_b)
+        cmp_func(mtime, abs_path_a, abs_path_b)
     }
 }
 
@@ -55,6 +139,8 @@ mod tests {
 
     use tempfile::Builder;
 
+    use crate::config::Sorter;
+
     #[test]
     fn cmp_paths() {
         // Create temp directory.
@@ -97,4 +183,59 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn natural_cmp_names() {
+        let expected = [
+            "",
+            "0",
+            "00",
+            "01",
+            "1",
+            "2",
+            "10",
+            "18446744073709551616",
+            "Track",
+            "Track 01.flac",
+            "track 01.flac",
+            "track 1.flac",
+            "Track 2.flac",
+            "Track 2b.flac",
+            "track 10.flac",
+            "Track 10a.flac",
+            "track10",
+            "track_2",
+        ];
+
+        for (o_i, o_val) in expected.iter().enumerate() {
+            for (i_i, i_val) in expected.iter().enumerate() {
+                assert_eq!(
+                    o_i.cmp(&i_i),
+                    super::natural_cmp_names(o_val.as_ref(), i_val.as_ref()),
+                    "{:?} vs {:?}", o_val, i_val,
+                );
+            }
+        }
+
+        let sort_by = SortBy::Natural;
+        assert_eq!(sort_by.cmp_paths(&"/a/Track 2.flac", &"/b/Track 10.flac"), Ordering::Less);
+        assert_eq!(sort_by.cmp_paths(&"/", &"/a"), Ordering::Less);
+
+        let sorter: Sorter = toml::from_str("sort_by = 'natural'").unwrap();
+        assert_eq!(sorter.sort_by, SortBy::Natural);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn natural_cmp_names_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name_a = OsStr::from_bytes(b"Track 2 \xff.flac");
+        let name_b = OsStr::from_bytes(b"Track 10 \xfe.flac");
+        let name_c = OsStr::from_bytes(b"Track 10 \xff.flac");
+
+        assert_eq!(super::natural_cmp_names(name_a, name_b), Ordering::Less);
+        assert_eq!(super::natural_cmp_names(name_b, name_c), Ordering::Less);
+        assert_eq!(super::natural_cmp_names(name_c, name_c), Ordering::Equal);
+    }
 }
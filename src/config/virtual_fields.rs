@@ -0,0 +1,379 @@
+//! Defines computed metadata fields that can be added to item blocks.
+
+use std::collections::HashMap;
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::config::{Selection, Sorter};
+use crate::fs::{DiskFs, Fs, FsMetadata};
+use crate::types::{Block, Value};
+
+const DEFAULT_NAMESPACE: &str = "_file";
+
+/// A struct that contains all of the information needed to add computed
+/// fields to the metadata block of an item file. If enabled, these fields are
+/// placed in a mapping under a reserved namespace key in the item's block, and
+/// consist of the following keys:
+///
+/// * `name`: The file name of the item file.
+/// * `stem`: The file name of the item file, without its extension.
+/// * `ext`: The extension of the item file, if any.
+/// * `size`: The size of the item file in bytes.
+/// * `mtime`: The mod time of the item file, in seconds since the Unix epoch.
+/// * `depth`: The number of path components between a root path and the item
+///   file, if a root path is known.
+/// * `index`: The zero-based position of the item file among its selected
+///   siblings, in the order given by the `Sorter`.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields)]
+pub struct VirtualFields {
+    pub enabled: bool,
+    pub namespace: String,
+}
+
+impl Default for VirtualFields {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            namespace: String::from(DEFAULT_NAMESPACE),
+        }
+    }
+}
+
+impl VirtualFields {
+    /// Adds the virtual fields for an item file to its metadata block, if
+    /// enabled. This accesses the filesystem, in order to get file info and to
+    /// find the sibling item files of the item file.
+    pub fn inject(
+        &self,
+        block: &mut Block,
+        item_path: &Path,
+        root_path: Option<&Path>,
+        selection: &Selection,
+        sorter: &Sorter,
+    ) -> IoResult<()> {
+        self.inject_in(&DiskFs, block, item_path, root_path, selection, sorter)
+    }
+
+    /// Similar to `inject`, but accesses a given filesystem.
+    pub fn inject_in(
+        &self,
+        fs: &dyn Fs,
+        block: &mut Block,
+        item_path: &Path,
+        root_path: Option<&Path>,
+        selection: &Selection,
+        sorter: &Sorter,
+    ) -> IoResult<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let index = Self::sibling_index(fs, item_path, selection, sorter)?;
+
+        self.inject_with_index(fs, block, item_path, root_path, index)
+    }
+
+    /// Similar to `inject_in`, but finds sibling indices using a cache of
+    /// directory listings. This avoids listing the same directory again for
+    /// each item file in it.
+    pub(crate) fn inject_with_siblings_in(
+        &self,
+        fs: &dyn Fs,
+        block: &mut Block,
+        item_path: &Path,
+        root_path: Option<&Path>,
+        siblings: &mut SiblingIndices<'_>,
+    ) -> IoResult<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let index = siblings.index_of(fs, item_path)?;
+
+        self.inject_with_index(fs, block, item_path, root_path, index)
+    }
+
+    /// Similar to `inject_in`, but uses an already-known sibling index.
+    pub(crate) fn inject_with_index(
+        &self,
+        fs: &dyn Fs,
+        block: &mut Block,
+        item_path: &Path,
+        root_path: Option<&Path>,
+        index: Option<usize>,
+    ) -> IoResult<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let file_info = fs.metadata(item_path)?;
+
+        self.insert_fields(block, item_path, &file_info, root_path, index);
+
+        Ok(())
+    }
+
+    /// Async version of `inject`.
+    #[cfg(feature = "async")]
+    pub async fn inject_async(
+        &self,
+        block: &mut Block,
+        item_path: &Path,
+        root_path: Option<&Path>,
+        selection: &Selection,
+        sorter: &Sorter,
+    ) -> IoResult<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let index = match item_path.parent() {
+            Some(parent_dir_path) => {
+                let mut res_paths = selection.select_in_dir_async(parent_dir_path).await?;
+                sorter.sort_path_results_async(&mut res_paths).await;
+
+                res_paths
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .position(|p| p == item_path)
+            },
+            None => None,
+        };
+
+        let file_info = tokio::fs::metadata(item_path).await?.into();
+
+        self.insert_fields(block, item_path, &file_info, root_path, index);
+
+        Ok(())
+    }
+
+    fn insert_fields(
+        &self,
+        block: &mut Block,
+        item_path: &Path,
+        file_info: &FsMetadata,
+        root_path: Option<&Path>,
+        index: Option<usize>,
+    ) {
+        let mut fields = Block::new();
+
+        let os_str_value = |o: Option<&std::ffi::OsStr>| {
+            o.map(|s| Value::String(s.to_string_lossy().into_owned()))
+        };
+
+        let entries = vec![
+            ("name", os_str_value(item_path.file_name())),
+            ("stem", os_str_value(item_path.file_stem())),
+            ("ext", os_str_value(item_path.extension())),
+            ("size", Some(Value::Integer(file_info.len as i64))),
+            (
+                "mtime",
+                file_info
+                    .modified
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| Value::Integer(d.as_secs() as i64)),
+            ),
+            (
+                "depth",
+                root_path
+                    .and_then(|r| item_path.strip_prefix(r).ok())
+                    .map(|p| Value::Integer(p.components().count() as i64)),
+            ),
+            ("index", index.map(|i| Value::Integer(i as i64))),
+        ];
+
+        for (key, opt_value) in entries {
+            if let Some(value) = opt_value {
+                fields.insert(String::from(key), value);
+            }
+        }
+
+        block.insert(self.namespace.clone(), Value::Mapping(fields));
+    }
+
+    /// Finds the position of an item file among its selected siblings.
+    fn sibling_index(fs: &dyn Fs, item_path: &Path, selection: &Selection, sorter: &Sorter) -> IoResult<Option<usize>> {
+        let parent_dir_path = match item_path.parent() {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let index = selection
+            .select_in_dir_sorted_in(fs, parent_dir_path, sorter)?
+            .into_iter()
+            .filter_map(Result::ok)
+            .position(|p| p == item_path);
+
+        Ok(index)
+    }
+}
+
+/// A cache of the positions of item files among their selected siblings,
+/// keyed by parent directory. Each directory is only listed and sorted once,
+/// and again whenever its mod time changes. If the selection or sorting of
+/// item files depends on their mod times or sizes, listings are only kept for
+/// a single batch of lookups, see `Cache::start_batch`.
+#[derive(Debug)]
+pub(crate) struct SiblingIndices<'a> {
+    selection: &'a Selection,
+    sorter: &'a Sorter,
+    dirs: HashMap<PathBuf, (Option<SystemTime>, HashMap<PathBuf, usize>)>,
+}
+
+impl<'a> SiblingIndices<'a> {
+    pub(crate) fn new(selection: &'a Selection, sorter: &'a Sorter) -> Self {
+        Self { selection, sorter, dirs: HashMap::new(), }
+    }
+
+    /// Finds the position of an item file among its selected siblings.
+    pub(crate) fn index_of(&mut self, fs: &dyn Fs, item_path: &Path) -> IoResult<Option<usize>> {
+        let parent_dir_path = match item_path.parent() {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let mtime = fs.mtime(parent_dir_path);
+
+        if let Some((cached_mtime, indices)) = self.dirs.get(parent_dir_path) {
+            if mtime.is_some() && *cached_mtime == mtime {
+                return Ok(indices.get(item_path).copied());
+            }
+        }
+
+        let indices = self.selection
+            .select_in_dir_sorted_in(fs, parent_dir_path, self.sorter)?
+            .into_iter()
+            .filter_map(Result::ok)
+            .enumerate()
+            .map(|(i, p)| (p, i))
+            .collect::<HashMap<_, _>>();
+
+        let index = indices.get(item_path).copied();
+        self.dirs.insert(parent_dir_path.to_path_buf(), (mtime, indices));
+
+        Ok(index)
+    }
+
+    /// Starts a new batch of lookups. Touching or resizing an item file does
+    /// not change the mod time of its directory, so listings that depend on
+    /// the stats of item files are dropped.
+    pub(crate) fn start_batch(&mut self) {
+        if self.selection.uses_item_stats(self.sorter) {
+            self.dirs.clear();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.dirs.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::config::sorter::SortBy;
+    use crate::fs::MemoryFs;
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn deserialization() {
+        let virtual_fields: VirtualFields = toml::from_str("enabled = true").unwrap();
+        assert!(virtual_fields.enabled);
+        assert_eq!(virtual_fields.namespace, "_file");
+
+        let virtual_fields: VirtualFields = toml::from_str("namespace = \"_virt\"").unwrap();
+        assert!(!virtual_fields.enabled);
+        assert_eq!(virtual_fields.namespace, "_virt");
+    }
+
+    #[test]
+    fn inject() {
+        let temp_dir = TU::create_temp_media_test_dir("virtual_fields_inject");
+        let path = temp_dir.path();
+
+        let selection = TU::sample_selection();
+        let sorter = Sorter::default();
+
+        let item_path = path.join("ALBUM_01").join("DISC_02").join("TRACK_02.flac");
+        std::fs::write(&item_path, "12345").unwrap();
+
+        // Nothing is added if not enabled.
+        let mut virtual_fields = VirtualFields::default();
+        let mut block = Block::new();
+        virtual_fields.inject(&mut block, &item_path, Some(path), &selection, &sorter).unwrap();
+        assert!(block.is_empty());
+
+        virtual_fields.enabled = true;
+        virtual_fields.inject(&mut block, &item_path, Some(path), &selection, &sorter).unwrap();
+
+        let fields = Value::Mapping(block);
+        let get = |key: &str| fields.get_key_path(&["_file", key]).cloned();
+
+        assert_eq!(get("name"), Some(TU::s("TRACK_02.flac")));
+        assert_eq!(get("stem"), Some(TU::s("TRACK_02")));
+        assert_eq!(get("ext"), Some(TU::s("flac")));
+        assert_eq!(get("size"), Some(TU::i(5)));
+        assert!(matches!(get("mtime"), Some(Value::Integer(_))));
+        assert_eq!(get("depth"), Some(TU::i(3)));
+        assert_eq!(get("index"), Some(TU::i(1)));
+
+        // Keys that do not apply are left out.
+        let item_path = path.join("ALBUM_01");
+        let mut block = Block::new();
+        virtual_fields.inject(&mut block, &item_path, None, &selection, &sorter).unwrap();
+
+        let fields = Value::Mapping(block);
+        let get = |key: &str| fields.get_key_path(&["_file", key]).cloned();
+
+        assert_eq!(get("name"), Some(TU::s("ALBUM_01")));
+        assert_eq!(get("ext"), None);
+        assert_eq!(get("depth"), None);
+        assert_eq!(get("index"), Some(TU::i(0)));
+    }
+
+    #[test]
+    fn sibling_indices() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/ALBUM/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM/TRACK_02.flac", "").unwrap();
+        fs.set_modified("/music/ALBUM/TRACK_01.flac", UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        fs.set_modified("/music/ALBUM/TRACK_02.flac", UNIX_EPOCH + Duration::from_secs(2)).unwrap();
+
+        let selection = TU::sample_selection();
+        let sorter = Sorter { sort_by: SortBy::ModTime, ..Default::default() };
+        let virtual_fields = VirtualFields { enabled: true, ..Default::default() };
+        let mut siblings = SiblingIndices::new(&selection, &sorter);
+
+        let item_path = Path::new("/music/ALBUM/TRACK_01.flac");
+        let index = |fs: &MemoryFs, siblings: &mut SiblingIndices<'_>| {
+            let mut block = Block::new();
+            virtual_fields.inject_with_siblings_in(fs, &mut block, item_path, None, siblings).unwrap();
+            Value::Mapping(block).get_key_path(&["_file", "index"]).cloned()
+        };
+
+        assert_eq!(index(&fs, &mut siblings), Some(TU::i(0)));
+
+        // Touching a track reorders its siblings without changing the mod time
+        // of their directory, which is only noticed in the next batch.
+        fs.set_modified(item_path, UNIX_EPOCH + Duration::from_secs(3)).unwrap();
+        assert_eq!(index(&fs, &mut siblings), Some(TU::i(0)));
+
+        siblings.start_batch();
+        assert_eq!(index(&fs, &mut siblings), Some(TU::i(1)));
+
+        // Listings that do not depend on item stats are kept across batches.
+        let sorter = Sorter::default();
+        let mut siblings = SiblingIndices::new(&selection, &sorter);
+        assert_eq!(index(&fs, &mut siblings), Some(TU::i(0)));
+        siblings.start_batch();
+        assert_eq!(siblings.dirs.len(), 1);
+    }
+}
@@ -0,0 +1,564 @@
+//! A filesystem that can look inside of zip and tar archives on disk.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Cursor, Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::fs::{DirEntries, DiskFs, EntryKind, Fs, FsMetadata, MemoryFs};
+
+/// The suffix that marks a path component as referring to the inside of an
+/// archive file, instead of the archive file itself.
+pub const ARCHIVE_MARKER: char = '!';
+
+/// Represents all the different archive formats that are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+impl ArchiveKind {
+    /// Determines the kind of an archive file from its extension, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "zip" => Some(Self::Zip),
+            "tar" => Some(Self::Tar),
+            _ => None,
+        }
+    }
+}
+
+/// Where the contents of a file entry can be read from in its archive file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    /// The index of the entry in a zip archive.
+    Zip(usize),
+    /// The offset of the entry's contents in a tar archive.
+    Tar(u64),
+}
+
+/// An entry in an archive, without its contents. Directories do not have a
+/// location.
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    info: FsMetadata,
+    location: Option<Location>,
+}
+
+/// The mod time and size of an archive file, used to detect when it changes.
+type ArchiveStamp = (Option<SystemTime>, u64);
+
+/// The entries of an archive file, keyed by absolute path inside of the
+/// archive. Only the file info of each entry is kept in memory, contents are
+/// read from the archive file when opened.
+#[derive(Debug)]
+struct ArchiveIndex {
+    stamp: ArchiveStamp,
+    entries: BTreeMap<PathBuf, IndexEntry>,
+}
+
+impl ArchiveIndex {
+    fn load(archive_path: &Path, stamp: ArchiveStamp) -> IoResult<Self> {
+        let kind = ArchiveKind::from_path(archive_path)
+            .ok_or_else(|| IoError::new(IoErrorKind::InvalidInput, "unknown archive extension"))?;
+
+        let mut index = Self { stamp, entries: BTreeMap::new(), };
+        index.insert(Path::new("/"), EntryKind::Dir, 0, stamp.0, None);
+
+        let file = File::open(archive_path)?;
+
+        match kind {
+            ArchiveKind::Zip => index.load_zip(file)?,
+            ArchiveKind::Tar => index.load_tar(file)?,
+        }
+
+        Ok(index)
+    }
+
+    fn load_zip(&mut self, file: File) -> IoResult<()> {
+        let mut archive = zip::ZipArchive::new(file).map_err(invalid_data)?;
+
+        for i in 0..archive.len() {
+            let zip_file = archive.by_index_raw(i).map_err(invalid_data)?;
+
+            // Skip any entries that would point outside of the archive.
+            let entry_path = match zip_file.enclosed_name() {
+                Some(p) => Path::new("/").join(p),
+                None => continue,
+            };
+
+            let modified = zip_modified(&zip_file);
+
+            if zip_file.is_dir() {
+                self.insert(&entry_path, EntryKind::Dir, 0, Some(modified), None);
+            } else {
+                self.insert(&entry_path, EntryKind::File, zip_file.size(), Some(modified), Some(Location::Zip(i)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_tar(&mut self, file: File) -> IoResult<()> {
+        let mut archive = tar::Archive::new(file);
+
+        for entry_res in archive.entries()? {
+            let entry = entry_res?;
+
+            // Skip any entries that would point outside of the archive.
+            let entry_path = entry.path()?.into_owned();
+            if entry_path.components().any(|c| !matches!(c, Component::Normal(..) | Component::CurDir)) {
+                continue;
+            }
+            let entry_path = Path::new("/").join(entry_path);
+
+            let entry_type = entry.header().entry_type();
+            let modified = Some(UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?));
+
+            if entry_type.is_dir() {
+                self.insert(&entry_path, EntryKind::Dir, 0, modified, None);
+            } else if entry_type.is_file() {
+                let location = Location::Tar(entry.raw_file_position());
+                self.insert(&entry_path, EntryKind::File, entry.size(), modified, Some(location));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds an entry, along with any of its missing parent directories. Parent
+    /// directories that are not in the archive use the archive's mod time.
+    fn insert(
+        &mut self,
+        path: &Path,
+        kind: EntryKind,
+        len: u64,
+        modified: Option<SystemTime>,
+        location: Option<Location>,
+    ) {
+        let path = MemoryFs::normalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        let archive_modified = self.stamp.0;
+
+        for ancestor in path.ancestors().skip(1) {
+            self.entries.entry(ancestor.to_path_buf()).or_insert_with(|| IndexEntry {
+                info: FsMetadata { kind: EntryKind::Dir, len: 0, modified: archive_modified, },
+                location: None,
+            });
+        }
+
+        self.entries.insert(path, IndexEntry { info: FsMetadata { kind, len, modified, }, location, });
+    }
+
+    fn get(&self, path: &Path) -> IoResult<(PathBuf, &IndexEntry)> {
+        let path = MemoryFs::normalize(path)?;
+
+        match self.entries.get(&path) {
+            Some(entry) => Ok((path, entry)),
+            None => Err(IoError::new(IoErrorKind::NotFound, "entry not found")),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> IoResult<Vec<PathBuf>> {
+        let (dir_path, entry) = self.get(path)?;
+
+        if !entry.info.is_dir() {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "not a directory"));
+        }
+
+        Ok(self.entries
+            .range(dir_path.clone()..)
+            .skip(1)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(&dir_path))
+            .filter(|p| p.parent() == Some(&dir_path))
+            .cloned()
+            .collect())
+    }
+
+    /// Reads the contents of a file entry from the archive file.
+    fn open(&self, archive_path: &Path, path: &Path) -> IoResult<Box<dyn Read>> {
+        let (_, entry) = self.get(path)?;
+
+        match entry.location {
+            Some(Location::Zip(i)) => {
+                let mut archive = zip::ZipArchive::new(File::open(archive_path)?).map_err(invalid_data)?;
+                let mut contents = Vec::new();
+                archive.by_index(i).map_err(invalid_data)?.read_to_end(&mut contents)?;
+
+                Ok(Box::new(Cursor::new(contents)))
+            },
+            Some(Location::Tar(offset)) => {
+                let mut file = File::open(archive_path)?;
+                file.seek(SeekFrom::Start(offset))?;
+
+                Ok(Box::new(file.take(entry.info.len)))
+            },
+            None => Err(IoError::new(IoErrorKind::InvalidInput, "is a directory")),
+        }
+    }
+}
+
+/// A filesystem that reads from the local disk, but also allows addressing
+/// the entries inside of archive files. A path component consisting of the
+/// name of an archive file followed by `!` refers to the root directory of
+/// that archive, e.g. `/music/album.zip!/DISC_01/TRACK_01.flac`. The entries
+/// of an archive are indexed the first time that it is accessed, and indexed
+/// again whenever the mod time or size of the archive file changes. Entry
+/// contents are only read from the archive file when opened. Archives nested
+/// inside of other archives are not supported.
+///
+/// Zip entries use the mod time in their extended timestamp field if they have
+/// one. Otherwise, their DOS mod time is used, which does not record a time
+/// zone. It is treated as UTC, and so may be off by the offset of the time
+/// zone that the archive was created in.
+#[derive(Debug, Default)]
+pub struct ArchiveFs {
+    archives: Mutex<HashMap<PathBuf, Arc<ArchiveIndex>>>,
+}
+
+impl ArchiveFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Splits a path into the path of the archive file it points into, the
+    /// path of the root directory of that archive, and the absolute path of
+    /// the entry inside of the archive. Returns `None` if the path does not
+    /// point inside of an archive.
+    fn split(path: &Path) -> Option<(PathBuf, PathBuf, PathBuf)> {
+        let mut archive_root_path = PathBuf::new();
+        let mut components = path.components();
+
+        for component in components.by_ref() {
+            archive_root_path.push(component);
+
+            if let Component::Normal(name) = component {
+                let archive_name = match name.to_str().and_then(|n| n.strip_suffix(ARCHIVE_MARKER)) {
+                    Some(n) => n,
+                    None => continue,
+                };
+
+                let archive_path = archive_root_path.with_file_name(archive_name);
+
+                if ArchiveKind::from_path(&archive_path).is_some() {
+                    let entry_path = Path::new("/").join(components.as_path());
+                    return Some((archive_path, archive_root_path, entry_path));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the index of an archive file, loading it if it has not been
+    /// loaded yet or if the archive file has changed since. Loading happens
+    /// without holding the lock, so that other archives can still be used.
+    fn archive(&self, archive_path: &Path) -> IoResult<Arc<ArchiveIndex>> {
+        let archive_info = DiskFs.metadata(archive_path)?;
+        let stamp = (archive_info.modified, archive_info.len);
+
+        if let Some(index) = self.lock().get(archive_path) {
+            if index.stamp == stamp {
+                return Ok(Arc::clone(index));
+            }
+        }
+
+        let index = Arc::new(ArchiveIndex::load(archive_path, stamp)?);
+        self.lock().insert(archive_path.to_path_buf(), Arc::clone(&index));
+
+        Ok(index)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<PathBuf, Arc<ArchiveIndex>>> {
+        self.archives.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Fs for ArchiveFs {
+    fn metadata(&self, path: &Path) -> IoResult<FsMetadata> {
+        match Self::split(path) {
+            Some((archive_path, _, entry_path)) => Ok(self.archive(&archive_path)?.get(&entry_path)?.1.info.clone()),
+            None => DiskFs.metadata(path),
+        }
+    }
+
+    fn read_dir<'a>(&'a self, path: &Path) -> IoResult<DirEntries<'a>> {
+        let (archive_path, archive_root_path, entry_path) = match Self::split(path) {
+            Some(t) => t,
+            None => return DiskFs.read_dir(path),
+        };
+
+        // Map the entry paths back to paths inside of the archive root.
+        let sub_paths = self
+            .archive(&archive_path)?
+            .read_dir(&entry_path)?
+            .into_iter()
+            .map(|p| match p.strip_prefix("/") {
+                Ok(rel) => archive_root_path.join(rel),
+                Err(_) => p,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Box::new(sub_paths.into_iter().map(Ok)))
+    }
+
+    fn is_symlink(&self, path: &Path) -> IoResult<bool> {
+        match Self::split(path) {
+            // Symlinks inside of archives are not indexed.
+            Some((archive_path, _, entry_path)) => self.archive(&archive_path)?.get(&entry_path).map(|_| false),
+            None => DiskFs.is_symlink(path),
+        }
+    }
+
+    fn open<'a>(&'a self, path: &Path) -> IoResult<Box<dyn Read + 'a>> {
+        match Self::split(path) {
+            Some((archive_path, _, entry_path)) => self.archive(&archive_path)?.open(&archive_path, &entry_path),
+            None => DiskFs.open(path),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> IoResult<PathBuf> {
+        match Self::split(path) {
+            Some((archive_path, _, entry_path)) => {
+                let (entry_path, _) = self.archive(&archive_path)?.get(&entry_path)?;
+                let archive_root_path = archive_root_path(&DiskFs.canonicalize(&archive_path)?);
+
+                Ok(match entry_path.strip_prefix("/") {
+                    Ok(rel) => archive_root_path.join(rel),
+                    Err(_) => archive_root_path,
+                })
+            },
+            None => DiskFs.canonicalize(path),
+        }
+    }
+}
+
+/// Returns the path of the root directory of an archive file, which can then
+/// be used with an `ArchiveFs`.
+pub fn archive_root_path(archive_path: &Path) -> PathBuf {
+    let mut name = archive_path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(ARCHIVE_MARKER.to_string());
+    archive_path.with_file_name(name)
+}
+
+fn invalid_data(err: zip::result::ZipError) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, err)
+}
+
+/// Returns the mod time of a zip entry. The extended timestamp extra field is
+/// used if present, as it is in UTC. Otherwise, the DOS mod time is used,
+/// which is in an unknown time zone, and is treated as UTC.
+fn zip_modified(zip_file: &zip::read::ZipFile<'_>) -> SystemTime {
+    extended_timestamp(zip_file.extra_data()).unwrap_or_else(|| {
+        let dt = zip_file.last_modified();
+        civil_to_system_time(
+            dt.year().into(),
+            dt.month().into(),
+            dt.day().into(),
+            u64::from(dt.hour()) * 3600 + u64::from(dt.minute()) * 60 + u64::from(dt.second()),
+        )
+    })
+}
+
+/// Finds the mod time in the extended timestamp extra field (ID `0x5455`) of
+/// a zip entry, if there is one.
+fn extended_timestamp(mut extra_data: &[u8]) -> Option<SystemTime> {
+    const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+    while extra_data.len() >= 4 {
+        let id = u16::from_le_bytes([extra_data[0], extra_data[1]]);
+        let len = usize::from(u16::from_le_bytes([extra_data[2], extra_data[3]]));
+        let data = extra_data.get(4..4 + len)?;
+
+        // The first byte holds flags, and the lowest bit marks that the mod
+        // time follows, as a signed 32-bit count of seconds.
+        if id == EXTENDED_TIMESTAMP_ID && data.first().is_some_and(|flags| flags & 1 != 0) {
+            let secs = match *data.get(1..5)? {
+                [a, b, c, d] => i32::from_le_bytes([a, b, c, d]),
+                _ => return None,
+            };
+            let offset = Duration::from_secs(u64::from(secs.unsigned_abs()));
+
+            return if secs >= 0 { UNIX_EPOCH.checked_add(offset) } else { UNIX_EPOCH.checked_sub(offset) };
+        }
+
+        extra_data = &extra_data[4 + len..];
+    }
+
+    None
+}
+
+/// Converts a calendar date and time of day (in UTC) to a `SystemTime`.
+fn civil_to_system_time(year: i64, month: i64, day: i64, secs_of_day: u64) -> SystemTime {
+    // Adapted from the "days from civil" algorithm by Howard Hinnant.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    UNIX_EPOCH + Duration::from_secs(days.max(0) as u64 * 86400 + secs_of_day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use tempfile::Builder;
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            ArchiveFs::split(Path::new("/music/album.zip!/DISC_01/TRACK_01.flac")),
+            Some((
+                PathBuf::from("/music/album.zip"),
+                PathBuf::from("/music/album.zip!"),
+                PathBuf::from("/DISC_01/TRACK_01.flac"),
+            )),
+        );
+        assert_eq!(
+            ArchiveFs::split(Path::new("/music/album.tar!")),
+            Some((PathBuf::from("/music/album.tar"), PathBuf::from("/music/album.tar!"), PathBuf::from("/"))),
+        );
+        assert_eq!(ArchiveFs::split(Path::new("/music/album.zip/DISC_01")), None);
+        assert_eq!(ArchiveFs::split(Path::new("/music/album.rar!/DISC_01")), None);
+        assert_eq!(ArchiveFs::split(Path::new("/music/album!/DISC_01")), None);
+
+        assert_eq!(archive_root_path(Path::new("/music/album.zip")), PathBuf::from("/music/album.zip!"));
+    }
+
+    #[test]
+    fn civil_time() {
+        assert_eq!(super::civil_to_system_time(1970, 1, 1, 0), UNIX_EPOCH);
+        assert_eq!(
+            super::civil_to_system_time(2000, 3, 1, 61),
+            UNIX_EPOCH + Duration::from_secs(951_868_800 + 61),
+        );
+    }
+
+    #[test]
+    fn extended_timestamp() {
+        // An unrelated field, followed by an extended timestamp field with
+        // both mod and access times.
+        let extra_data = [
+            0x0A, 0x00, 0x02, 0x00, 0xFF, 0xFF,
+            0x55, 0x54, 0x09, 0x00, 0x03, 0x40, 0x42, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            super::extended_timestamp(&extra_data),
+            Some(UNIX_EPOCH + Duration::from_secs(1_000_000)),
+        );
+
+        // Fields without the mod time flag are ignored.
+        assert_eq!(super::extended_timestamp(&[0x55, 0x54, 0x05, 0x00, 0x02, 0x40, 0x42, 0x0F, 0x00]), None);
+
+        // Truncated fields are ignored.
+        assert_eq!(super::extended_timestamp(&[0x55, 0x54, 0x05, 0x00, 0x01, 0x40]), None);
+        assert_eq!(super::extended_timestamp(&[]), None);
+    }
+
+    fn write_tar(tar_path: &Path, entries: &[(&str, &str)]) {
+        let mut tar_builder = tar::Builder::new(File::create(tar_path).unwrap());
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mtime(1_000_000);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar_builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+        }
+        tar_builder.finish().unwrap();
+    }
+
+    #[test]
+    fn archive_fs_reload() {
+        let temp_dir = Builder::new().suffix("archive_fs_reload").tempdir().unwrap();
+        let tar_path = temp_dir.path().join("album.tar");
+        let entry_path = archive_root_path(&tar_path).join("TRACK_01.flac");
+
+        let fs = ArchiveFs::new();
+
+        write_tar(&tar_path, &[("TRACK_01.flac", "1")]);
+        let file_info = fs.metadata(&entry_path).unwrap();
+        assert_eq!(file_info.len, 1);
+        assert_eq!(file_info.modified, Some(UNIX_EPOCH + Duration::from_secs(1_000_000)));
+
+        // Changing the archive file causes it to be indexed again.
+        write_tar(&tar_path, &[("TRACK_01.flac", "22"), ("TRACK_02.flac", "333")]);
+        assert_eq!(fs.metadata(&entry_path).unwrap().len, 2);
+        assert!(fs.metadata(&entry_path.with_file_name("TRACK_02.flac")).unwrap().is_file());
+
+        let mut contents = String::new();
+        fs.open(&entry_path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "22");
+    }
+
+    #[test]
+    fn archive_fs() {
+        let temp_dir = Builder::new().suffix("archive_fs").tempdir().unwrap();
+        let path = temp_dir.path();
+
+        let entries = &[
+            ("DISC_01/TRACK_01.flac", "1"),
+            ("DISC_01/TRACK_02.flac", "22"),
+            ("DISC_02/TRACK_01.flac", "333"),
+        ];
+
+        let zip_path = path.join("album.zip");
+        let mut zip_writer = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for (name, contents) in entries {
+            zip_writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip_writer.write_all(contents.as_bytes()).unwrap();
+        }
+        zip_writer.finish().unwrap();
+
+        let tar_path = path.join("album.tar");
+        write_tar(&tar_path, entries);
+
+        let fs = ArchiveFs::new();
+
+        for archive_path in &[zip_path, tar_path] {
+            let root_path = archive_root_path(archive_path);
+
+            // The archive file itself is still a file.
+            assert!(fs.metadata(archive_path).unwrap().is_file());
+            assert!(fs.metadata(&root_path).unwrap().is_dir());
+            assert!(fs.metadata(&root_path.join("DISC_01")).unwrap().is_dir());
+
+            let file_info = fs.metadata(&root_path.join("DISC_01").join("TRACK_02.flac")).unwrap();
+            assert!(file_info.is_file());
+            assert_eq!(file_info.len, 2);
+
+            let err = fs.metadata(&root_path.join("DISC_03")).unwrap_err();
+            assert_eq!(err.kind(), IoErrorKind::NotFound);
+
+            let mut produced = fs
+                .read_dir(&root_path)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>();
+            produced.sort();
+            assert_eq!(produced, vec![root_path.join("DISC_01"), root_path.join("DISC_02")]);
+
+            let mut contents = String::new();
+            fs.open(&root_path.join("DISC_02").join("TRACK_01.flac"))
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "333");
+        }
+
+        // Paths outside of archives go to the disk.
+        let mut produced = fs.read_dir(path).unwrap().map(Result::unwrap).collect::<Vec<_>>();
+        produced.sort();
+        assert_eq!(produced, vec![path.join("album.tar"), path.join("album.zip")]);
+    }
+}
@@ -0,0 +1,241 @@
+//! An in-memory filesystem, useful for synthesizing trees of item and meta files.
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::fs::{DirEntries, EntryKind, Fs, FsMetadata};
+
+#[derive(Debug, Clone)]
+struct Entry {
+    contents: Option<Vec<u8>>,
+    modified: SystemTime,
+}
+
+/// A filesystem that lives entirely in memory. Entries are keyed by absolute
+/// path, and parent directories are created as needed when adding entries.
+/// Mod times are assigned in order of creation, starting at the Unix epoch and
+/// increasing by one second each time, so that sorting by mod time is
+/// deterministic.
+#[derive(Debug, Clone)]
+pub struct MemoryFs {
+    entries: BTreeMap<PathBuf, Entry>,
+    next_tick: u64,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFs {
+    /// Creates a new `MemoryFs` containing just the filesystem root directory.
+    pub fn new() -> Self {
+        let mut entries = BTreeMap::new();
+        entries.insert(PathBuf::from("/"), Entry { contents: None, modified: UNIX_EPOCH });
+
+        Self { entries, next_tick: 1, }
+    }
+
+    fn tick(&mut self) -> SystemTime {
+        let modified = UNIX_EPOCH + Duration::from_secs(self.next_tick);
+        self.next_tick += 1;
+        modified
+    }
+
+    /// Makes an absolute path lexically normal, by resolving any `.` and `..`
+    /// components.
+    pub(crate) fn normalize(path: &Path) -> IoResult<PathBuf> {
+        if !path.is_absolute() {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "path is not absolute"));
+        }
+
+        let mut normalized = PathBuf::new();
+
+        for component in path.components() {
+            match component {
+                Component::Prefix(..) | Component::RootDir | Component::Normal(..) => {
+                    normalized.push(component);
+                },
+                Component::CurDir => {},
+                Component::ParentDir => { normalized.pop(); },
+            }
+        }
+
+        Ok(normalized)
+    }
+
+    fn insert(&mut self, path: &Path, contents: Option<Vec<u8>>) -> IoResult<&mut Self> {
+        let path = Self::normalize(path)?;
+
+        // Ensure that all ancestors exist as directories.
+        let ancestors = path.ancestors().skip(1).collect::<Vec<_>>();
+        for ancestor in ancestors.into_iter().rev() {
+            match self.entries.get(ancestor) {
+                Some(Entry { contents: None, .. }) => {},
+                Some(Entry { contents: Some(..), .. }) => {
+                    return Err(IoError::new(IoErrorKind::InvalidInput, "ancestor is not a directory"));
+                },
+                None => {
+                    let modified = self.tick();
+                    self.entries.insert(ancestor.to_path_buf(), Entry { contents: None, modified });
+                },
+            }
+        }
+
+        if let Some(existing) = self.entries.get(&path) {
+            if existing.contents.is_some() != contents.is_some() {
+                return Err(IoError::new(IoErrorKind::InvalidInput, "path already exists as a different kind"));
+            }
+        }
+
+        let modified = self.tick();
+        self.entries.insert(path, Entry { contents, modified });
+
+        Ok(self)
+    }
+
+    /// Adds a file with the given contents, creating any missing parent
+    /// directories. If the file already exists, its contents are replaced.
+    pub fn add_file<P, C>(&mut self, path: P, contents: C) -> IoResult<&mut Self>
+    where
+        P: AsRef<Path>,
+        C: Into<Vec<u8>>,
+    {
+        self.insert(path.as_ref(), Some(contents.into()))
+    }
+
+    /// Adds an empty directory, creating any missing parent directories.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> IoResult<&mut Self> {
+        self.insert(path.as_ref(), None)
+    }
+
+    /// Overrides the mod time of an existing entry.
+    pub fn set_modified<P: AsRef<Path>>(&mut self, path: P, modified: SystemTime) -> IoResult<()> {
+        let path = Self::normalize(path.as_ref())?;
+
+        match self.entries.get_mut(&path) {
+            Some(entry) => {
+                entry.modified = modified;
+                Ok(())
+            },
+            None => Err(IoError::new(IoErrorKind::NotFound, "entry not found")),
+        }
+    }
+
+    fn get(&self, path: &Path) -> IoResult<(PathBuf, &Entry)> {
+        let path = Self::normalize(path)?;
+
+        match self.entries.get(&path) {
+            Some(entry) => Ok((path, entry)),
+            None => Err(IoError::new(IoErrorKind::NotFound, "entry not found")),
+        }
+    }
+}
+
+impl Fs for MemoryFs {
+    fn metadata(&self, path: &Path) -> IoResult<FsMetadata> {
+        let (_, entry) = self.get(path)?;
+
+        let (kind, len) = match &entry.contents {
+            Some(contents) => (EntryKind::File, contents.len() as u64),
+            None => (EntryKind::Dir, 0),
+        };
+
+        Ok(FsMetadata { kind, len, modified: Some(entry.modified), })
+    }
+
+    fn read_dir<'a>(&'a self, path: &Path) -> IoResult<DirEntries<'a>> {
+        let (dir_path, entry) = self.get(path)?;
+
+        if entry.contents.is_some() {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "not a directory"));
+        }
+
+        let child_paths = self.entries
+            .range(dir_path.clone()..)
+            .skip(1)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(&dir_path))
+            .filter(|p| p.parent() == Some(&dir_path))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Ok(Box::new(child_paths.into_iter().map(Ok)))
+    }
+
+    fn open<'a>(&'a self, path: &Path) -> IoResult<Box<dyn Read + 'a>> {
+        let (_, entry) = self.get(path)?;
+
+        match &entry.contents {
+            Some(contents) => Ok(Box::new(Cursor::new(contents.as_slice()))),
+            None => Err(IoError::new(IoErrorKind::InvalidInput, "is a directory")),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> IoResult<PathBuf> {
+        // There are no symlinks, so this only needs to check for existence.
+        self.get(path).map(|(path, _)| path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_fs() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/ALBUM/TRACK_02.flac", "22").unwrap()
+            .add_file("/music/ALBUM/TRACK_01.flac", "1").unwrap()
+            .add_dir("/music/ALBUM/EMPTY").unwrap()
+            .add_file("/music/ALBUM_2/TRACK_01.flac", "").unwrap();
+
+        // Parent directories are created automatically.
+        assert!(fs.metadata(Path::new("/music")).unwrap().is_dir());
+        assert!(fs.metadata(Path::new("/music/ALBUM")).unwrap().is_dir());
+        assert!(fs.metadata(Path::new("/music/ALBUM/EMPTY")).unwrap().is_dir());
+
+        let file_info = fs.metadata(Path::new("/music/ALBUM/TRACK_02.flac")).unwrap();
+        assert!(file_info.is_file());
+        assert_eq!(file_info.len, 2);
+
+        // Mod times follow creation order.
+        assert!(
+            fs.mtime(Path::new("/music/ALBUM/TRACK_02.flac"))
+            < fs.mtime(Path::new("/music/ALBUM/TRACK_01.flac"))
+        );
+
+        let err = fs.metadata(Path::new("/music/ALBUM/TRACK_03.flac")).unwrap_err();
+        assert_eq!(err.kind(), IoErrorKind::NotFound);
+
+        // Only direct children are listed.
+        let produced = fs
+            .read_dir(Path::new("/music/ALBUM"))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let expected = vec![
+            PathBuf::from("/music/ALBUM/EMPTY"),
+            PathBuf::from("/music/ALBUM/TRACK_01.flac"),
+            PathBuf::from("/music/ALBUM/TRACK_02.flac"),
+        ];
+        assert_eq!(produced, expected);
+
+        assert!(fs.read_dir(Path::new("/music/ALBUM/TRACK_01.flac")).is_err());
+
+        let mut contents = String::new();
+        fs.open(Path::new("/music/ALBUM/TRACK_02.flac")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "22");
+
+        assert!(fs.open(Path::new("/music/ALBUM")).is_err());
+
+        // Files and directories cannot be swapped out for each other.
+        assert!(fs.add_file("/music/ALBUM", "").is_err());
+        assert!(fs.add_dir("/music/ALBUM/TRACK_01.flac").is_err());
+        assert!(fs.add_file("/music/ALBUM/TRACK_01.flac/nested", "").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Abstractions over the filesystem that item and meta files are read from.
+
+#[cfg(feature = "archive")] pub mod archive;
+pub mod memory;
+
+#[cfg(feature = "archive")] pub use self::archive::ArchiveFs;
+pub use self::memory::MemoryFs;
+
+use std::fmt::Debug;
+use std::fs::Metadata;
+use std::io::{Read, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// An iterator over the paths of the entries of a directory.
+pub type DirEntries<'a> = Box<dyn Iterator<Item = IoResult<PathBuf>> + 'a>;
+
+/// The kinds of entries that can be found in a filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Other,
+}
+
+/// Information about an entry in a filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsMetadata {
+    pub kind: EntryKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FsMetadata {
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+}
+
+impl From<Metadata> for FsMetadata {
+    fn from(value: Metadata) -> Self {
+        let kind =
+            if value.is_file() { EntryKind::File }
+            else if value.is_dir() { EntryKind::Dir }
+            else { EntryKind::Other };
+
+        Self { kind, len: value.len(), modified: value.modified().ok(), }
+    }
+}
+
+/// A filesystem that item files and meta files can be read from. All paths
+/// passed into a filesystem are expected to be absolute.
+pub trait Fs: Debug + Send + Sync {
+    /// Returns information about the entry at a path, following symlinks.
+    fn metadata(&self, path: &Path) -> IoResult<FsMetadata>;
+
+    /// Returns the paths of the entries of a directory, in no particular order.
+    fn read_dir<'a>(&'a self, path: &Path) -> IoResult<DirEntries<'a>>;
+
+    /// Returns true if the entry at a path is a symlink. Filesystems that do
+    /// not support symlinks only check that the path exists.
+    fn is_symlink(&self, path: &Path) -> IoResult<bool> {
+        self.metadata(path).map(|_| false)
+    }
+
+    /// Opens a file for reading.
+    fn open<'a>(&'a self, path: &Path) -> IoResult<Box<dyn Read + 'a>>;
+
+    /// Returns the absolute form of a path, with all intermediate components
+    /// normalized and symlinks resolved. The path needs to exist.
+    fn canonicalize(&self, path: &Path) -> IoResult<PathBuf>;
+
+    /// Returns the directory that relative paths are resolved against, if
+    /// this filesystem has one. Only the local disk has a working directory.
+    fn current_dir(&self) -> IoResult<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Convenience method that gets the mod time of a path.
+    /// Errors are coerced to `None`.
+    fn mtime(&self, path: &Path) -> Option<SystemTime> {
+        self.metadata(path).ok().and_then(|m| m.modified)
+    }
+}
+
+/// The filesystem of the local disk, using `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskFs;
+
+impl Fs for DiskFs {
+    fn metadata(&self, path: &Path) -> IoResult<FsMetadata> {
+        std::fs::metadata(path).map(Into::into)
+    }
+
+    fn read_dir<'a>(&'a self, path: &Path) -> IoResult<DirEntries<'a>> {
+        let read_dir = std::fs::read_dir(path)?;
+        Ok(Box::new(read_dir.map(|res| res.map(|e| e.path()))))
+    }
+
+    fn is_symlink(&self, path: &Path) -> IoResult<bool> {
+        std::fs::symlink_metadata(path).map(|m| m.file_type().is_symlink())
+    }
+
+    fn open<'a>(&'a self, path: &Path) -> IoResult<Box<dyn Read + 'a>> {
+        Ok(Box::new(std::fs::File::open(path)?))
+    }
+
+    fn canonicalize(&self, path: &Path) -> IoResult<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+
+    fn current_dir(&self) -> IoResult<Option<PathBuf>> {
+        std::env::current_dir().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+
+    use tempfile::Builder;
+
+    #[test]
+    // NOTE: Using `SystemTime` is not guaranteed to be monotonic, so this test might be fragile.
+    fn mtime() {
+        // Create temp directory.
+        let temp = Builder::new().suffix("mtime").tempdir().unwrap();
+        let tp = temp.path();
+
+        let time_a = SystemTime::now();
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        // Create a file to get the mtime of.
+        let path = tp.join("file");
+        File::create(&path).unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        let time_b = SystemTime::now();
+
+        let file_time = DiskFs.mtime(&path).unwrap();
+
+        assert!(time_a < file_time);
+        assert!(file_time < time_b);
+
+        // Test getting time of nonexistent file.
+        assert_eq!(None, DiskFs.mtime(&tp.join("DOES_NOT_EXIST")));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod config;
+pub mod fs;
+pub mod library;
 pub mod metadata;
 pub mod sources;
 pub mod types;
@@ -12,6 +14,7 @@ use crate::config::Config;
 use crate::metadata::processor::Processor;
 use crate::types::Block;
 
+pub use crate::library::Library;
 pub use crate::util::FileWalker;
 
 pub fn get<P: AsRef<Path>>(path: &P) -> Block {
@@ -20,10 +23,5 @@ pub fn get<P: AsRef<Path>>(path: &P) -> Block {
 }
 
 pub fn get_with_config<P: AsRef<Path>>(path: &P, config: &Config) -> Block {
-    Processor::process_item_file(
-        path.as_ref(),
-        &config.sourcer,
-        &config.selection,
-        &config.sorter,
-    ).unwrap()
+    Processor::process_item_file_with_config(path.as_ref(), config).unwrap()
 }
@@ -0,0 +1,539 @@
+//! A handle to a library of item files, anchored at a root directory.
+
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::config::merger::Directives;
+use crate::fs::{DiskFs, Fs};
+use crate::metadata::processor::{Error as ProcessorError, ProcessTree, Processor};
+use crate::sources::Normalization;
+use crate::types::Block;
+use crate::util::file_walker::{ChildFileWalker, ParentFileWalker};
+use crate::util::FileWalker;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("item path is outside of library root: {}", .0.display())]
+    OutsideRoot(PathBuf),
+    #[error(r#"cannot resolve item path "{}": {1}"#, .0.display())]
+    Resolve(PathBuf, #[source] IoError),
+    #[error(r#"cannot process item file "{}": {1}"#, .0.display())]
+    Process(PathBuf, #[source] ProcessorError),
+    #[error("cannot walk library: {0}")]
+    Walk(#[source] ProcessorError),
+}
+
+/// A collection of item files inside of a root directory, along with the
+/// configuration used to process them. Item paths passed into a library may
+/// be either absolute, or relative to the library root. Item paths produced by
+/// a library, in both results and errors, are always relative to the root.
+/// This allows results to be portable between machines that have a library
+/// located at different paths. Both the library root and item paths passed
+/// into a library are normalized using the normalization of the `Sourcer`.
+#[derive(Debug)]
+pub struct Library<'f> {
+    fs: &'f dyn Fs,
+    root: PathBuf,
+    config: Config,
+}
+
+impl Library<'static> {
+    pub fn new<P: Into<PathBuf>>(root: P, config: Config) -> Self {
+        Self::new_in(&DiskFs, root, config)
+    }
+}
+
+impl<'f> Library<'f> {
+    /// Similar to `new`, but accesses a given filesystem.
+    pub fn new_in<P: Into<PathBuf>>(fs: &'f dyn Fs, root: P, mut config: Config) -> Self {
+        let root = root.into();
+        let root = config.sourcer.normalize_in(fs, &root).into_owned();
+
+        // Patterns can be matched relative to the library root, and root meta
+        // files are not searched for above it.
+        config.selection.set_root(&root);
+        config.sourcer.set_root(&root);
+
+        Self { fs, root, config, }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Converts an item path into an absolute path inside of this library.
+    /// Relative paths are treated as being relative to the library root.
+    /// `.` and `..` components are always resolved before checking that the
+    /// item path is inside of the root, regardless of the normalization of
+    /// the `Sourcer`.
+    pub fn resolve(&self, item_path: &Path) -> Result<PathBuf, Error> {
+        let abs_item_path = self.root.join(item_path);
+        let abs_item_path = self.config.sourcer.normalize_in(self.fs, &abs_item_path);
+
+        let lexical = |path| {
+            Normalization::Lexical
+                .normalize_in(self.fs, path)
+                .map_err(|err| Error::Resolve(item_path.into(), err))
+        };
+
+        let lex_root = lexical(&self.root)?;
+        let lex_item_path = lexical(&abs_item_path)?;
+
+        match lex_item_path.strip_prefix(&lex_root) {
+            Ok(rel_item_path) if rel_item_path.as_os_str().is_empty() => Ok(self.root.clone()),
+            Ok(rel_item_path) => Ok(self.root.join(rel_item_path)),
+            Err(_) => Err(Error::OutsideRoot(item_path.into())),
+        }
+    }
+
+    /// Converts an item path into a path relative to the library root.
+    pub fn relativize<'a>(&self, item_path: &'a Path) -> Result<&'a Path, Error> {
+        item_path
+            .strip_prefix(&self.root)
+            .map_err(|_| Error::OutsideRoot(item_path.into()))
+    }
+
+    /// Processes metadata for a target item file in this library.
+    /// Meta files are found in the same way as by `process_tree`: meta files
+    /// from overlay sources are always used, while other meta files located
+    /// outside of the library root are ignored. In particular, this means
+    /// that the root item itself does not receive any metadata from meta
+    /// files in the parent directory of the root. If enabled, the `depth`
+    /// virtual field is relative to the library root.
+    pub fn process_item_file(&self, item_path: &Path) -> Result<Block, Error> {
+        self.process_item_file_with_provenance(item_path).map(|(block, _)| block)
+    }
+
+    /// Similar to `process_item_file`, but also returns the merge directives
+    /// of the item file, see `Processor::process_item_file_with_provenance`.
+    pub fn process_item_file_with_provenance(&self, item_path: &Path) -> Result<(Block, Directives), Error> {
+        let abs_item_path = self.resolve(item_path)?;
+        let rel_item_path = self.relativize(&abs_item_path)?.to_path_buf();
+
+        Processor::process_item_file_with_provenance_in(self.fs, &abs_item_path, &self.config)
+            .map_err(|err| Error::Process(rel_item_path, err))
+    }
+
+    /// Converts a walked item path into a path relative to the library root.
+    /// All walked item paths are inside of the library root.
+    fn relativize_walked(&self, item_path: &Path) -> PathBuf {
+        self.relativize(item_path).map(Path::to_path_buf).unwrap_or_else(|_| item_path.into())
+    }
+
+    /// Processes metadata for all selected item files in this library.
+    /// The library root itself is not included.
+    pub fn process_tree(&self) -> LibraryTree<'_> {
+        let config = &self.config;
+
+        let inner = Processor::process_subtrees_in(
+            self.fs,
+            &self.root,
+            &config.sourcer,
+            &config.selection,
+            &config.sorter,
+            &config.merger,
+            &config.virtual_fields,
+        );
+
+        LibraryTree { library: self, inner, }
+    }
+
+    /// Walks all selected item files in this library, without their metadata.
+    /// The library root itself is not included. If the selection has meta
+    /// filters, each item file is processed in the same way as by
+    /// `process_tree`, and only visited if its metadata passes the filters.
+    /// Directories excluded by their metadata are not walked into. Without
+    /// meta filters, no metadata is processed.
+    pub fn walk(&self) -> LibraryWalk<'_> {
+        let config = &self.config;
+
+        let inner = if config.selection.meta_filters().is_empty() {
+            let mut walker = ChildFileWalker::new(&self.root);
+
+            // Visit the root item path, and queue up its children.
+            walker.next();
+            let pending_err = walker.delve_in(self.fs, &config.selection, &config.sorter).err();
+
+            LibraryWalkInner::Files { walker, pending_err, }
+        }
+        else {
+            LibraryWalkInner::Processed(Box::new(self.process_tree()))
+        };
+
+        LibraryWalk { library: self, inner, }
+    }
+
+    /// Returns a file walker that visits the ancestors of an item file, up to
+    /// and including the library root. The item path must be absolute.
+    pub fn ancestors<'a>(&'a self, item_path: &'a Path) -> Result<FileWalker<'a>, Error> {
+        self.relativize(item_path)?;
+        Ok(ParentFileWalker::with_root(item_path, &self.root).into())
+    }
+}
+
+/// An iterator over the item files in a library and their metadata.
+/// Created by `Library::process_tree`.
+pub struct LibraryTree<'a> {
+    library: &'a Library<'a>,
+    inner: ProcessTree<'a>,
+}
+
+impl<'a> Iterator for LibraryTree<'a> {
+    type Item = Result<(PathBuf, Block), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.inner.next()?;
+        let library = self.library;
+
+        Some(match res {
+            Ok((item_path, block)) => Ok((library.relativize_walked(&item_path), block)),
+            Err(ProcessorError::CannotProcessItem(item_path, err)) => {
+                Err(Error::Process(library.relativize_walked(&item_path), *err))
+            },
+            Err(err) => Err(Error::Walk(err)),
+        })
+    }
+}
+
+/// An iterator over the item files in a library, relative to the library root.
+/// Created by `Library::walk`.
+pub struct LibraryWalk<'a> {
+    library: &'a Library<'a>,
+    inner: LibraryWalkInner<'a>,
+}
+
+enum LibraryWalkInner<'a> {
+    // Walks item files without processing them, used without meta filters.
+    Files { walker: ChildFileWalker<'a>, pending_err: Option<IoError>, },
+    // Processes item files, so that meta filters can be evaluated.
+    Processed(Box<LibraryTree<'a>>),
+}
+
+impl<'a> Iterator for LibraryWalk<'a> {
+    type Item = Result<PathBuf, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let config = &self.library.config;
+
+        match &mut self.inner {
+            LibraryWalkInner::Files { walker, pending_err } => {
+                if let Some(err) = pending_err.take() {
+                    return Some(Err(Error::Walk(ProcessorError::CannotWalkTree(err))));
+                }
+
+                let item_path = match walker.next()? {
+                    Ok(item_path) => item_path,
+                    Err(err) => return Some(Err(Error::Walk(ProcessorError::CannotWalkTree(err)))),
+                };
+
+                // Queue up the children of this item file, if it is a directory.
+                *pending_err = walker.delve_in(self.library.fs, &config.selection, &config.sorter).err();
+
+                Some(Ok(self.library.relativize_walked(&item_path)))
+            },
+            LibraryWalkInner::Processed(tree) => Some(tree.next()?.map(|(item_path, _)| item_path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::config::Selection;
+    use crate::config::selection::{Matcher, MetaFilters, PatternScope};
+    use crate::fs::MemoryFs;
+    use crate::sources::{Anchor, Overlay, Source, Sourcer};
+    use crate::types::Value;
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn process_item_file() {
+        let temp_dir = TU::create_temp_media_test_dir("library_process_item_file");
+        let path = temp_dir.path();
+
+        let library = Library::new(path.join("ALBUM_01"), TU::sample_config());
+
+        let rel_item_path = Path::new("DISC_01").join("TRACK_01.flac");
+        let expected = Block(btreemap![
+            str!("TRACK_01_item_key") => TU::s("TRACK_01_item_val"),
+            str!("const_key") => TU::s("const_val"),
+            str!("item_key") => TU::s("item_val"),
+            str!("overridden") => TU::s("TRACK_01_item"),
+        ]);
+
+        assert_eq!(library.process_item_file(&rel_item_path).unwrap(), expected);
+        assert_eq!(library.process_item_file(&library.root().join(&rel_item_path)).unwrap(), expected);
+
+        // The root item does not get metadata from outside of the library.
+        let expected = Block(btreemap![
+            str!("ALBUM_01_self_key") => TU::s("ALBUM_01_self_val"),
+            str!("const_key") => TU::s("const_val"),
+            str!("self_key") => TU::s("self_val"),
+            str!("overridden") => TU::s("ALBUM_01_self"),
+        ]);
+        assert_eq!(library.process_item_file(Path::new("")).unwrap(), expected);
+
+        // Item paths outside of the library are rejected.
+        assert!(matches!(
+            library.process_item_file(&path.join("ALBUM_02")),
+            Err(Error::OutsideRoot(..)),
+        ));
+        assert!(matches!(
+            library.process_item_file(&Path::new("..").join("ALBUM_02")),
+            Err(Error::OutsideRoot(..)),
+        ));
+        assert_eq!(
+            library.resolve(&Path::new("DISC_01").join("..").join("DISC_02")).unwrap(),
+            library.root().join("DISC_02"),
+        );
+
+        // Errors use root-relative item paths.
+        match library.process_item_file(Path::new("DISC_01").join("TRACK_XX.flac").as_path()) {
+            Err(Error::Process(p, _)) => assert_eq!(p, Path::new("DISC_01").join("TRACK_XX.flac")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn process_tree() {
+        let temp_dir = TU::create_temp_media_test_dir("library_process_tree");
+        let path = temp_dir.path();
+
+        let library = Library::new(path.join("ALBUM_03").join("DISC_02"), TU::sample_config());
+
+        let produced = library
+            .process_tree()
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            Path::new("TRACK_01").to_path_buf(),
+            Path::new("TRACK_01").join("SUBTRACK_01.flac"),
+            Path::new("TRACK_01").join("SUBTRACK_02.flac"),
+            Path::new("TRACK_02").to_path_buf(),
+            Path::new("TRACK_02").join("SUBTRACK_01.flac"),
+            Path::new("TRACK_02").join("SUBTRACK_02.flac"),
+            Path::new("TRACK_03.flac").to_path_buf(),
+            Path::new("TRACK_04.flac").to_path_buf(),
+        ];
+        assert_eq!(expected, produced);
+
+        // Patterns can be matched relative to the library root.
+        let mut config = TU::sample_config();
+        config.selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["**/*.json", "TRACK_01/*.flac"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        )
+        .with_scope(PatternScope::Root);
+
+        let library = Library::new(path.join("ALBUM_03").join("DISC_02"), config);
+
+        let produced = library
+            .process_tree()
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+
+        let expected = expected
+            .into_iter()
+            .filter(|p| !p.starts_with("TRACK_01") || p == Path::new("TRACK_01"))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn walk() {
+        let temp_dir = TU::create_temp_media_test_dir("library_walk");
+        let path = temp_dir.path();
+        let root_path = path.join("ALBUM_03").join("DISC_02");
+
+        let walk = |library: &Library| library.walk().map(Result::unwrap).collect::<Vec<_>>();
+        let process_tree = |library: &Library| {
+            library.process_tree().map(|res| res.unwrap().0).collect::<Vec<_>>()
+        };
+
+        let library = Library::new(&root_path, TU::sample_config());
+
+        let expected = vec![
+            Path::new("TRACK_01").to_path_buf(),
+            Path::new("TRACK_01").join("SUBTRACK_01.flac"),
+            Path::new("TRACK_01").join("SUBTRACK_02.flac"),
+            Path::new("TRACK_02").to_path_buf(),
+            Path::new("TRACK_02").join("SUBTRACK_01.flac"),
+            Path::new("TRACK_02").join("SUBTRACK_02.flac"),
+            Path::new("TRACK_03.flac").to_path_buf(),
+            Path::new("TRACK_04.flac").to_path_buf(),
+        ];
+        assert_eq!(walk(&library), expected);
+
+        // Excluded directories are not walked into, and items need to pass
+        // the include filters to be visited.
+        let mut config = TU::sample_config();
+        config.selection = config.selection.with_meta_filters(MetaFilters::from_reprs(
+            btreemap![str!("item_key") => TU::s("item_val")],
+            btreemap![str!("overridden") => TU::s("TRACK_01_self")],
+        ));
+
+        let library = Library::new(&root_path, config);
+
+        let expected = vec![
+            Path::new("TRACK_02").to_path_buf(),
+            Path::new("TRACK_02").join("SUBTRACK_01.flac"),
+            Path::new("TRACK_02").join("SUBTRACK_02.flac"),
+            Path::new("TRACK_03.flac").to_path_buf(),
+            Path::new("TRACK_04.flac").to_path_buf(),
+        ];
+        assert_eq!(walk(&library), expected);
+        assert_eq!(walk(&library), process_tree(&library));
+
+        // Walk errors are reported.
+        let library = Library::new(path.join("MISSING"), TU::sample_config());
+        assert!(matches!(library.walk().next(), Some(Err(Error::Walk(..)))));
+    }
+
+    #[test]
+    fn root_sources() {
+        let temp_dir = TU::create_temp_media_test_dir("library_root_sources");
+        let path = temp_dir.path();
+
+        let mut config = TU::sample_config();
+        config.sourcer = Sourcer::new();
+        config.sourcer.source(Source::from_name(str!("library.json"), Anchor::Root).unwrap());
+
+        // Root meta files above the library root are not searched for.
+        std::fs::write(
+            path.join("library.json"),
+            r#"{"ALBUM_01/DISC_01/TRACK_01.flac": {"lib_key": "outside_val"}}"#,
+        ).unwrap();
+
+        let library = Library::new(path.join("ALBUM_01"), config);
+
+        let produced = library
+            .process_tree()
+            .map(|res| res.unwrap())
+            .find(|(p, _)| p == &Path::new("DISC_01").join("TRACK_01.flac"))
+            .unwrap();
+        assert_eq!(produced.1, Block::new());
+
+        let source = &library.config().sourcer.as_sources()[0];
+        assert_eq!(source.root(), Some(library.root()));
+        assert!(source.meta_path(&library.root().join("DISC_01").join("TRACK_01.flac")).is_err());
+
+        // Root meta files inside of the library root are used.
+        std::fs::write(
+            library.root().join("library.json"),
+            r#"{"DISC_01/TRACK_01.flac": {"lib_key": "inside_val"}}"#,
+        ).unwrap();
+
+        let produced = library.process_item_file(&Path::new("DISC_01").join("TRACK_01.flac")).unwrap();
+        assert_eq!(produced, Block(btreemap![str!("lib_key") => TU::s("inside_val")]));
+    }
+
+    #[test]
+    fn overlay_sources() {
+        let temp_dir = TU::create_temp_media_test_dir("library_overlay_sources");
+        let path = temp_dir.path();
+        let overlay_dir = tempfile::Builder::new().tempdir().unwrap();
+        let overlay_path = overlay_dir.path();
+
+        let root = path.join("ALBUM_01");
+
+        let mut config = TU::sample_config();
+        let overlay = Overlay::new(&root, overlay_path);
+        config.sourcer
+            .source(Source::from_name(str!("album.yml"), Anchor::Internal).unwrap().with_overlay(overlay.clone()))
+            .source(Source::from_name(str!("track.yml"), Anchor::External).unwrap().with_overlay(overlay));
+
+        std::fs::create_dir(overlay_path.join("DISC_01")).unwrap();
+        std::fs::write(overlay_path.join("album.yml"), "overlay_key: album").unwrap();
+        std::fs::write(overlay_path.join("DISC_01").join("track.yml"), "{TRACK_01.flac: {overlay_key: track}, TRACK_02.flac: {}, TRACK_03.flac: {}}").unwrap();
+
+        let library = Library::new(&root, config);
+
+        // Overlay meta files are used, even for the root item.
+        let produced = library.process_item_file(Path::new("")).unwrap();
+        assert_eq!(produced.get("overlay_key"), Some(&TU::s("album")));
+
+        let item_path = Path::new("DISC_01").join("TRACK_01.flac");
+        let produced = library.process_item_file(&item_path).unwrap();
+        assert_eq!(produced.get("overlay_key"), Some(&TU::s("track")));
+
+        // Processing a single item file agrees with processing the tree.
+        for res in library.process_tree() {
+            let (item_path, block) = res.unwrap();
+            assert_eq!(library.process_item_file(&item_path).unwrap(), block);
+        }
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/item.json", r#"{"ALBUM": {"outside": true}}"#).unwrap()
+            .add_file("/music/ALBUM/self.json", r#"{"title": "Album"}"#).unwrap()
+            .add_file("/music/ALBUM/item.json", r#"[{"track": 1}, {"track": 2}]"#).unwrap()
+            .add_file("/music/ALBUM/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM/TRACK_02.flac", "").unwrap();
+
+        let mut config = TU::sample_config();
+        config.virtual_fields.enabled = true;
+
+        let library = Library::new_in(&fs, "/music/ALBUM", config);
+
+        let get = |block: &Block, key_path: &[&str]| Value::Mapping(block.clone()).get_key_path(key_path).cloned();
+
+        // The root item does not get metadata from outside of the library.
+        let produced = library.process_item_file(Path::new("")).unwrap();
+        assert_eq!(get(&produced, &["title"]), Some(TU::s("Album")));
+        assert_eq!(get(&produced, &["outside"]), None);
+
+        let produced = library.process_item_file(Path::new("TRACK_02.flac")).unwrap();
+        assert_eq!(get(&produced, &["track"]), Some(TU::i(2)));
+        assert_eq!(get(&produced, &["_file", "depth"]), Some(TU::i(1)));
+        assert_eq!(get(&produced, &["_file", "index"]), Some(TU::i(1)));
+
+        let produced = library.process_tree().map(Result::unwrap).collect::<Vec<_>>();
+        let expected = vec![Path::new("TRACK_01.flac"), Path::new("TRACK_02.flac")];
+        assert_eq!(produced.iter().map(|(p, _)| p).collect::<Vec<_>>(), expected);
+
+        for (item_path, block) in produced {
+            assert_eq!(library.process_item_file(&item_path).unwrap(), block);
+        }
+
+        assert_eq!(library.walk().map(Result::unwrap).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn ancestors() {
+        let temp_dir = TU::create_temp_media_test_dir("library_ancestors");
+        let path = temp_dir.path();
+
+        let library = Library::new(path.join("ALBUM_01"), TU::sample_config());
+
+        let item_path = library.root().join("DISC_01").join("TRACK_01.flac");
+        let produced = library
+            .ancestors(&item_path)
+            .unwrap()
+            .map(|res| res.unwrap().into_owned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            produced,
+            vec![item_path.clone(), library.root().join("DISC_01"), library.root().to_path_buf()],
+        );
+
+        assert!(library.ancestors(path).is_err());
+    }
+}
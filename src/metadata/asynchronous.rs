@@ -0,0 +1,164 @@
+//! Async processing of item file metadata, for use inside of async runtimes.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Merger, Selection, Sorter, VirtualFields};
+use crate::metadata::Schema;
+use crate::metadata::plexer::{resolve_relative_key, PlexRooted, Plexer};
+use crate::metadata::processor::{Error, Processor};
+use crate::sources::{Anchor, Source, Sourcer};
+use crate::types::Block;
+
+impl Processor {
+    /// Async version of `Processor::process_meta_file`, producing owned item
+    /// file paths.
+    pub async fn process_meta_file_async(
+        meta_path: &Path,
+        source: &Source,
+        selection: &Selection,
+        sorter: &Sorter,
+    ) -> Result<HashMap<PathBuf, Block>, Error> {
+        let schema = source.read_schema_async(meta_path).await.map_err(Error::CannotReadMetadata)?;
+
+        if let Anchor::Root = source.anchor {
+            return Self::process_root_meta_file_async(meta_path, source, schema, selection).await;
+        }
+
+        let mut sel_item_paths = source
+            .selected_item_paths_async(meta_path, selection)
+            .await
+            .map_err(Error::CannotFindItemPaths)?;
+
+        // Sort up front, so that plexing does not need to read mod times.
+        sorter.sort_path_results_async(&mut sel_item_paths).await;
+
+        let mut meta_plexed = HashMap::new();
+
+        let meta_plexer = Plexer::new_presorted(
+            schema,
+            sel_item_paths.into_iter().map(|res| res.map(Into::into)),
+        );
+
+        for meta_plex_res in meta_plexer {
+            let (item_path, meta_block) = meta_plex_res.map_err(Error::PlexerError)?;
+            meta_plexed.insert(item_path.into_owned(), meta_block);
+        }
+
+        Ok(meta_plexed)
+    }
+
+    /// Async version of plexing a root meta file, which finds item paths
+    /// using the relative keys in the meta file.
+    async fn process_root_meta_file_async(
+        meta_path: &Path,
+        source: &Source,
+        schema: Schema,
+        selection: &Selection,
+    ) -> Result<HashMap<PathBuf, Block>, Error> {
+        let root_dir_path = source
+            .meta_target_dir(meta_path)
+            .map_err(Error::CannotFindItemPaths)?;
+
+        let mb_map = PlexRooted::keyed_blocks(schema).map_err(Error::PlexerError)?;
+
+        let mut meta_plexed = HashMap::new();
+
+        for (key, block) in mb_map {
+            let item_path = resolve_relative_key(&root_dir_path, &key).map_err(Error::PlexerError)?;
+            let sel_res = selection.is_selected_from_async(&root_dir_path, &item_path).await;
+
+            let (item_path, block) = PlexRooted::selected_block(item_path, block, key, sel_res)
+                .map_err(Error::PlexerError)?;
+
+            meta_plexed.insert(item_path, block);
+        }
+
+        Ok(meta_plexed)
+    }
+
+    /// Async version of `Processor::process_item_file_with_config`, with the
+    /// parts of a `Config` passed separately.
+    pub async fn process_item_file_async(
+        item_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        virtual_fields: &VirtualFields,
+    ) -> Result<Block, Error> {
+        let norm_item_path = sourcer.normalize_async(item_path).await;
+        let item_path: &Path = &norm_item_path;
+
+        let meta_paths = sourcer.meta_paths_async(item_path).await;
+
+        // Process all of the meta files up front, stopping at the first error.
+        // Merging then encounters the same error at the same point.
+        let mut item_blocks = HashMap::<(PathBuf, Anchor), Option<Block>>::new();
+
+        for res in meta_paths.iter() {
+            let (meta_path, source) = match res {
+                Ok(o) => o,
+                Err(_) => break,
+            };
+
+            let mut processed_meta_file =
+                Self::process_meta_file_async(meta_path, source, selection, sorter).await?;
+
+            item_blocks.insert((meta_path.clone(), source.anchor), processed_meta_file.remove(item_path));
+        }
+
+        let (mut block, _) = Self::merge_meta_path_blocks(item_path, meta_paths.into_iter(), merger, |meta_path, source| {
+            Ok(item_blocks.remove(&(meta_path.to_path_buf(), source.anchor)).flatten())
+        })?;
+
+        virtual_fields
+            .inject_async(&mut block, item_path, None, selection, sorter)
+            .await
+            .map_err(Error::CannotComputeVirtualFields)?;
+
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TestUtil as TU;
+
+    #[tokio::test]
+    async fn process_item_file_async() {
+        let temp_dir = TU::create_temp_media_test_dir("process_item_file_async");
+        let path = temp_dir.path();
+
+        let mut config = TU::sample_config();
+        config.virtual_fields.enabled = true;
+
+        let item_paths = vec![
+            path.to_path_buf(),
+            path.join("ALBUM_01"),
+            path.join("ALBUM_01").join("DISC_01"),
+            path.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac"),
+            path.join("ALBUM_03").join("DISC_02").join("TRACK_01").join("SUBTRACK_02.flac"),
+            path.join("ALBUM_01").join("DISC_01").join("TRACK_XX.flac"),
+        ];
+
+        for item_path in item_paths {
+            let expected = Processor::process_item_file_with_config(&item_path, &config)
+                .map_err(|err| err.to_string());
+            let produced = Processor::process_item_file_async(
+                &item_path,
+                &config.sourcer,
+                &config.selection,
+                &config.sorter,
+                &config.merger,
+                &config.virtual_fields,
+            )
+            .await
+            .map_err(|err| err.to_string());
+
+            assert_eq!(expected, produced);
+        }
+    }
+}
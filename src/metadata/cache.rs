@@ -0,0 +1,484 @@
+//! Caching of processed meta files, for reuse across multiple item lookups.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{Selection, Sorter};
+use crate::fs::{DiskFs, Fs, FsMetadata};
+use crate::metadata::processor::{Error, Processor};
+use crate::sources::{Anchor, Source};
+use crate::types::Block;
+
+/// The default maximum number of bytes of meta files held in a cache.
+pub const DEFAULT_CAPACITY: usize = 16 * 1024 * 1024;
+
+type Key = (PathBuf, Anchor);
+
+/// The stamps of the item files in a directory, sorted by path.
+type DirItems = Arc<Vec<(PathBuf, FileStamp)>>;
+
+/// Converts a mod time into the time since the Unix epoch, keeping the full
+/// sub-second precision that the filesystem provides.
+fn mtime_nanos(mtime: SystemTime) -> Option<Duration> {
+    mtime.duration_since(UNIX_EPOCH).ok()
+}
+
+/// The parts of a file's metadata that are used to detect changes to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileStamp {
+    mtime: Option<Duration>,
+    len: u64,
+}
+
+impl From<FsMetadata> for FileStamp {
+    fn from(fs_stat: FsMetadata) -> Self {
+        Self { mtime: fs_stat.modified.and_then(mtime_nanos), len: fs_stat.len, }
+    }
+}
+
+/// A snapshot of the filesystem state that a processed meta file depends on.
+/// If any part of this changes, the cached results are considered stale.
+/// Mod times are compared with sub-second precision, so that edits made
+/// within the same second are still detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    meta: FileStamp,
+    // The contents of the target directory determine the item paths for a meta
+    // file, so changes to that directory also need to be detected.
+    parent_mtime: Option<Duration>,
+    // The item paths in the target directory, if their mod times or sizes
+    // affect which ones are selected or how they are sorted. These are shared
+    // by all meta files for the same directory, and are only taken once per
+    // batch, see `Cache::start_batch`.
+    items: Option<DirItems>,
+    // The ignore files that affect the selection of the item paths, which may
+    // be edited without changing the target directory.
+    ignore_files: Vec<Option<FileStamp>>,
+}
+
+impl Stamp {
+    /// Takes a snapshot for a root meta file. Root meta files name their item
+    /// paths themselves, so only changes to the meta file are detected.
+    fn of_root(fs: &dyn Fs, meta_path: &Path) -> Option<Self> {
+        let meta = fs.metadata(meta_path).ok()?.into();
+        Some(Self { meta, parent_mtime: None, items: None, ignore_files: Vec::new(), })
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    stamp: Stamp,
+    blocks: HashMap<PathBuf, Block>,
+    last_used: u64,
+}
+
+impl Entry {
+    /// The approximate number of bytes held by this entry, which is taken to
+    /// be the size of its meta file.
+    fn size(&self) -> usize {
+        usize::try_from(self.stamp.meta.len).unwrap_or(usize::MAX)
+    }
+}
+
+/// A cache of plexed meta file results, keyed by meta file path.
+/// Entries are invalidated when the meta file's mod time or size change, when
+/// the contents of its parent directory change, or when anything else that
+/// its item paths depend on changes, such as ignore files. Results for root
+/// meta files are only invalidated when the root meta file itself changes.
+///
+/// If sorting by mod time or filtering on item file stats, the stats of the
+/// item files in a directory are only checked once per batch of lookups, so
+/// that looking up all of the item files in a directory stays linear. Item
+/// files that are changed during a batch are only noticed once the next batch
+/// is started with `start_batch`.
+///
+/// A cache is bound to the selection and sorter that it plexes meta files
+/// with, so that its results are never reused for a different config.
+///
+/// The capacity of a cache is measured in bytes, using the size of each meta
+/// file as an approximation of the size of its cached blocks. Once full, the
+/// least recently used meta files are evicted first.
+#[derive(Debug)]
+pub struct Cache<'c> {
+    selection: &'c Selection,
+    sorter: &'c Sorter,
+    entries: HashMap<Key, Entry>,
+    // Item stamps of target directories, along with the batch that they were
+    // taken in.
+    dir_items: HashMap<PathBuf, (u64, DirItems)>,
+    batch: u64,
+    // Keys of the cached entries, ordered from least to most recently used.
+    recency: BTreeMap<u64, Key>,
+    capacity: usize,
+    size: usize,
+    tick: u64,
+}
+
+impl<'c> Cache<'c> {
+    pub fn new(selection: &'c Selection, sorter: &'c Sorter) -> Self {
+        Self::with_capacity(selection, sorter, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new cache that holds at most `capacity` bytes of meta files.
+    pub fn with_capacity(selection: &'c Selection, sorter: &'c Sorter, capacity: usize) -> Self {
+        Self {
+            selection,
+            sorter,
+            entries: HashMap::new(),
+            dir_items: HashMap::new(),
+            batch: 0,
+            recency: BTreeMap::new(),
+            capacity,
+            size: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the approximate number of bytes currently held in this cache.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of meta files currently held in this cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dir_items.clear();
+        self.recency.clear();
+        self.size = 0;
+    }
+
+    /// Starts a new batch of lookups, after which the stats of item files are
+    /// checked again.
+    pub fn start_batch(&mut self) {
+        self.batch += 1;
+
+        // Only keep item stamps that cached entries still use.
+        self.dir_items.retain(|_, (_, items)| Arc::strong_count(items) > 1);
+    }
+
+    /// Returns the metadata block for an item file, as provided by a meta file.
+    /// The meta file is only read and plexed if there is no valid cached
+    /// result for it. Returns `None` if the meta file does not provide a block
+    /// for the item file.
+    pub fn item_block(&mut self, item_path: &Path, meta_path: &Path, source: &Source) -> Result<Option<Block>, Error> {
+        self.item_block_in(&DiskFs, item_path, meta_path, source)
+    }
+
+    /// Similar to `item_block`, but accesses a given filesystem. A cache
+    /// should only be used with a single filesystem.
+    pub fn item_block_in(
+        &mut self,
+        fs: &dyn Fs,
+        item_path: &Path,
+        meta_path: &Path,
+        source: &Source,
+    ) -> Result<Option<Block>, Error> {
+        self.tick += 1;
+
+        let key = (meta_path.to_path_buf(), source.anchor);
+        let stamp = self.stamp(fs, meta_path, source);
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if Some(&entry.stamp) == stamp.as_ref() {
+                self.recency.remove(&entry.last_used);
+                self.recency.insert(self.tick, key);
+                entry.last_used = self.tick;
+
+                return Ok(entry.blocks.get(item_path).cloned());
+            }
+
+            // The cached entry is stale, remove it.
+            self.remove(&key);
+        }
+
+        let blocks = Processor::process_meta_file_owned_in(fs, None, meta_path, source, self.selection, self.sorter)?;
+
+        let item_block = blocks.get(item_path).cloned();
+
+        // Only cache results if they could be read in a stable state.
+        if let Some(stamp) = stamp {
+            self.insert(key, Entry { stamp, blocks, last_used: self.tick });
+        }
+
+        Ok(item_block)
+    }
+
+    /// Takes a snapshot for a meta file. Returns `None` if the results for
+    /// the meta file should not be cached.
+    fn stamp(&mut self, fs: &dyn Fs, meta_path: &Path, source: &Source) -> Option<Stamp> {
+        if source.anchor == Anchor::Root {
+            return Stamp::of_root(fs, meta_path);
+        }
+
+        let meta = fs.metadata(meta_path).ok()?.into();
+        let target_dir_path = source.meta_target_dir(meta_path).ok()?;
+        let parent_mtime = fs.mtime(&target_dir_path).and_then(mtime_nanos);
+
+        let has_sibling_items = source.anchor != Anchor::Internal;
+        let uses_item_stats = self.selection.uses_item_stats(self.sorter);
+
+        let items = if has_sibling_items && uses_item_stats {
+            Some(self.dir_items(fs, &target_dir_path)?)
+        } else {
+            None
+        };
+
+        let items_dir_path = if has_sibling_items { Some(&*target_dir_path) } else { target_dir_path.parent() };
+        let ignore_files = items_dir_path
+            .map(|p| self.selection.ignore_file_paths(p))
+            .unwrap_or_default()
+            .iter()
+            .map(|p| fs.metadata(p).ok().map(FileStamp::from))
+            .collect();
+
+        Some(Stamp { meta, parent_mtime, items, ignore_files, })
+    }
+
+    /// Returns the stamps of the item files in a directory, which are only
+    /// taken once per batch. Unchanged stamps are shared with the previous
+    /// batch, so that comparing them is cheap.
+    fn dir_items(&mut self, fs: &dyn Fs, dir_path: &Path) -> Option<DirItems> {
+        if let Some((batch, items)) = self.dir_items.get(dir_path) {
+            if *batch == self.batch {
+                return Some(items.clone());
+            }
+        }
+
+        let mut items = fs
+            .read_dir(dir_path)
+            .ok()?
+            .map(|res| {
+                let path = res.ok()?;
+                let file_stamp = fs.metadata(&path).ok()?.into();
+                Some((path, file_stamp))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let items = match self.dir_items.get(dir_path) {
+            Some((_, old_items)) if **old_items == items => old_items.clone(),
+            _ => Arc::new(items),
+        };
+
+        self.dir_items.insert(dir_path.to_path_buf(), (self.batch, items.clone()));
+
+        Some(items)
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry) {
+        let entry_size = entry.size();
+
+        // Entries that would never fit are not cached at all.
+        if entry_size > self.capacity {
+            return;
+        }
+
+        while self.size + entry_size > self.capacity {
+            match self.recency.pop_first() {
+                Some((_, lru_key)) => {
+                    if let Some(evicted) = self.entries.remove(&lru_key) {
+                        self.size -= evicted.size();
+                    }
+                },
+                None => break,
+            }
+        }
+
+        self.size += entry_size;
+        self.recency.insert(entry.last_used, key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.size -= entry.size();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::io::Write;
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::config::sorter::SortBy;
+    use crate::fs::MemoryFs;
+    use crate::test_util::TestUtil as TU;
+
+    #[test]
+    fn item_block() {
+        let temp_dir = TU::create_temp_media_test_dir("cache_item_block");
+        let path = temp_dir.path();
+
+        let selection = TU::sample_selection();
+        let sorter = Sorter::default();
+        let source = Source::from_name(str!("item.json"), Anchor::External).unwrap();
+
+        let meta_path = path.join("ALBUM_01").join("DISC_01").join("item.json");
+        let item_path_a = path.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac");
+        let item_path_b = path.join("ALBUM_01").join("DISC_01").join("TRACK_02.flac");
+
+        let mut cache = Cache::new(&selection, &sorter);
+        assert!(cache.is_empty());
+
+        let block_a = cache
+            .item_block(&item_path_a, &meta_path, &source)
+            .unwrap()
+            .unwrap();
+        assert_eq!(block_a.get("overridden"), Some(&TU::s("TRACK_01_item")));
+        assert_eq!(cache.len(), 1);
+
+        let block_b = cache
+            .item_block(&item_path_b, &meta_path, &source)
+            .unwrap()
+            .unwrap();
+        assert_eq!(block_b.get("overridden"), Some(&TU::s("TRACK_02_item")));
+        assert_eq!(cache.len(), 1);
+
+        // Changing the meta file invalidates the cached entry.
+        let mut f = File::create(&meta_path).unwrap();
+        write!(f, r#"[{{"a": 1}}, {{"a": 2}}, {{"a": 3}}]"#).unwrap();
+        drop(f);
+
+        let block_b = cache
+            .item_block(&item_path_b, &meta_path, &source)
+            .unwrap()
+            .unwrap();
+        assert_eq!(block_b.get("a"), Some(&TU::i(2)));
+        assert_eq!(cache.len(), 1);
+
+        // Edits that keep the same size are detected within the same second.
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(&meta_path, r#"[{"a": 4}, {"a": 5}, {"a": 6}]"#).unwrap();
+
+        let block_b = cache
+            .item_block(&item_path_b, &meta_path, &source)
+            .unwrap()
+            .unwrap();
+        assert_eq!(block_b.get("a"), Some(&TU::i(5)));
+        assert_eq!(cache.len(), 1);
+
+        // The size of a cache is the size of its meta files.
+        let meta_len = std::fs::metadata(&meta_path).unwrap().len() as usize;
+        assert_eq!(cache.size(), meta_len);
+
+        // Meta files larger than the capacity are not cached.
+        let mut cache = Cache::with_capacity(&selection, &sorter, meta_len - 1);
+        cache
+            .item_block(&item_path_a, &meta_path, &source)
+            .unwrap()
+            .unwrap();
+        assert!(cache.is_empty());
+
+        // Least recently used entries get evicted first.
+        let self_meta_len = std::fs::metadata(path.join("ALBUM_01").join("self.json")).unwrap().len() as usize;
+        let mut cache = Cache::with_capacity(&selection, &sorter, self_meta_len * 3);
+        let source = Source::from_name(str!("self.json"), Anchor::Internal).unwrap();
+        let album_names = vec!["ALBUM_01", "ALBUM_02", "ALBUM_03", "ALBUM_01", "ALBUM_05"];
+
+        for album_name in album_names {
+            let album_path = path.join(album_name);
+            cache
+                .item_block(&album_path, &album_path.join("self.json"), &source)
+                .unwrap()
+                .unwrap();
+        }
+
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.size(), self_meta_len * 3);
+        for (album_name, expected) in [
+            ("ALBUM_01", true),
+            ("ALBUM_02", false),
+            ("ALBUM_03", true),
+            ("ALBUM_05", true),
+        ] {
+            let key = (path.join(album_name).join("self.json"), Anchor::Internal);
+            assert_eq!(expected, cache.entries.contains_key(&key));
+        }
+    }
+
+    #[test]
+    fn item_block_stamps() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/lib/ALBUM/item.json", r#"[{"n": 1}, {"n": 2}]"#).unwrap()
+            .add_file("/lib/ALBUM/TRACK_A.flac", "").unwrap()
+            .add_file("/lib/ALBUM/TRACK_B.flac", "").unwrap()
+            .add_file("/lib/root.json", r#"{"ALBUM/TRACK_A.flac": {"n": 3}}"#).unwrap();
+        fs.set_modified("/lib/ALBUM/TRACK_A.flac", UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        fs.set_modified("/lib/ALBUM/TRACK_B.flac", UNIX_EPOCH + Duration::from_secs(2)).unwrap();
+
+        let meta_path = Path::new("/lib/ALBUM/item.json");
+        let item_path = Path::new("/lib/ALBUM/TRACK_A.flac");
+        let source = Source::from_name(str!("item.json"), Anchor::External).unwrap();
+
+        // Touching an item file changes the order of items sorted by mod time.
+        let selection = TU::sample_selection();
+        let sorter = Sorter { sort_by: SortBy::ModTime, ..Default::default() };
+        let mut cache = Cache::new(&selection, &sorter);
+
+        let block = cache.item_block_in(&fs, item_path, meta_path, &source).unwrap();
+        assert_eq!(block, Some(Block(btreemap![str!("n") => TU::i(1)])));
+
+        // Item files are only checked again once a new batch is started.
+        fs.set_modified("/lib/ALBUM/TRACK_A.flac", UNIX_EPOCH + Duration::from_secs(3)).unwrap();
+        let block = cache.item_block_in(&fs, item_path, meta_path, &source).unwrap();
+        assert_eq!(block, Some(Block(btreemap![str!("n") => TU::i(1)])));
+
+        cache.start_batch();
+        let block = cache.item_block_in(&fs, item_path, meta_path, &source).unwrap();
+        assert_eq!(block, Some(Block(btreemap![str!("n") => TU::i(2)])));
+
+        // Unchanged item stamps are kept for the next batch, unused ones are not.
+        cache.start_batch();
+        assert_eq!(cache.dir_items.len(), 1);
+        cache.clear();
+        cache.start_batch();
+        assert!(cache.dir_items.is_empty());
+
+        // Editing an ignore file changes which items are selected, so the meta
+        // file no longer matches them.
+        let selection = TU::sample_selection().with_ignore_files(true);
+        let sorter = Sorter::default();
+        let mut cache = Cache::new(&selection, &sorter);
+        fs.add_file("/lib/ALBUM/.anagmaignore", "").unwrap();
+
+        let block = cache.item_block_in(&fs, item_path, meta_path, &source).unwrap();
+        assert_eq!(block, Some(Block(btreemap![str!("n") => TU::i(1)])));
+
+        fs.add_file("/lib/ALBUM/.anagmaignore", "TRACK_B.flac").unwrap();
+        assert!(cache.item_block_in(&fs, item_path, meta_path, &source).is_err());
+
+        // Root meta files are cached until they change themselves.
+        let source = Source::from_name(str!("root.json"), Anchor::Root).unwrap();
+        let root_meta_path = Path::new("/lib/root.json");
+        let block = cache.item_block_in(&fs, item_path, root_meta_path, &source).unwrap();
+        assert_eq!(block, Some(Block(btreemap![str!("n") => TU::i(3)])));
+        assert!(cache.entries.contains_key(&(root_meta_path.to_path_buf(), Anchor::Root)));
+
+        fs.add_file("/lib/root.json", r#"{"ALBUM/TRACK_A.flac": {"n": 4}}"#).unwrap();
+        let block = cache.item_block_in(&fs, item_path, root_meta_path, &source).unwrap();
+        assert_eq!(block, Some(Block(btreemap![str!("n") => TU::i(4)])));
+    }
+}
@@ -1,12 +1,16 @@
 //! Primitives and methods for accessing and working with item metadata.
 
+#[cfg(feature = "async")] mod asynchronous;
+pub mod cache;
 pub mod item_paths;
+#[cfg(feature = "parallel")] mod parallel;
 pub mod plexer;
 pub mod processor;
 pub mod schema;
 
+pub use self::cache::Cache;
 pub use self::schema::{Arity, Schema};
 pub use self::plexer::{Plexer, Error as PlexerError};
-pub use self::processor::Error as ProcessorError;
+pub use self::processor::{CachedProcessor, Error as ProcessorError};
 
 pub(crate) use self::schema::SchemaRepr;
@@ -0,0 +1,342 @@
+//! Multithreaded processing of item file metadata for whole directory trees.
+
+use std::collections::HashMap;
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use rayon::prelude::*;
+
+use crate::config::{Merger, Selection, Sorter, VirtualFields};
+use crate::fs::{DiskFs, Fs};
+use crate::metadata::processor::{Error, ProcessedItem, Processor};
+use crate::sources::{Anchor, Source, Sourcer};
+use crate::types::Block;
+
+type MetaKey = (PathBuf, Anchor);
+type PlexedMetaFile = HashMap<PathBuf, Block>;
+
+impl Processor {
+    /// Processes metadata for all selected item files in a directory tree, in
+    /// the same way as `Processor::process_tree`, but using a thread pool.
+    /// Each meta file is only read and plexed once. Results are returned in
+    /// the same order as `Processor::process_tree`, with errors reported per
+    /// item file.
+    pub fn process_tree_par(
+        root_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        virtual_fields: &VirtualFields,
+    ) -> Vec<ProcessedItem> {
+        Self::process_tree_par_in(&DiskFs, root_path, sourcer, selection, sorter, merger, virtual_fields)
+    }
+
+    /// Similar to `process_tree_par`, but walks a given filesystem.
+    pub fn process_tree_par_in(
+        fs: &dyn Fs,
+        root_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        virtual_fields: &VirtualFields,
+    ) -> Vec<ProcessedItem> {
+        let norm_root_path = sourcer.normalize_in(fs, root_path);
+
+        let par_tree = ParTree {
+            fs,
+            root_path: &norm_root_path,
+            sourcer,
+            selection,
+            sorter,
+            merger,
+            virtual_fields,
+            plexed: Mutex::new(HashMap::new()),
+        };
+
+        par_tree.walk(Ok(norm_root_path.to_path_buf()), None)
+    }
+}
+
+/// The shared state of a parallel tree walk.
+struct ParTree<'a> {
+    fs: &'a dyn Fs,
+    root_path: &'a Path,
+    sourcer: &'a Sourcer,
+    selection: &'a Selection,
+    sorter: &'a Sorter,
+    merger: &'a Merger,
+    virtual_fields: &'a VirtualFields,
+    // Each meta file is plexed by the first item file that needs it, while
+    // any others wait for the result. Failures are stored as `None`, and are
+    // reported again when processing the item files that use them.
+    plexed: Mutex<HashMap<MetaKey, Arc<OnceLock<Option<PlexedMetaFile>>>>>,
+}
+
+impl<'a> ParTree<'a> {
+    /// Processes an item file and, unless it is excluded by the metadata
+    /// filters of the `Selection`, its descendants, which are listed in
+    /// parallel. Results are in the same order as a `ChildFileWalker`.
+    fn walk(&self, res: IoResult<PathBuf>, sibling_index: Option<usize>) -> Vec<ProcessedItem> {
+        let item_path = match res {
+            Ok(item_path) => item_path,
+            Err(err) => return vec![Err(Error::CannotWalkTree(err))],
+        };
+
+        let meta_filters = self.selection.meta_filters();
+        let res = self.process_item_file(&item_path, sibling_index);
+
+        let mut walked = Vec::new();
+
+        // Directories excluded by their metadata are not walked into.
+        let is_excluded = matches!(&res, Ok(block) if meta_filters.is_excluded(block));
+
+        match res {
+            Ok(block) if !meta_filters.is_match(&block) => {},
+            res => walked.push(res.map(|block| (item_path.clone(), block))),
+        }
+
+        if is_excluded {
+            return walked;
+        }
+
+        let sub_item_paths = self.fs
+            .metadata(&item_path)
+            .and_then(|file_info| match file_info.is_dir() {
+                true => self.selection.select_in_dir_sorted_within_in(self.fs, self.root_path, &item_path, self.sorter),
+                false => Ok(Vec::new()),
+            });
+
+        match sub_item_paths {
+            Ok(sub_item_paths) => {
+                // Children are selected in sorted order, so the sibling index
+                // of each item file is its position among the found paths.
+                let mut index = 0;
+                let sub_item_paths = sub_item_paths
+                    .into_iter()
+                    .map(|res| {
+                        let sibling_index = res.is_ok().then(|| { index += 1; index - 1 });
+                        (res, sibling_index)
+                    })
+                    .collect::<Vec<_>>();
+
+                let sub_walked = sub_item_paths
+                    .into_par_iter()
+                    .map(|(res, sibling_index)| self.walk(res, sibling_index))
+                    .collect::<Vec<_>>();
+
+                walked.extend(sub_walked.into_iter().flatten());
+            },
+            Err(err) => walked.push(Err(Error::CannotWalkTree(err))),
+        }
+
+        walked
+    }
+
+    /// Processes the metadata of an item file. The sibling index is `None`
+    /// only for the root item path, whose siblings are not walked.
+    fn process_item_file(&self, item_path: &Path, sibling_index: Option<usize>) -> Result<Block, Error> {
+        let fs = self.fs;
+        let meta_paths = self.sourcer.meta_paths_in(fs, item_path);
+
+        let comp_res = Processor::merge_meta_path_blocks(item_path, meta_paths, self.merger, |meta_path, source| {
+            let cell = self.plexed(meta_path, source);
+
+            match cell.get().and_then(Option::as_ref) {
+                Some(item_blocks) => Ok(item_blocks.get(item_path).cloned()),
+                None => {
+                    let mut item_blocks =
+                        Processor::process_meta_file_owned_in(fs, Some(self.root_path), meta_path, source, self.selection, self.sorter)?;
+                    Ok(item_blocks.remove(item_path))
+                },
+            }
+        })
+        .and_then(|(mut block, _)| {
+            let root_path = Some(self.root_path);
+
+            let inject_res = match sibling_index {
+                Some(index) => self.virtual_fields
+                    .inject_with_index(fs, &mut block, item_path, root_path, Some(index)),
+                None => self.virtual_fields
+                    .inject_in(fs, &mut block, item_path, root_path, self.selection, self.sorter),
+            };
+
+            inject_res.map_err(Error::CannotComputeVirtualFields)?;
+            Ok(block)
+        });
+
+        comp_res.map_err(|err| Error::CannotProcessItem(item_path.to_path_buf(), Box::new(err)))
+    }
+
+    /// Returns the plexed results of a meta file, plexing it if this is the
+    /// first time that it is needed. The lock on the map is not held while
+    /// plexing, so that different meta files are plexed in parallel.
+    fn plexed(&self, meta_path: &Path, source: &Source) -> Arc<OnceLock<Option<PlexedMetaFile>>> {
+        let cell = self.plexed
+            .lock()
+            .unwrap()
+            .entry((meta_path.to_path_buf(), source.anchor))
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| {
+            Processor::process_meta_file_owned_in(self.fs, Some(self.root_path), meta_path, source, self.selection, self.sorter).ok()
+        });
+
+        cell
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::test_util::TestUtil as TU;
+    use std::io::{Error as IoError, ErrorKind, Read};
+
+    use maplit::btreemap;
+    use str_macro::str;
+
+    use crate::config::selection::MetaFilters;
+    use crate::fs::{DirEntries, FsMetadata, MemoryFs};
+    use crate::types::Value;
+
+    #[test]
+    fn process_tree_par() {
+        let temp_dir = TU::create_temp_media_test_dir("process_tree_par");
+        let path = temp_dir.path();
+
+        let Config { selection, sorter, sourcer, merger, .. } = TU::sample_config();
+        let virtual_fields = VirtualFields { enabled: true, ..Default::default() };
+
+        let expected = Processor::process_tree(path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .map(|res| res.map_err(|err| err.to_string()))
+            .collect::<Vec<_>>();
+        let produced = Processor::process_tree_par(path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .into_iter()
+            .map(|res| res.map_err(|err| err.to_string()))
+            .collect::<Vec<_>>();
+
+        assert!(!produced.is_empty());
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn process_tree_par_failing_sibling() {
+        let temp_dir = TU::create_temp_media_test_dir("process_tree_par_failing_sibling");
+        let path = temp_dir.path();
+
+        let Config { selection, sorter, sourcer, merger, .. } = TU::sample_config();
+        let virtual_fields = VirtualFields { enabled: true, ..Default::default() };
+
+        // Item files that fail to process still count towards the sibling
+        // indices of the item files after them.
+        std::fs::write(path.join("ALBUM_02").join("self.json"), "[]").unwrap();
+
+        let expected = Processor::process_tree(path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .map(|res| res.map_err(|err| err.to_string()))
+            .collect::<Vec<_>>();
+        let produced = Processor::process_tree_par(path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .into_iter()
+            .map(|res| res.map_err(|err| err.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected, produced);
+
+        let index_of = |item_path: &Path| {
+            produced
+                .iter()
+                .filter_map(|res| res.as_ref().ok())
+                .find(|(p, _)| p == item_path)
+                .and_then(|(_, block)| Value::Mapping(block.clone()).get_key_path(&["_file", "index"]).cloned())
+        };
+
+        assert!(expected.iter().any(|res| res.is_err()));
+        assert_eq!(index_of(&path.join("ALBUM_01")), Some(Value::Integer(0)));
+        assert_eq!(index_of(&path.join("ALBUM_03")), Some(Value::Integer(2)));
+    }
+
+    #[test]
+    fn process_tree_par_meta_filters() {
+        // A filesystem that cannot list one of its directories.
+        #[derive(Debug)]
+        struct FailingFs(MemoryFs, PathBuf);
+
+        impl Fs for FailingFs {
+            fn metadata(&self, path: &Path) -> IoResult<FsMetadata> {
+                self.0.metadata(path)
+            }
+
+            fn read_dir<'a>(&'a self, path: &Path) -> IoResult<DirEntries<'a>> {
+                match path == self.1 {
+                    true => Err(IoError::new(ErrorKind::PermissionDenied, "cannot list directory")),
+                    false => self.0.read_dir(path),
+                }
+            }
+
+            fn open<'a>(&'a self, path: &Path) -> IoResult<Box<dyn Read + 'a>> {
+                self.0.open(path)
+            }
+
+            fn canonicalize(&self, path: &Path) -> IoResult<PathBuf> {
+                self.0.canonicalize(path)
+            }
+        }
+
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/item.json", r#"{"ALBUM_01": {"hidden": true}, "ALBUM_02": {}}"#).unwrap()
+            .add_file("/music/ALBUM_01/item.json", r#"{"DISC_01": {}}"#).unwrap()
+            .add_file("/music/ALBUM_01/DISC_01/item.json", r#"[{"status": "final"}]"#).unwrap()
+            .add_file("/music/ALBUM_01/DISC_01/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM_02/item.json", r#"[{"status": "final"}, {"status": "draft"}]"#).unwrap()
+            .add_file("/music/ALBUM_02/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM_02/TRACK_02.flac", "").unwrap();
+        let fs = FailingFs(fs, PathBuf::from("/music/ALBUM_01/DISC_01"));
+
+        let Config { mut selection, sorter, sourcer, merger, virtual_fields } = TU::sample_config();
+
+        let process = |selection: &Selection| {
+            let expected = Processor::process_tree_in(&fs, Path::new("/music"), &sourcer, selection, &sorter, &merger, &virtual_fields)
+                .map(|res| res.map_err(|err| err.to_string()))
+                .collect::<Vec<_>>();
+            let produced = Processor::process_tree_par_in(&fs, Path::new("/music"), &sourcer, selection, &sorter, &merger, &virtual_fields)
+                .into_iter()
+                .map(|res| res.map_err(|err| err.to_string()))
+                .collect::<Vec<_>>();
+
+            assert_eq!(expected, produced);
+            produced
+        };
+
+        // Without filters, the directory that cannot be listed is an error.
+        assert!(process(&selection).iter().any(|res| res.is_err()));
+
+        // Directories excluded by their metadata are not walked into, so
+        // errors from inside of them are not reported either.
+        selection = selection.with_meta_filters(MetaFilters::from_reprs(
+            btreemap![str!("status") => TU::s("final")],
+            btreemap![str!("hidden") => Value::Boolean(true)],
+        ));
+        let produced = process(&selection)
+            .into_iter()
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(produced, vec![PathBuf::from("/music/ALBUM_02/TRACK_01.flac")]);
+    }
+
+    #[test]
+    fn shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Selection>();
+        assert_send_sync::<Sorter>();
+        assert_send_sync::<Sourcer>();
+        assert_send_sync::<Merger>();
+        assert_send_sync::<VirtualFields>();
+        assert_send_sync::<Config>();
+    }
+}
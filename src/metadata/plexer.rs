@@ -1,16 +1,17 @@
 //! Methods to assign blocks of metadata to their corresponding item file paths.
 
 use std::borrow::Cow;
-use std::io::{Error as IoError, Result as IoResult};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
 use std::iter::FusedIterator;
-use std::path::Path;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::vec::IntoIter as VecIntoIter;
 
 use thiserror::Error;
 
-use crate::config::Sorter;
+use crate::config::{Selection, Sorter};
+use crate::fs::{DiskFs, Fs};
 use crate::types::{Block, BlockMap};
+use crate::types::block_map::IntoIter as BlockMapIntoIter;
 use crate::types::block_seq::IntoIter as BlockSeqIntoIter;
 use crate::metadata::schema::Schema;
 
@@ -26,6 +27,10 @@ pub enum Error {
     UnusedTaggedBlock(Block, String),
     #[error("item path does not have a file name: {}", .0.display())]
     NamelessItemPath(PathBuf),
+    #[error(r#"invalid relative item path key: "{0}""#)]
+    InvalidRelativeKey(String),
+    #[error("meta blocks must be keyed by relative item paths")]
+    UnkeyedBlocks,
 }
 
 type PlexInItem<'a> = IoResult<Cow<'a, Path>>;
@@ -167,6 +172,32 @@ where
     pub fn new<II>(schema: Schema, file_path_iter: II, sorter: &Sorter) -> Self
     where
         II: IntoIterator<IntoIter = I, Item = I::Item>,
+    {
+        Self::new_in(&DiskFs, schema, file_path_iter, sorter)
+    }
+
+    /// Similar to `new`, but reads any file info needed for sorting from a
+    /// given filesystem.
+    pub fn new_in<II>(fs: &dyn Fs, schema: Schema, file_path_iter: II, sorter: &Sorter) -> Self
+    where
+        II: IntoIterator<IntoIter = I, Item = I::Item>,
+    {
+        Self::new_with(schema, file_path_iter, |paths| sorter.sort_paths_in(fs, paths))
+    }
+
+    /// Similar to `new`, but assumes that the item paths are already sorted.
+    #[cfg(feature = "async")]
+    pub(crate) fn new_presorted<II>(schema: Schema, file_path_iter: II) -> Self
+    where
+        II: IntoIterator<IntoIter = I, Item = I::Item>,
+    {
+        Self::new_with(schema, file_path_iter, |_| {})
+    }
+
+    fn new_with<II, F>(schema: Schema, file_path_iter: II, sort_func: F) -> Self
+    where
+        II: IntoIterator<IntoIter = I, Item = I::Item>,
+        F: FnOnce(&mut [Cow<'a, Path>]),
     {
         let file_path_iter = file_path_iter.into_iter();
 
@@ -186,7 +217,7 @@ where
                     }
                 }
 
-                sorter.sort_paths(&mut paths);
+                sort_func(&mut paths);
 
                 let plex_seq = PlexSeq {
                     block_iter: mb_seq.into_iter(),
@@ -201,6 +232,106 @@ where
     }
 }
 
+/// Resolves a key of the form `DIR/SUB_DIR/ITEM` into an item path inside of
+/// a root directory. Keys must be relative, and cannot refer to the root
+/// directory itself or contain parent directory components.
+pub(crate) fn resolve_relative_key(root_dir_path: &Path, key: &str) -> Result<PathBuf, Error> {
+    let mut item_path = root_dir_path.to_path_buf();
+    let mut is_empty = true;
+
+    for component in Path::new(key).components() {
+        match component {
+            Component::Normal(name) => {
+                item_path.push(name);
+                is_empty = false;
+            },
+            Component::CurDir => {},
+            _ => return Err(Error::InvalidRelativeKey(key.into())),
+        }
+    }
+
+    if is_empty {
+        return Err(Error::InvalidRelativeKey(key.into()));
+    }
+
+    Ok(item_path)
+}
+
+/// Assigns meta blocks to item paths for a schema that is keyed by item paths
+/// relative to a root directory, instead of by item file names. Unlike with
+/// `Plexer`, item paths are found from the keys of the meta blocks, so not
+/// every item path needs to have a meta block. Meta blocks with keys that do
+/// not refer to a selected item path are reported as unused.
+pub struct PlexRooted<'a> {
+    root_dir_path: &'a Path,
+    selection: &'a Selection,
+    fs: &'a dyn Fs,
+    blocks: Option<BlockMapIntoIter>,
+}
+
+impl<'a> PlexRooted<'a> {
+    pub fn new(schema: Schema, root_dir_path: &'a Path, selection: &'a Selection) -> Self {
+        Self::new_in(&DiskFs, schema, root_dir_path, selection)
+    }
+
+    /// Similar to `new`, but checks item paths against a given filesystem.
+    pub fn new_in(fs: &'a dyn Fs, schema: Schema, root_dir_path: &'a Path, selection: &'a Selection) -> Self {
+        let blocks = Self::keyed_blocks(schema).ok().map(IntoIterator::into_iter);
+
+        Self { root_dir_path, selection, fs, blocks, }
+    }
+
+    /// Returns the meta blocks of a schema keyed by relative item paths. Only
+    /// map schemas are supported for root meta files.
+    pub(crate) fn keyed_blocks(schema: Schema) -> Result<BlockMap, Error> {
+        match schema {
+            Schema::Map(mb_map) => Ok(mb_map),
+            Schema::One(..) | Schema::Seq(..) => Err(Error::UnkeyedBlocks),
+        }
+    }
+
+    /// Decides the result of plexing a meta block, given the item path that
+    /// its key resolves to and whether that item path is selected. Blocks for
+    /// item paths that are not selected or that do not exist are unused.
+    pub(crate) fn selected_block(
+        item_path: PathBuf,
+        block: Block,
+        key: String,
+        sel_res: IoResult<bool>,
+    ) -> Result<(PathBuf, Block), Error> {
+        match sel_res {
+            Ok(true) => Ok((item_path, block)),
+            Ok(false) => Err(Error::UnusedTaggedBlock(block, key)),
+            Err(err) if err.kind() == IoErrorKind::NotFound => Err(Error::UnusedTaggedBlock(block, key)),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+}
+
+impl<'a> Iterator for PlexRooted<'a> {
+    type Item = PlexOutItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, block) = match &mut self.blocks {
+            Some(blocks) => blocks.next()?,
+            None => {
+                // Report the unsupported schema once, and then stop.
+                self.blocks = Some(BlockMap::default().into_iter());
+                return Some(Err(Error::UnkeyedBlocks));
+            },
+        };
+
+        let item_path = match resolve_relative_key(self.root_dir_path, &key) {
+            Ok(p) => p,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let sel_res = self.selection.is_selected_from_in(self.fs, self.root_dir_path, &item_path);
+
+        Some(Self::selected_block(item_path, block, key, sel_res).map(|(p, b)| (Cow::Owned(p), b)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
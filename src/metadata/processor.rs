@@ -2,14 +2,20 @@
 
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::path::Path;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 
-use crate::config::{Selection, Sorter, FormatError};
-use crate::metadata::plexer::{Error as PlexerError, Plexer};
-use crate::sources::{SourceError, Source, Sourcer};
+use crate::config::{Config, Merger, Selection, Sorter, FormatError, VirtualFields};
+use crate::config::merger::{Directives, Error as MergerError};
+use crate::config::virtual_fields::SiblingIndices;
+use crate::fs::{DiskFs, Fs};
+use crate::metadata::cache::{Cache, DEFAULT_CAPACITY};
+use crate::metadata::plexer::{Error as PlexerError, PlexRooted, Plexer};
+use crate::sources::{Anchor, SourceError, Source, Sourcer};
 use crate::types::Block;
+use crate::util::file_walker::ChildFileWalker;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -21,10 +27,24 @@ pub enum Error {
     CannotFindMetaPath(#[source] SourceError),
     #[error("plexing error: {0}")]
     PlexerError(#[source] PlexerError),
-    #[error("missing metadata")]
-    MissingMetadata,
+    #[error(r#"meta file "{}" from source "{2}" has no metadata for item file "{}""#, .1.display(), .0.display())]
+    MissingMetadata(PathBuf, PathBuf, String),
+    #[error("cannot walk item tree: {0}")]
+    CannotWalkTree(#[source] IoError),
+    #[error(r#"cannot process item file "{}": {1}"#, .0.display())]
+    CannotProcessItem(PathBuf, #[source] Box<Error>),
+    #[error("meta file does not match any source: {}", .0.display())]
+    UnknownMetaFile(PathBuf),
+    #[error(r#"cannot merge meta file "{}": {1}"#, .0.display())]
+    CannotMerge(PathBuf, #[source] MergerError),
+    #[error("cannot compute virtual fields: {0}")]
+    CannotComputeVirtualFields(#[source] IoError),
 }
 
+/// The result of processing the metadata of an item file, as part of a group
+/// of item files.
+pub type ProcessedItem = Result<(PathBuf, Block), Error>;
+
 pub struct Processor;
 
 impl Processor {
@@ -37,16 +57,57 @@ impl Processor {
         selection: &'a Selection,
         sorter: &'a Sorter,
     ) -> Result<HashMap<Cow<'a, Path>, Block>, Error> {
-        let schema = source.read_schema(meta_path).map_err(Error::CannotReadMetadata)?;
+        Self::process_meta_file_in(&DiskFs, meta_path, source, selection, sorter)
+    }
+
+    /// Similar to `process_meta_file`, but reads from a given filesystem.
+    pub fn process_meta_file_in<'a>(
+        fs: &'a dyn Fs,
+        meta_path: &'a Path,
+        source: &'a Source,
+        selection: &'a Selection,
+        sorter: &'a Sorter,
+    ) -> Result<HashMap<Cow<'a, Path>, Block>, Error> {
+        Self::process_meta_file_within_in(fs, None, meta_path, source, selection, sorter)
+    }
+
+    /// Similar to `process_meta_file_in`, but for a meta file found while
+    /// walking from an origin directory, so that ignore files up to the origin
+    /// apply when selecting its item paths.
+    fn process_meta_file_within_in<'a>(
+        fs: &'a dyn Fs,
+        origin_dir_path: Option<&Path>,
+        meta_path: &'a Path,
+        source: &'a Source,
+        selection: &'a Selection,
+        sorter: &'a Sorter,
+    ) -> Result<HashMap<Cow<'a, Path>, Block>, Error> {
+        let schema = source.read_schema_in(fs, meta_path).map_err(Error::CannotReadMetadata)?;
+
+        // Root meta files provide their own item paths, as relative keys.
+        if let Anchor::Root = source.anchor {
+            let root_dir_path = source
+                .meta_target_dir(meta_path)
+                .map_err(Error::CannotFindItemPaths)?;
+
+            let mut meta_plexed = HashMap::new();
+
+            for meta_plex_res in PlexRooted::new_in(fs, schema, &root_dir_path, selection) {
+                let (item_path, meta_block) = meta_plex_res.map_err(Error::PlexerError)?;
+                meta_plexed.insert(Cow::Owned(item_path.into_owned()), meta_block);
+            }
+
+            return Ok(meta_plexed);
+        }
 
         // LEARN: Since `meta_path` is already a ref, no need to add `&`!
         let sel_item_paths = source
-            .selected_item_paths(meta_path, selection)
+            .selected_item_paths_within_in(fs, origin_dir_path, meta_path, selection)
             .map_err(Error::CannotFindItemPaths)?;
 
         let mut meta_plexed = HashMap::new();
 
-        let meta_plexer = Plexer::new(schema, sel_item_paths, &sorter);
+        let meta_plexer = Plexer::new_in(fs, schema, sel_item_paths, sorter);
 
         for meta_plex_res in meta_plexer {
             let (item_path, meta_block) = meta_plex_res.map_err(Error::PlexerError)?;
@@ -56,38 +117,594 @@ impl Processor {
         Ok(meta_plexed)
     }
 
+    /// Similar to `process_meta_file_in`, but produces owned item file paths.
+    /// If given, ignore files up to an origin directory are used, see
+    /// `process_meta_file_within_in`.
+    pub(crate) fn process_meta_file_owned_in(
+        fs: &dyn Fs,
+        origin_dir_path: Option<&Path>,
+        meta_path: &Path,
+        source: &Source,
+        selection: &Selection,
+        sorter: &Sorter,
+    ) -> Result<HashMap<PathBuf, Block>, Error> {
+        Ok(
+            Self::process_meta_file_within_in(fs, origin_dir_path, meta_path, source, selection, sorter)?
+            .into_iter()
+            .map(|(p, b)| (p.into_owned(), b))
+            .collect()
+        )
+    }
+
     /// Processes metadata for a target item file.
     /// This performs the necessary merging of all metadata across different
     /// targets that may provide data for this item file. Merging is done in a
     /// "combine-last" fashion; if a later target produces the same metadata key
     /// as an earlier target, the later one wins and overwrites the earlier one.
+    /// To use other merge strategies or add virtual fields, use
+    /// `process_item_file_with_config`.
     pub fn process_item_file(
         item_path: &Path,
         sourcer: &Sourcer,
         selection: &Selection,
         sorter: &Sorter,
     ) -> Result<Block, Error> {
-        let mut comp_mb = Block::new();
+        Self::process_item_file_in(&DiskFs, item_path, sourcer, selection, sorter)
+    }
 
-        let meta_paths = sourcer.meta_paths(item_path);
+    /// Similar to `process_item_file`, but reads from a given filesystem.
+    pub fn process_item_file_in(
+        fs: &dyn Fs,
+        item_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+    ) -> Result<Block, Error> {
+        let merger = Merger::default();
+        let virtual_fields = VirtualFields::default();
 
-        for mps_res in meta_paths {
-            let (meta_path, source) = mps_res.map_err(Error::CannotFindMetaPath)?;
+        Self::merge_item_file_in(fs, item_path, sourcer, selection, sorter, &merger, &virtual_fields)
+            .map(|(block, _)| block)
+    }
 
+    /// Processes metadata for a target item file, using all of the options in
+    /// a `Config`. Merging is done in source order, using the strategies
+    /// provided by the `Merger`. If enabled, virtual fields are added
+    /// afterwards. If the `Selection` has a root, as in a `Library`, meta
+    /// files outside of the root are not used, other than ones from overlay
+    /// sources, and `depth` is relative to the root. Otherwise, `depth` is
+    /// not included.
+    pub fn process_item_file_with_config(item_path: &Path, config: &Config) -> Result<Block, Error> {
+        Self::process_item_file_with_config_in(&DiskFs, item_path, config)
+    }
+
+    /// Similar to `process_item_file_with_config`, but reads from a given
+    /// filesystem.
+    pub fn process_item_file_with_config_in(fs: &dyn Fs, item_path: &Path, config: &Config) -> Result<Block, Error> {
+        Self::process_item_file_with_provenance_in(fs, item_path, config).map(|(block, _)| block)
+    }
+
+    /// Similar to `process_item_file_with_config`, but also returns the merge
+    /// directives of the item file. These record which meta files deleted
+    /// keys with tombstones, and which ones made keys final.
+    pub fn process_item_file_with_provenance(item_path: &Path, config: &Config) -> Result<(Block, Directives), Error> {
+        Self::process_item_file_with_provenance_in(&DiskFs, item_path, config)
+    }
+
+    /// Similar to `process_item_file_with_provenance`, but reads from a given
+    /// filesystem.
+    pub fn process_item_file_with_provenance_in(
+        fs: &dyn Fs,
+        item_path: &Path,
+        config: &Config,
+    ) -> Result<(Block, Directives), Error> {
+        Self::merge_item_file_in(
+            fs,
+            item_path,
+            &config.sourcer,
+            &config.selection,
+            &config.sorter,
+            &config.merger,
+            &config.virtual_fields,
+        )
+    }
+
+    fn merge_item_file_in(
+        fs: &dyn Fs,
+        item_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        virtual_fields: &VirtualFields,
+    ) -> Result<(Block, Directives), Error> {
+        let norm_item_path = sourcer.normalize_in(fs, item_path);
+        let item_path: &Path = &norm_item_path;
+
+        let root_path = selection.root();
+
+        let meta_paths = sourcer
+            .meta_paths_in(fs, item_path)
+            .filter(|res| match res {
+                Ok((meta_path, source)) => Self::is_meta_path_used(root_path, meta_path, source),
+                Err(_) => true,
+            });
+
+        let (mut block, directives) = Self::merge_meta_path_blocks(item_path, meta_paths, merger, |meta_path, source| {
             let mut processed_meta_file =
-                Self::process_meta_file(&meta_path, source, selection, sorter)?;
+                Self::process_meta_file_in(fs, meta_path, source, selection, sorter)?;
 
             // The results of processing a meta file will often return extra
             // metadata for item files besides the targeted one. Extract the
             // target item file's metadata, and drop the remaining results.
-            if let Some(meta_block) = processed_meta_file.remove(item_path) {
-                comp_mb.extend(meta_block)
-            } else {
-                Err(Error::MissingMetadata)?
+            Ok(processed_meta_file.remove(item_path))
+        })?;
+
+        virtual_fields
+            .inject_in(fs, &mut block, item_path, root_path, selection, sorter)
+            .map_err(Error::CannotComputeVirtualFields)?;
+
+        Ok((block, directives))
+    }
+
+    /// Returns true if a meta file is used for item files inside of a root
+    /// directory. Overlay meta files live outside of the root by design. Root
+    /// meta files are not searched for above the root, and tree walks never
+    /// look outside of it, so this only excludes the meta files of the root
+    /// item itself that are in the parent directory of the root.
+    fn is_meta_path_used(root_path: Option<&Path>, meta_path: &Path, source: &Source) -> bool {
+        match root_path {
+            Some(root_path) => source.overlay().is_some() || meta_path.starts_with(root_path),
+            None => true,
+        }
+    }
+
+    /// Processes metadata for all selected item files in a directory tree,
+    /// starting at and including a root item path. Item files are visited
+    /// depth-first, in the order given by the `Sorter`. Each meta file is only
+    /// read and plexed once, no matter how many item files it provides
+    /// metadata for. Item files that do not pass the metadata filters of the
+    /// `Selection` are skipped, and excluded directories are not walked into.
+    pub fn process_tree<'a>(
+        root_path: &'a Path,
+        sourcer: &'a Sourcer,
+        selection: &'a Selection,
+        sorter: &'a Sorter,
+        merger: &'a Merger,
+        virtual_fields: &'a VirtualFields,
+    ) -> ProcessTree<'a> {
+        Self::process_tree_in(&DiskFs, root_path, sourcer, selection, sorter, merger, virtual_fields)
+    }
+
+    /// Similar to `process_tree`, but walks a given filesystem.
+    pub fn process_tree_in<'a>(
+        fs: &'a dyn Fs,
+        root_path: &'a Path,
+        sourcer: &'a Sourcer,
+        selection: &'a Selection,
+        sorter: &'a Sorter,
+        merger: &'a Merger,
+        virtual_fields: &'a VirtualFields,
+    ) -> ProcessTree<'a> {
+        let root_path = sourcer.normalize_in(fs, root_path);
+
+        ProcessTree {
+            fs,
+            walker: ChildFileWalker::from_cow(root_path.clone()),
+            root_path,
+            sourcer,
+            selection,
+            sorter,
+            merger,
+            virtual_fields,
+            plexed: HashMap::new(),
+            sibling_counts: HashMap::new(),
+            pending_err: None,
+        }
+    }
+
+    /// Similar to `process_tree_in`, but does not include the root item path,
+    /// only its descendants.
+    pub(crate) fn process_subtrees_in<'a>(
+        fs: &'a dyn Fs,
+        root_path: &'a Path,
+        sourcer: &'a Sourcer,
+        selection: &'a Selection,
+        sorter: &'a Sorter,
+        merger: &'a Merger,
+        virtual_fields: &'a VirtualFields,
+    ) -> ProcessTree<'a> {
+        let mut process_tree =
+            Self::process_tree_in(fs, root_path, sourcer, selection, sorter, merger, virtual_fields);
+
+        // Visit the root item path without processing it, and queue up its children.
+        process_tree.walker.next();
+        if let Err(err) = process_tree.walker.delve_in(process_tree.fs, selection, sorter) {
+            process_tree.pending_err = Some(Error::CannotWalkTree(err));
+        }
+
+        process_tree
+    }
+
+    /// Finds all of the item files that a meta file provides metadata for, and
+    /// processes the final metadata of each of them, after merging with all
+    /// other sources. This is useful to see the full effect of editing a meta
+    /// file. The sources that the meta file belongs to are determined by its
+    /// file name. Item files are returned in the order given by the `Sorter`,
+    /// with errors reported per item file.
+    pub fn process_impacted_item_files(
+        meta_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        virtual_fields: &VirtualFields,
+    ) -> Result<Vec<ProcessedItem>, Error> {
+        Self::process_impacted_item_files_in(&DiskFs, meta_path, sourcer, selection, sorter, merger, virtual_fields)
+    }
+
+    /// Similar to `process_impacted_item_files`, but accesses a given filesystem.
+    pub fn process_impacted_item_files_in(
+        fs: &dyn Fs,
+        meta_path: &Path,
+        sourcer: &Sourcer,
+        selection: &Selection,
+        sorter: &Sorter,
+        merger: &Merger,
+        virtual_fields: &VirtualFields,
+    ) -> Result<Vec<ProcessedItem>, Error> {
+        let norm_meta_path = sourcer.normalize_in(fs, meta_path);
+        let meta_path: &Path = &norm_meta_path;
+
+        let sources = sourcer
+            .as_sources()
+            .iter()
+            .filter(|source| source.is_meta_path_match(meta_path))
+            .collect::<Vec<_>>();
+
+        if sources.is_empty() {
+            return Err(Error::UnknownMetaFile(meta_path.into()));
+        }
+
+        let mut item_paths = Vec::new();
+
+        for source in sources {
+            if let Anchor::Root = source.anchor {
+                let item_blocks = Self::process_meta_file_owned_in(fs, None, meta_path, source, selection, sorter)?;
+                item_paths.extend(item_blocks.into_keys().map(Ok));
+                continue;
+            }
+
+            let sel_item_paths = source
+                .selected_item_paths_in(fs, meta_path, selection)
+                .map_err(Error::CannotFindItemPaths)?;
+
+            item_paths.extend(sel_item_paths.map(|res| res.map(Cow::into_owned)));
+        }
+
+        sorter.sort_path_results_in(fs, &mut item_paths);
+
+        // Item files that share meta files with each other are common here, so
+        // use an unbounded cache to only plex each meta file once.
+        let mut cache = Cache::with_capacity(selection, sorter, usize::MAX);
+        let mut siblings = SiblingIndices::new(selection, sorter);
+
+        let impacted = item_paths
+            .into_iter()
+            .map(|res| {
+                let item_path = res
+                    .map_err(|io| Error::CannotFindItemPaths(SourceError::IterDir(io)))?;
+
+                let comp_res = Self::merge_item_blocks(fs, &item_path, sourcer, merger, |mp, source| {
+                    cache.item_block_in(fs, &item_path, mp, source)
+                })
+                .and_then(|(mut block, _)| {
+                    virtual_fields
+                        .inject_with_siblings_in(fs, &mut block, &item_path, None, &mut siblings)
+                        .map_err(Error::CannotComputeVirtualFields)?;
+                    Ok(block)
+                });
+
+                match comp_res {
+                    Ok(block) => Ok((item_path, block)),
+                    Err(err) => Err(Error::CannotProcessItem(item_path, Box::new(err))),
+                }
+            })
+            .collect();
+
+        Ok(impacted)
+    }
+
+    /// Merges the metadata blocks for a target item file across all sources,
+    /// using a callback to obtain the block that a meta file provides. Returns
+    /// the merged block, along with the merge directives that were applied.
+    pub(crate) fn merge_item_blocks<F>(
+        fs: &dyn Fs,
+        item_path: &Path,
+        sourcer: &Sourcer,
+        merger: &Merger,
+        item_block_func: F,
+    ) -> Result<(Block, Directives), Error>
+    where
+        F: FnMut(&Path, &Source) -> Result<Option<Block>, Error>,
+    {
+        Self::merge_meta_path_blocks(item_path, sourcer.meta_paths_in(fs, item_path), merger, item_block_func)
+    }
+
+    /// Similar to `merge_item_blocks`, but uses an explicit iterator of meta
+    /// file paths, instead of all of the ones found by a `Sourcer`.
+    pub(crate) fn merge_meta_path_blocks<'s, I, F>(
+        item_path: &Path,
+        meta_paths: I,
+        merger: &Merger,
+        mut item_block_func: F,
+    ) -> Result<(Block, Directives), Error>
+    where
+        I: Iterator<Item = Result<(PathBuf, &'s Source), SourceError>>,
+        F: FnMut(&Path, &Source) -> Result<Option<Block>, Error>,
+    {
+        let mut comp_mb = Block::new();
+        let mut directives = Directives::default();
+
+        for mps_res in meta_paths {
+            let (meta_path, source) = mps_res.map_err(Error::CannotFindMetaPath)?;
+
+            match item_block_func(&meta_path, source)? {
+                Some(meta_block) => {
+                    merger
+                        .merge_directed(&mut comp_mb, meta_block, &meta_path, &mut directives)
+                        .map_err(|err| Error::CannotMerge(meta_path.clone(), err))?;
+                },
+
+                // The meta file does not provide anything for this item file,
+                // such as when the item file is not selected. If allowed, just
+                // treat this source as contributing nothing. Root meta files
+                // are not expected to provide metadata for every item file.
+                None if merger.skip_missing || source.anchor == Anchor::Root => {},
+                None => {
+                    return Err(Error::MissingMetadata(
+                        item_path.into(),
+                        meta_path,
+                        source.name.clone(),
+                    ));
+                },
+            }
+        }
+
+        Ok((comp_mb, directives))
+    }
+}
+
+/// An iterator over the item files in a directory tree and their metadata.
+/// Created by `Processor::process_tree`.
+pub struct ProcessTree<'a> {
+    fs: &'a dyn Fs,
+    root_path: Cow<'a, Path>,
+    walker: ChildFileWalker<'a>,
+    sourcer: &'a Sourcer,
+    selection: &'a Selection,
+    sorter: &'a Sorter,
+    merger: &'a Merger,
+    virtual_fields: &'a VirtualFields,
+    // Plexed meta file results that still have unvisited item files.
+    plexed: HashMap<(PathBuf, Anchor), HashMap<PathBuf, Block>>,
+    // Number of children visited so far for each directory. Children are
+    // walked in sorted order, so this gives the sibling index of each item.
+    sibling_counts: HashMap<PathBuf, usize>,
+    pending_err: Option<Error>,
+}
+
+impl<'a> ProcessTree<'a> {
+    fn process_item_file(&mut self, item_path: &Path) -> Result<Block, Error> {
+        // Count this item file among its siblings before processing it, so
+        // that item files that fail to process still take up their positions.
+        let sibling_index = self.next_sibling_index(item_path);
+
+        let fs = self.fs;
+        let plexed = &mut self.plexed;
+        let selection = self.selection;
+        let sorter = self.sorter;
+        let root_path: &Path = &self.root_path;
+        let meta_paths = self.sourcer.meta_paths_in(fs, item_path);
+
+        let (mut block, _) = Processor::merge_meta_path_blocks(item_path, meta_paths, self.merger, |meta_path, source| {
+            let key = (meta_path.to_path_buf(), source.anchor);
+
+            let item_blocks = match plexed.get_mut(&key) {
+                Some(item_blocks) => item_blocks,
+                None => {
+                    let item_blocks =
+                        Processor::process_meta_file_owned_in(fs, Some(root_path), meta_path, source, selection, sorter)?;
+
+                    plexed.entry(key.clone()).or_insert(item_blocks)
+                },
+            };
+
+            // Each item file is only visited once, so its block can be taken.
+            // Once all of the blocks of a meta file are used, drop the entry.
+            // Root meta files are kept, as they are looked up by many item
+            // files that they do not provide blocks for.
+            let item_block = item_blocks.remove(item_path);
+
+            if item_blocks.is_empty() && source.anchor != Anchor::Root {
+                plexed.remove(&key);
             }
+
+            Ok(item_block)
+        })?;
+
+        let root_path = Some(self.root_path.as_ref());
+
+        let inject_res = match sibling_index {
+            Some(index) => self.virtual_fields.inject_with_index(self.fs, &mut block, item_path, root_path, index),
+
+            // The root item path is the only one whose siblings are not walked.
+            None => self.virtual_fields.inject_in(self.fs, &mut block, item_path, root_path, self.selection, self.sorter),
+        };
+
+        inject_res.map_err(Error::CannotComputeVirtualFields)?;
+
+        Ok(block)
+    }
+
+    /// Returns the sibling index of a walked item file, or `None` for the root
+    /// item path. Children are walked in sorted order, so this is found by
+    /// counting the children visited so far in each directory.
+    fn next_sibling_index(&mut self, item_path: &Path) -> Option<Option<usize>> {
+        if item_path == self.root_path.as_ref() {
+            return None;
         }
 
-        Ok(comp_mb)
+        let index = item_path.parent().map(|parent_dir_path| {
+            let count = self.sibling_counts.entry(parent_dir_path.to_path_buf()).or_insert(0);
+            *count += 1;
+            *count - 1
+        });
+
+        Some(index)
+    }
+}
+
+impl<'a> Iterator for ProcessTree<'a> {
+    type Item = ProcessedItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(err) = self.pending_err.take() {
+                return Some(Err(err));
+            }
+
+            let item_path = match self.walker.next()? {
+                Ok(item_path) => item_path.into_owned(),
+                Err(err) => return Some(Err(Error::CannotWalkTree(err))),
+            };
+
+            let res = self.process_item_file(&item_path)
+                .map_err(|err| Error::CannotProcessItem(item_path.clone(), Box::new(err)));
+
+            let meta_filters = self.selection.meta_filters();
+
+            // Queue up the children of this item file, if it is a directory.
+            // Directories excluded by their metadata are not walked into.
+            let is_excluded = matches!(&res, Ok(block) if meta_filters.is_excluded(block));
+            if !is_excluded {
+                if let Err(err) = self.walker.delve_in(self.fs, self.selection, self.sorter) {
+                    self.pending_err = Some(Error::CannotWalkTree(err));
+                }
+            }
+
+            match res {
+                Ok(block) if !meta_filters.is_match(&block) => continue,
+                res => return Some(res.map(|block| (item_path, block))),
+            }
+        }
+    }
+}
+
+/// A reusable processor that caches the results of processing meta files.
+/// This is useful when looking up many item files that share meta files, such
+/// as all of the tracks in an album, as each meta file only needs to be read
+/// and plexed once for as long as it remains unchanged.
+pub struct CachedProcessor<'c> {
+    fs: &'c dyn Fs,
+    config: &'c Config,
+    cache: Cache<'c>,
+    siblings: SiblingIndices<'c>,
+}
+
+impl<'c> CachedProcessor<'c> {
+    pub fn new(config: &'c Config) -> Self {
+        Self::new_in(&DiskFs, config)
+    }
+
+    /// Similar to `new`, but accesses a given filesystem.
+    pub fn new_in(fs: &'c dyn Fs, config: &'c Config) -> Self {
+        Self::with_capacity_in(fs, config, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new processor whose cache holds at most `capacity` bytes of
+    /// meta files.
+    pub fn with_capacity(config: &'c Config, capacity: usize) -> Self {
+        Self::with_capacity_in(&DiskFs, config, capacity)
+    }
+
+    /// Similar to `with_capacity`, but accesses a given filesystem.
+    pub fn with_capacity_in(fs: &'c dyn Fs, config: &'c Config, capacity: usize) -> Self {
+        let cache = Cache::with_capacity(&config.selection, &config.sorter, capacity);
+        let siblings = SiblingIndices::new(&config.selection, &config.sorter);
+        Self { fs, config, cache, siblings, }
+    }
+
+    pub fn cache(&self) -> &Cache<'c> {
+        &self.cache
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+        self.siblings.clear();
+    }
+
+    /// Processes metadata for a target item file, in the same way as
+    /// `Processor::process_item_file`, but reusing cached meta file results.
+    /// Each call is its own batch of lookups, see `Cache::start_batch`.
+    pub fn process_item_file(&mut self, item_path: &Path) -> Result<Block, Error> {
+        self.process_item_file_with_provenance(item_path).map(|(block, _)| block)
+    }
+
+    /// Similar to `process_item_file`, but also returns the merge directives
+    /// of the item file, see `Processor::process_item_file_with_provenance`.
+    pub fn process_item_file_with_provenance(&mut self, item_path: &Path) -> Result<(Block, Directives), Error> {
+        self.start_batch();
+        self.process_item_file_in_batch(item_path)
+    }
+
+    /// Processes metadata for several item files as a single batch of lookups,
+    /// see `Cache::start_batch`. This avoids checking the stats of the same
+    /// item files again for each item file, such as when sorting by mod time.
+    /// Results are returned in the given order, with errors reported per item
+    /// file.
+    pub fn process_item_files<I, P>(&mut self, item_paths: I) -> Vec<ProcessedItem>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.start_batch();
+
+        item_paths
+            .into_iter()
+            .map(|item_path| {
+                let item_path = item_path.as_ref();
+
+                match self.process_item_file_in_batch(item_path) {
+                    Ok((block, _)) => Ok((item_path.to_path_buf(), block)),
+                    Err(err) => Err(Error::CannotProcessItem(item_path.to_path_buf(), Box::new(err))),
+                }
+            })
+            .collect()
+    }
+
+    fn start_batch(&mut self) {
+        self.cache.start_batch();
+        self.siblings.start_batch();
+    }
+
+    fn process_item_file_in_batch(&mut self, item_path: &Path) -> Result<(Block, Directives), Error> {
+        let fs = self.fs;
+        let config = self.config;
+        let cache = &mut self.cache;
+
+        let norm_item_path = config.sourcer.normalize_in(fs, item_path);
+        let item_path: &Path = &norm_item_path;
+
+        let (mut block, directives) = Processor::merge_item_blocks(fs, item_path, &config.sourcer, &config.merger, |meta_path, source| {
+            cache.item_block_in(fs, item_path, meta_path, source)
+        })?;
+
+        config.virtual_fields
+            .inject_with_siblings_in(fs, &mut block, item_path, None, &mut self.siblings)
+            .map_err(Error::CannotComputeVirtualFields)?;
+
+        Ok((block, directives))
     }
 }
 
@@ -98,22 +715,37 @@ mod tests {
     use maplit::{btreemap, hashmap};
     use str_macro::str;
 
-    use crate::config::selection::Matcher;
-    use crate::sources::Anchor;
+    use crate::config::merger::MergeStrategy;
+    use crate::config::sorter::SortBy;
+    use crate::config::selection::{FsFilters, Matcher, MetaFilters};
+    use crate::fs::MemoryFs;
+    use crate::library::Library;
+    use crate::sources::{Anchor, Normalization, Overlay, SidecarMode};
+    use crate::types::Value;
 
     use crate::test_util::TestUtil as TU;
 
+    /// Processes a tree using the parts of a config, and collects the results.
+    fn process_tree_in(fs: &dyn Fs, root_path: &Path, config: &Config) -> Vec<(PathBuf, Block)> {
+        Processor::process_tree_in(
+            fs,
+            root_path,
+            &config.sourcer,
+            &config.selection,
+            &config.sorter,
+            &config.merger,
+            &config.virtual_fields,
+        )
+        .map(Result::unwrap)
+        .collect()
+    }
+
     #[test]
     fn process_meta_file() {
         let temp_dir = TU::create_temp_media_test_dir("process_meta_file");
         let path = temp_dir.path();
 
-        let selection = Selection::new(
-            Matcher::any(),
-            Matcher::build(&["*.json"]).unwrap(),
-            Matcher::any(),
-            Matcher::empty(),
-        );
+        let selection = TU::sample_selection();
         let sorter = Sorter::default();
 
         // Success cases
@@ -249,4 +881,870 @@ mod tests {
             assert_eq!(expected, produced);
         }
     }
+
+    #[test]
+    fn process_item_file_with_config() {
+        let temp_dir = TU::create_temp_media_test_dir("process_item_file_with_config");
+        let path = temp_dir.path();
+
+        let config = TU::sample_config();
+
+        let item_paths = vec![
+            path.to_owned(),
+            path.join("ALBUM_01"),
+            path.join("ALBUM_01").join("DISC_01").join("TRACK_01.flac"),
+        ];
+
+        for item_path in item_paths {
+            let expected = Processor::process_item_file(
+                &item_path,
+                &config.sourcer,
+                &config.selection,
+                &config.sorter,
+            )
+            .unwrap();
+            let produced = Processor::process_item_file_with_config(&item_path, &config).unwrap();
+            assert_eq!(expected, produced);
+        }
+
+        // The merger of the config is used.
+        let config = Config { merger: Merger { strategy: MergeStrategy::KeepFirst, ..Default::default() }, ..config };
+        let produced = Processor::process_item_file_with_config(&path.join("ALBUM_01"), &config).unwrap();
+        assert_eq!(produced.get("overridden"), Some(&TU::s("ALBUM_01_item")));
+    }
+
+    #[test]
+    fn cached_process_item_file() {
+        let temp_dir = TU::create_temp_media_test_dir("cached_process_item_file");
+        let path = temp_dir.path();
+
+        let config = TU::sample_config();
+
+        let mut processor = CachedProcessor::new(&config);
+
+        let disc_path = path.join("ALBUM_01").join("DISC_01");
+        let track_names = vec!["TRACK_01.flac", "TRACK_02.flac", "TRACK_03.flac", "TRACK_01.flac"];
+
+        for track_name in track_names {
+            let item_path = disc_path.join(track_name);
+
+            let expected = Processor::process_item_file_with_config(&item_path, &config)
+            .unwrap();
+            let produced = processor.process_item_file(&item_path).unwrap();
+            assert_eq!(expected, produced);
+        }
+
+        // Only the disc's external meta file should have been cached.
+        assert_eq!(processor.cache().len(), 1);
+
+        let produced = processor.process_item_file(&disc_path).unwrap();
+        assert_eq!(produced.get("overridden"), Some(&TU::s("DISC_01_self")));
+        assert_eq!(processor.cache().len(), 3);
+
+        processor.clear_cache();
+        assert!(processor.cache().is_empty());
+    }
+
+    #[test]
+    fn cached_processor_in() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/ALBUM/item.json", r#"[{"track": 1}, {"track": 2}]"#).unwrap()
+            .add_file("/music/ALBUM/self.json", r#"{"title": "Album"}"#).unwrap()
+            .add_file("/music/ALBUM/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM/TRACK_02.flac", "").unwrap();
+
+        let mut config = TU::sample_config();
+        config.sourcer.normalize_with(Normalization::None);
+
+        let album_path = Path::new("/music/ALBUM");
+        let mut processor = CachedProcessor::new_in(&fs, &config);
+
+        let produced = processor.process_item_file(&album_path.join("TRACK_02.flac")).unwrap();
+        assert_eq!(produced, Block(btreemap![str!("track") => TU::i(2)]));
+        assert_eq!(processor.cache().len(), 1);
+        assert_eq!(processor.cache().size(), r#"[{"track": 1}, {"track": 2}]"#.len());
+
+        let produced = Processor::process_impacted_item_files_in(
+            &fs,
+            &album_path.join("item.json"),
+            &config.sourcer,
+            &config.selection,
+            &config.sorter,
+            &config.merger,
+            &config.virtual_fields,
+        )
+        .unwrap()
+        .into_iter()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+
+        let expected = vec![
+            (album_path.join("TRACK_01.flac"), Block(btreemap![str!("track") => TU::i(1)])),
+            (album_path.join("TRACK_02.flac"), Block(btreemap![str!("track") => TU::i(2)])),
+        ];
+
+        assert_eq!(produced, expected);
+
+        let produced = processor
+            .process_item_files(expected.iter().map(|(p, _)| p))
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        assert_eq!(produced, expected);
+        assert_eq!(processor.cache().len(), 1);
+    }
+
+    #[test]
+    fn process_tree() {
+        let temp_dir = TU::create_temp_media_test_dir("process_tree");
+        let path = temp_dir.path();
+
+        let Config { selection, sorter, sourcer, merger, virtual_fields } = TU::sample_config();
+
+        let album_path = path.join("ALBUM_01");
+
+        let produced = Processor::process_tree(&album_path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        let expected_paths = vec![
+            album_path.clone(),
+            album_path.join("DISC_01"),
+            album_path.join("DISC_01").join("TRACK_01.flac"),
+            album_path.join("DISC_01").join("TRACK_02.flac"),
+            album_path.join("DISC_01").join("TRACK_03.flac"),
+            album_path.join("DISC_02"),
+            album_path.join("DISC_02").join("TRACK_01.flac"),
+            album_path.join("DISC_02").join("TRACK_02.flac"),
+            album_path.join("DISC_02").join("TRACK_03.flac"),
+        ];
+
+        assert_eq!(
+            expected_paths,
+            produced.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>(),
+        );
+
+        for (item_path, block) in produced {
+            let expected = Processor::process_item_file(
+                &item_path,
+                &sourcer,
+                &selection,
+                &sorter,
+            )
+            .unwrap();
+            assert_eq!(expected, block);
+        }
+
+        // Errors are reported per item file, and do not stop the iteration.
+        std::fs::write(album_path.join("DISC_02").join("item.json"), "[{}]").unwrap();
+
+        let produced = Processor::process_tree(&album_path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .collect::<Vec<_>>();
+
+        assert_eq!(produced.len(), expected_paths.len());
+        for (res, expected_path) in produced.iter().zip(&expected_paths) {
+            let is_disc_02_track = expected_path.parent() == Some(&album_path.join("DISC_02"));
+
+            match res {
+                Ok((p, _)) => {
+                    assert_eq!(p, expected_path);
+                    assert!(!is_disc_02_track);
+                },
+                Err(Error::CannotProcessItem(p, _)) => {
+                    assert_eq!(p, expected_path);
+                    assert!(is_disc_02_track);
+                },
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+
+        // Virtual fields use the walk root for depth, and sorted sibling order
+        // for index.
+        let virtual_fields = VirtualFields { enabled: true, ..Default::default() };
+
+        let produced = Processor::process_tree(&album_path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .filter_map(Result::ok)
+            .map(|(p, block)| {
+                let fields = Value::Mapping(block);
+                let get = |key: &str| fields.get_key_path(&["_file", key]).cloned();
+                (p, get("depth"), get("index"))
+            })
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            (album_path.clone(), Some(TU::i(0)), Some(TU::i(0))),
+            (album_path.join("DISC_01"), Some(TU::i(1)), Some(TU::i(0))),
+            (album_path.join("DISC_01").join("TRACK_01.flac"), Some(TU::i(2)), Some(TU::i(0))),
+            (album_path.join("DISC_01").join("TRACK_02.flac"), Some(TU::i(2)), Some(TU::i(1))),
+            (album_path.join("DISC_01").join("TRACK_03.flac"), Some(TU::i(2)), Some(TU::i(2))),
+            (album_path.join("DISC_02"), Some(TU::i(1)), Some(TU::i(1))),
+        ];
+        assert_eq!(expected, produced);
+    }
+
+    #[test]
+    fn process_tree_in_memory() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/self.json", r#"{"genre": "rock"}"#).unwrap()
+            .add_file("/music/item.json", r#"{"ALBUM_01": {"year": 2000}}"#).unwrap()
+            .add_file("/music/ALBUM_01/self.json", r#"{"title": "Album"}"#).unwrap()
+            .add_file("/music/ALBUM_01/item.json", r#"[{"track": 1}, {"track": 2}]"#).unwrap()
+            .add_file("/music/ALBUM_01/TRACK_02.flac", "22").unwrap()
+            .add_file("/music/ALBUM_01/TRACK_01.flac", "1").unwrap();
+
+        let mut config = TU::sample_config();
+        // Mod times in memory follow creation order, so this is deterministic.
+        config.sorter = Sorter { sort_by: SortBy::ModTime, ..Default::default() };
+        config.virtual_fields.enabled = true;
+
+        let root_path = Path::new("/music");
+        let album_path = root_path.join("ALBUM_01");
+
+        let produced = process_tree_in(&fs, root_path, &config);
+
+        let get = |block: &Block, key_path: &[&str]| Value::Mapping(block.clone()).get_key_path(key_path).cloned();
+
+        let expected = vec![
+            (root_path.to_path_buf(), "genre", TU::s("rock")),
+            (album_path.clone(), "year", TU::i(2000)),
+            (album_path.join("TRACK_02.flac"), "track", TU::i(1)),
+            (album_path.join("TRACK_01.flac"), "track", TU::i(2)),
+        ];
+
+        assert_eq!(produced.len(), expected.len());
+        for ((item_path, block), (expected_path, key, expected_value)) in produced.iter().zip(expected) {
+            assert_eq!(item_path, &expected_path);
+            assert_eq!(get(block, &[key]), Some(expected_value));
+
+            let single = Processor::process_item_file_with_config_in(&fs, item_path, &config).unwrap();
+            assert_eq!(get(&single, &[key]), get(block, &[key]));
+            assert_eq!(get(&single, &["_file", "size"]), get(block, &["_file", "size"]));
+        }
+
+        let (_, album_block) = &produced[1];
+        assert_eq!(get(album_block, &["title"]), Some(TU::s("Album")));
+
+        let (_, track_block) = &produced[3];
+        assert_eq!(get(track_block, &["_file", "size"]), Some(TU::i(1)));
+        assert_eq!(get(track_block, &["_file", "depth"]), Some(TU::i(2)));
+        assert_eq!(get(track_block, &["_file", "index"]), Some(TU::i(1)));
+
+        // Nothing here touches the real filesystem.
+        assert!(matches!(
+            Processor::process_item_file_with_config(&album_path.join("TRACK_01.flac"), &config),
+            Err(Error::CannotFindMetaPath(SourceError::ItemAccess(..))),
+        ));
+    }
+
+    #[test]
+    fn process_tree_meta_filters() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/item.json", r#"{"ALBUM_01": {"hidden": true}, "ALBUM_02": {}}"#).unwrap()
+            .add_file("/music/ALBUM_01/item.json", r#"[{"status": "final"}]"#).unwrap()
+            .add_file("/music/ALBUM_01/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM_02/item.json", r#"[{"status": "final"}, {"status": "draft"}, {"status": "final"}]"#).unwrap()
+            .add_file("/music/ALBUM_02/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM_02/TRACK_02.flac", "").unwrap()
+            .add_file("/music/ALBUM_02/TRACK_03.flac", "").unwrap();
+
+        let process = |config: &Config| {
+            process_tree_in(&fs, Path::new("/music"), config).into_iter().map(|(p, _)| p).collect::<Vec<_>>()
+        };
+
+        let mut config = TU::sample_config();
+        assert_eq!(process(&config).len(), 7);
+
+        // Excluded directories are not walked into. Items removed by metadata
+        // still keep their positions in sequence meta files.
+        config.selection = config.selection.with_meta_filters(MetaFilters::from_reprs(
+            btreemap![str!("status") => TU::s("final")],
+            btreemap![str!("hidden") => Value::Boolean(true)],
+        ));
+        assert_eq!(
+            process(&config),
+            vec![PathBuf::from("/music/ALBUM_02/TRACK_01.flac"), PathBuf::from("/music/ALBUM_02/TRACK_03.flac")],
+        );
+
+        config.selection = config.selection.with_meta_filters(MetaFilters::from_reprs(
+            btreemap![],
+            btreemap![str!("status") => TU::s("draft")],
+        ));
+        assert_eq!(
+            process(&config),
+            vec![
+                PathBuf::from("/music"),
+                PathBuf::from("/music/ALBUM_01"),
+                PathBuf::from("/music/ALBUM_01/TRACK_01.flac"),
+                PathBuf::from("/music/ALBUM_02"),
+                PathBuf::from("/music/ALBUM_02/TRACK_01.flac"),
+                PathBuf::from("/music/ALBUM_02/TRACK_03.flac"),
+            ],
+        );
+    }
+
+    #[test]
+    fn process_tree_ignore_files() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/.anagmaignore", "*.log").unwrap()
+            .add_file("/music/item.json", r#"{"ALBUM_01": {}}"#).unwrap()
+            .add_file("/music/ALBUM_01/item.json", r#"[{"track": 1}, {"track": 2}]"#).unwrap()
+            .add_file("/music/ALBUM_01/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM_01/TRACK_01.log", "").unwrap()
+            .add_file("/music/ALBUM_01/TRACK_02.flac", "").unwrap();
+
+        let mut config = TU::sample_config();
+        config.selection = config.selection.with_ignore_files(true);
+
+        // Without a library root, ignore files are inherited from the origin
+        // of the walk, both when walking and when plexing.
+        assert_eq!(
+            process_tree_in(&fs, Path::new("/music"), &config),
+            vec![
+                (PathBuf::from("/music"), Block::new()),
+                (PathBuf::from("/music/ALBUM_01"), Block::new()),
+                (PathBuf::from("/music/ALBUM_01/TRACK_01.flac"), Block(btreemap![str!("track") => TU::i(1)])),
+                (PathBuf::from("/music/ALBUM_01/TRACK_02.flac"), Block(btreemap![str!("track") => TU::i(2)])),
+            ],
+        );
+
+        let mut walker = ChildFileWalker::new(Path::new("/music"));
+        let mut walked = Vec::new();
+        while let Some(res) = walker.next() {
+            walked.push(res.unwrap().into_owned());
+            walker.delve_in(&fs, &config.selection, &config.sorter).unwrap();
+        }
+        assert!(!walked.contains(&PathBuf::from("/music/ALBUM_01/TRACK_01.log")));
+        assert_eq!(walked.len(), 4);
+    }
+
+    #[test]
+    fn process_tree_max_depth() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/item.json", r#"{"ALBUM_01": {"year": 2000}}"#).unwrap()
+            .add_file("/music/ALBUM_01/item.json", r#"[{"track": 1}]"#).unwrap()
+            .add_file("/music/ALBUM_01/TRACK_01.flac", "").unwrap();
+
+        let mut config = TU::sample_config();
+        config.selection = config.selection.with_filters(FsFilters { max_depth: Some(1), ..Default::default() });
+
+        // Without a library root, depth is measured from the origin of the walk.
+        let produced = process_tree_in(&fs, Path::new("/music"), &config)
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect::<Vec<_>>();
+        assert_eq!(produced, vec![PathBuf::from("/music"), PathBuf::from("/music/ALBUM_01")]);
+
+        // Single item files measure depth from the directory of each meta file.
+        let produced = Processor::process_item_file_with_config_in(&fs, Path::new("/music/ALBUM_01/TRACK_01.flac"), &config);
+        assert_eq!(produced.unwrap(), Block(btreemap![str!("track") => TU::i(1)]));
+    }
+
+    #[test]
+    fn process_tree_meta_dir() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/music/.anagma/library.json", r#"{"ALBUM_01/TRACK_01.flac": {"lib": true}}"#).unwrap()
+            .add_file("/music/.anagma/track.json", r#"{"ALBUM_01": {"year": 2000}}"#).unwrap()
+            .add_file("/music/ALBUM_01/.anagma/album.json", r#"{"title": "Album"}"#).unwrap()
+            .add_file("/music/ALBUM_01/.anagma/track.json", r#"[{"track": 1}, {"track": 2}]"#).unwrap()
+            .add_file("/music/ALBUM_01/.anagma/TRACK_02.flac.json", r#"{"sidecar": true}"#).unwrap()
+            .add_file("/music/ALBUM_01/TRACK_01.flac", "").unwrap()
+            .add_file("/music/ALBUM_01/TRACK_02.flac", "").unwrap()
+            // Meta files outside of the meta directory are ignored.
+            .add_file("/music/ALBUM_01/album.json", r#"{"title": "Stray"}"#).unwrap();
+
+        let mut config = TU::sample_config();
+        config.selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::build(&[".anagma"]).unwrap(),
+        );
+        config.sourcer = Sourcer::new();
+        for (name, anchor) in [
+            ("library.json", Anchor::Root),
+            ("track.json", Anchor::External),
+            ("album.json", Anchor::Internal),
+            ("json", Anchor::Sidecar(SidecarMode::FullName)),
+        ] {
+            let source = Source::from_name(str!(name), anchor).unwrap().with_meta_dir(str!(".anagma")).unwrap();
+            config.sourcer.source(source);
+        }
+
+        let root_path = Path::new("/music");
+        let album_path = root_path.join("ALBUM_01");
+
+        let produced = process_tree_in(&fs, root_path, &config);
+
+        let expected = vec![
+            (root_path.to_path_buf(), Block::default()),
+            (album_path.clone(), Block(btreemap![
+                str!("title") => TU::s("Album"),
+                str!("year") => TU::i(2000),
+            ])),
+            (album_path.join("TRACK_01.flac"), Block(btreemap![
+                str!("lib") => Value::Boolean(true),
+                str!("track") => TU::i(1),
+            ])),
+            (album_path.join("TRACK_02.flac"), Block(btreemap![
+                str!("sidecar") => Value::Boolean(true),
+                str!("track") => TU::i(2),
+            ])),
+        ];
+        assert_eq!(expected, produced);
+
+        let source = &config.sourcer.as_sources()[2];
+        assert!(source.is_meta_path_match(&album_path.join(".anagma").join("album.json")));
+        assert!(!source.is_meta_path_match(&album_path.join("album.json")));
+        assert!(matches!(
+            source.item_paths_in(&fs, &album_path.join("album.json")),
+            Err(SourceError::NotInMetaDir(..)),
+        ));
+    }
+
+    #[test]
+    fn process_tree_overlay() {
+        let mut fs = MemoryFs::new();
+        fs
+            .add_file("/media/ALBUM/self.json", r#"{"title": "Album", "genre": "rock"}"#).unwrap()
+            .add_file("/media/ALBUM/item.json", r#"{"TRACK.flac": {"track": 1}}"#).unwrap()
+            .add_file("/media/ALBUM/TRACK.flac", "").unwrap()
+            .add_file("/media/OTHER.flac", "").unwrap()
+            .add_file("/overlay/ALBUM/album.yml", "genre: jazz").unwrap()
+            .add_file("/overlay/ALBUM/track.yml", "TRACK.flac: {rating: 5}").unwrap()
+            .add_file("/overlay/library.yml", "ALBUM/TRACK.flac: {lib: true}").unwrap();
+
+        let overlay = Overlay::new("/media", "/overlay");
+        let mut config = TU::sample_config();
+        config.sourcer = Sourcer::new();
+        config.sourcer
+            .source(Source::from_name(str!("library.yml"), Anchor::Root).unwrap().with_overlay(overlay.clone()))
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("self.json"), Anchor::Internal).unwrap())
+            .source(Source::from_name(str!("track.yml"), Anchor::External).unwrap().with_overlay(overlay.clone()))
+            .source(Source::from_name(str!("album.yml"), Anchor::Internal).unwrap().with_overlay(overlay));
+
+        let root_path = Path::new("/media");
+        let album_path = root_path.join("ALBUM");
+
+        let produced = process_tree_in(&fs, root_path, &config);
+
+        let expected = vec![
+            (root_path.to_path_buf(), Block::default()),
+            (album_path.clone(), Block(btreemap![
+                str!("title") => TU::s("Album"),
+                str!("genre") => TU::s("jazz"),
+            ])),
+            (album_path.join("TRACK.flac"), Block(btreemap![
+                str!("lib") => Value::Boolean(true),
+                str!("track") => TU::i(1),
+                str!("rating") => TU::i(5),
+            ])),
+            (root_path.join("OTHER.flac"), Block::default()),
+        ];
+        assert_eq!(expected, produced);
+
+        // Item paths outside of the mirrored tree do not use the overlay.
+        let source = &config.sourcer.as_sources()[3];
+        assert!(matches!(
+            source.meta_path_in(&fs, Path::new("/overlay/ALBUM")),
+            Err(SourceError::ItemOutsideOverlay(..)),
+        ));
+        assert!(source.is_meta_path_match(Path::new("/overlay/ALBUM/track.yml")));
+        assert!(!source.is_meta_path_match(Path::new("/media/ALBUM/track.yml")));
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn process_item_file_archive() {
+        use std::io::Write;
+
+        use crate::fs::ArchiveFs;
+        use crate::fs::archive::archive_root_path;
+
+        let temp_dir = tempfile::Builder::new().suffix("process_item_file_archive").tempdir().unwrap();
+        let archive_path = temp_dir.path().join("album.zip");
+
+        let entries = &[
+            ("self.json", r#"{"title": "Album"}"#),
+            ("DISC_01/self.json", r#"{"disc": 1}"#),
+            ("DISC_01/item.json", r#"{"TRACK_01.flac": {"track": 1}}"#),
+            ("DISC_01/TRACK_01.flac", ""),
+        ];
+
+        let mut zip_writer = zip::ZipWriter::new(std::fs::File::create(&archive_path).unwrap());
+        for (name, contents) in entries {
+            zip_writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip_writer.write_all(contents.as_bytes()).unwrap();
+        }
+        zip_writer.finish().unwrap();
+
+        let config = TU::sample_config();
+
+        let fs = ArchiveFs::new();
+        let root_path = archive_root_path(&archive_path);
+
+        let produced = process_tree_in(&fs, &root_path, &config);
+
+        let expected = vec![
+            (root_path.clone(), Block(btreemap![str!("title") => TU::s("Album")])),
+            (root_path.join("DISC_01"), Block(btreemap![str!("disc") => TU::i(1)])),
+            (root_path.join("DISC_01").join("TRACK_01.flac"), Block(btreemap![str!("track") => TU::i(1)])),
+        ];
+        assert_eq!(expected, produced);
+
+        let produced = Processor::process_item_file_with_config_in(&fs, &root_path.join("DISC_01").join("TRACK_01.flac"), &config)
+        .unwrap();
+        assert_eq!(produced, Block(btreemap![str!("track") => TU::i(1)]));
+    }
+
+    #[test]
+    fn process_item_file_normalization() {
+        let temp_dir = TU::create_temp_media_test_dir("process_item_file_normalization");
+        let path = temp_dir.path();
+
+        let Config { selection, sorter, mut sourcer, merger, virtual_fields } = TU::sample_config();
+
+        let album_path = path.join("ALBUM_01");
+        let dotted_album_path = album_path.join(".").join("DISC_01").join("..");
+
+        let process = |sourcer: &Sourcer, item_path: &Path| {
+            Processor::process_item_file(item_path, sourcer, &selection, &sorter)
+        };
+
+        let expected = process(&sourcer, &album_path).unwrap();
+
+        // Without normalization, the parent of the dotted path is wrong.
+        sourcer.normalize_with(Normalization::None);
+        assert!(process(&sourcer, &dotted_album_path).ok() != Some(expected.clone()));
+
+        sourcer.normalize_with(Normalization::Lexical);
+        assert_eq!(process(&sourcer, &dotted_album_path).unwrap(), expected);
+
+        sourcer.normalize_with(Normalization::Canonical);
+        assert_eq!(process(&sourcer, &dotted_album_path).unwrap(), expected);
+
+        // Canonical normalization sees through symlinked parent directories.
+        #[cfg(unix)]
+        {
+            // The link is placed outside of the media directory, so that it
+            // is not seen as an item file there.
+            let link_dir = tempfile::Builder::new().tempdir().unwrap();
+            let link_path = link_dir.path().join("LINK");
+            std::os::unix::fs::symlink(&album_path, &link_path).unwrap();
+
+            let produced = process(&sourcer, &link_path.join("DISC_01").join("TRACK_01.flac")).unwrap();
+            assert_eq!(produced, process(&sourcer, &album_path.join("DISC_01").join("TRACK_01.flac")).unwrap());
+
+            // A symlinked item file gets the metadata of the link, not of its target.
+            let track_path = album_path.join("DISC_01").join("TRACK_01.flac");
+            let expected_track = process(&sourcer, &track_path).unwrap();
+            let store_path = link_dir.path().join("x.flac");
+            std::fs::rename(&track_path, &store_path).unwrap();
+            std::os::unix::fs::symlink(&store_path, &track_path).unwrap();
+
+            assert_eq!(process(&sourcer, &track_path).unwrap(), expected_track);
+            assert_eq!(process(&sourcer, &link_path.join("DISC_01").join("TRACK_01.flac")).unwrap(), expected_track);
+
+            // The link itself is an item of the directory that it is in.
+            assert!(process(&sourcer, &link_path).ok() != Some(expected.clone()));
+        }
+
+        // The root of a tree walk is normalized, and so are the produced item paths.
+        sourcer.normalize_with(Normalization::Lexical);
+        let produced = Processor::process_tree(&dotted_album_path, &sourcer, &selection, &sorter, &merger, &virtual_fields)
+            .map(|res| res.unwrap().0)
+            .next();
+        assert_eq!(produced, Some(album_path));
+    }
+
+    #[test]
+    fn process_item_file_missing() {
+        let temp_dir = TU::create_temp_media_test_dir("process_item_file_missing");
+        let path = temp_dir.path();
+
+        // Exclude one of the tracks, so that it is not plexed.
+        let mut config = TU::sample_config();
+        config.selection = Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json", "TRACK_02*"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        );
+
+        // Use a map-based meta file, so that the remaining tracks still plex.
+        let disc_path = path.join("ALBUM_01").join("DISC_01");
+        std::fs::write(
+            disc_path.join("item.json"),
+            r#"{"TRACK_01.flac": {"key": "val_1"}, "TRACK_03.flac": {"key": "val_3"}}"#,
+        ).unwrap();
+
+        let item_path = disc_path.join("TRACK_02.flac");
+
+        match Processor::process_item_file_with_config(&item_path, &config) {
+            Err(Error::MissingMetadata(ip, mp, sn)) => {
+                assert_eq!(ip, item_path);
+                assert_eq!(mp, disc_path.join("item.json"));
+                assert_eq!(sn, "item.json");
+            },
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        config.merger.skip_missing = true;
+
+        let produced = Processor::process_item_file_with_config(&item_path, &config).unwrap();
+        assert_eq!(produced, Block::new());
+
+        let produced = Processor::process_item_file_with_config(&disc_path.join("TRACK_03.flac"), &config).unwrap();
+        assert_eq!(produced, Block(btreemap![str!("key") => TU::s("val_3")]));
+    }
+
+    #[test]
+    fn process_item_file_directives() {
+        let temp_dir = TU::create_temp_media_test_dir("process_item_file_directives");
+        let path = temp_dir.path();
+
+        let mut config = TU::sample_config();
+        config.merger.tombstone = str!("!delete");
+        config.merger.final_suffix = str!("!");
+        config.sourcer = Sourcer::new();
+        config.sourcer
+            .source(Source::from_name(str!("self.json"), Anchor::Internal).unwrap())
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap());
+
+        let album_path = path.join("ALBUM_01");
+        let meta_path = album_path.join("self.json");
+
+        // Later sources cannot change final keys.
+        std::fs::write(&meta_path, r#"{"overridden!": "final_val"}"#).unwrap();
+
+        match Processor::process_item_file_with_config(&album_path, &config) {
+            Err(Error::CannotMerge(mp, MergerError::FinalKey { key, origin, final_origin })) => {
+                assert_eq!(mp, path.join("item.json"));
+                assert_eq!(key, "overridden");
+                assert_eq!(origin, mp);
+                assert_eq!(final_origin, meta_path);
+            },
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // Tombstones remove keys set by earlier sources.
+        config.sourcer = TU::sample_sourcer();
+
+        std::fs::write(&meta_path, r#"{"const_key": "!delete", "item_key": "!delete"}"#).unwrap();
+
+        let produced = Processor::process_item_file_with_config(&album_path, &config).unwrap();
+        assert_eq!(
+            produced,
+            Block(btreemap![
+                str!("ALBUM_01_item_key") => TU::s("ALBUM_01_item_val"),
+                str!("overridden") => TU::s("ALBUM_01_item"),
+            ]),
+        );
+
+        // The meta files that deleted keys and made them final are recorded.
+        std::fs::write(&meta_path, r#"{"const_key": "!delete", "overridden!": "final_val"}"#).unwrap();
+
+        let (produced, directives) = Processor::process_item_file_with_provenance(&album_path, &config).unwrap();
+        assert_eq!(produced.get("overridden"), Some(&TU::s("final_val")));
+        assert_eq!(directives.deleted_keys, hashmap![str!("const_key") => meta_path.clone()]);
+        assert_eq!(directives.final_keys, hashmap![str!("overridden") => meta_path.clone()]);
+
+        let mut processor = CachedProcessor::new(&config);
+        assert_eq!(processor.process_item_file_with_provenance(&album_path).unwrap(), (produced.clone(), directives.clone()));
+
+        let library = Library::new(path, config);
+        assert_eq!(library.process_item_file_with_provenance(Path::new("ALBUM_01")).unwrap(), (produced, directives));
+    }
+
+    #[test]
+    fn process_item_file_sidecar() {
+        let temp_dir = TU::create_temp_media_test_dir("process_item_file_sidecar");
+        let path = temp_dir.path();
+
+        let mut config = TU::sample_config();
+        config.sourcer = Sourcer::new();
+        config.sourcer
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("json"), Anchor::Sidecar(SidecarMode::FullName)).unwrap())
+            .source(Source::from_name(str!("meta.json"), Anchor::Sidecar(SidecarMode::Stem)).unwrap());
+
+        let disc_path = path.join("ALBUM_01").join("DISC_01");
+        std::fs::write(disc_path.join("TRACK_01.flac.json"), r#"{"overridden": "TRACK_01_sidecar"}"#).unwrap();
+        std::fs::write(disc_path.join("TRACK_02.meta.json"), r#"{"overridden": "TRACK_02_sidecar"}"#).unwrap();
+
+        let inputs_and_expected = vec![
+            ("TRACK_01.flac", "TRACK_01_sidecar"),
+            ("TRACK_02.flac", "TRACK_02_sidecar"),
+            ("TRACK_03.flac", "TRACK_03_item"),
+        ];
+
+        for (item_name, expected) in inputs_and_expected {
+            let produced = Processor::process_item_file_with_config(&disc_path.join(item_name), &config).unwrap();
+
+            assert_eq!(produced.get("overridden"), Some(&TU::s(expected)));
+            assert_eq!(produced.get("item_key"), Some(&TU::s("item_val")));
+        }
+
+        // Sidecar meta files only impact their own item file.
+        for (meta_name, expected_name) in [
+            ("TRACK_01.flac.json", "TRACK_01.flac"),
+            ("TRACK_02.meta.json", "TRACK_02.flac"),
+        ] {
+            let produced = Processor::process_impacted_item_files(
+                &disc_path.join(meta_name),
+                &config.sourcer,
+                &config.selection,
+                &config.sorter,
+                &config.merger,
+                &config.virtual_fields,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+
+            assert_eq!(produced, vec![disc_path.join(expected_name)]);
+        }
+    }
+
+    #[test]
+    fn process_item_file_root() {
+        let temp_dir = TU::create_temp_media_test_dir("process_item_file_root");
+        let path = temp_dir.path();
+
+        let mut config = TU::sample_config();
+        config.sourcer = Sourcer::new();
+        config.sourcer
+            .source(Source::from_name(str!("library.json"), Anchor::Root).unwrap())
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap());
+
+        std::fs::write(
+            path.join("library.json"),
+            r#"{
+                "ALBUM_01/DISC_01/TRACK_01.flac": {"lib_key": "lib_val", "overridden": "lib"},
+                "ALBUM_02": {"lib_key": "lib_album_val"}
+            }"#,
+        ).unwrap();
+
+        let disc_path = path.join("ALBUM_01").join("DISC_01");
+
+        let process = |item_path: &Path| Processor::process_item_file_with_config(item_path, &config);
+
+        let produced = process(&disc_path.join("TRACK_01.flac")).unwrap();
+        assert_eq!(produced.get("lib_key"), Some(&TU::s("lib_val")));
+        assert_eq!(produced.get("overridden"), Some(&TU::s("TRACK_01_item")));
+
+        let produced = process(&path.join("ALBUM_02")).unwrap();
+        assert_eq!(produced.get("lib_key"), Some(&TU::s("lib_album_val")));
+
+        // Item files without an entry in the root meta file are not an error.
+        let produced = process(&disc_path.join("TRACK_02.flac")).unwrap();
+        assert_eq!(produced.get("lib_key"), None);
+
+        // The nearest root meta file is used.
+        std::fs::write(
+            path.join("ALBUM_01").join("library.json"),
+            r#"{"./DISC_01/TRACK_02.flac": {"lib_key": "nested_lib_val"}}"#,
+        ).unwrap();
+
+        let produced = process(&disc_path.join("TRACK_01.flac")).unwrap();
+        assert_eq!(produced.get("lib_key"), None);
+        let produced = process(&disc_path.join("TRACK_02.flac")).unwrap();
+        assert_eq!(produced.get("lib_key"), Some(&TU::s("nested_lib_val")));
+
+        let produced = Processor::process_impacted_item_files(
+            &path.join("ALBUM_01").join("library.json"),
+            &config.sourcer,
+            &config.selection,
+            &config.sorter,
+            &config.merger,
+            &config.virtual_fields,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|res| res.unwrap().0)
+        .collect::<Vec<_>>();
+        assert_eq!(produced, vec![disc_path.join("TRACK_02.flac")]);
+
+        // Keys need to refer to existing descendants.
+        for key in ["../ALBUM_02", "DISC_01/TRACK_XX.flac", ""] {
+            std::fs::write(
+                path.join("ALBUM_01").join("library.json"),
+                format!(r#"{{"{}": {{}}}}"#, key),
+            ).unwrap();
+
+            assert!(matches!(
+                process(&disc_path.join("TRACK_02.flac")),
+                Err(Error::PlexerError(..)),
+            ));
+        }
+    }
+
+    #[test]
+    fn process_impacted_item_files() {
+        let temp_dir = TU::create_temp_media_test_dir("process_impacted_item_files");
+        let path = temp_dir.path();
+
+        let config = TU::sample_config();
+
+        let album_path = path.join("ALBUM_02");
+
+        let inputs_and_expected = vec![
+            (
+                album_path.join("item.json"),
+                vec![
+                    album_path.join("DISC_01"),
+                    album_path.join("TRACK_01.flac"),
+                    album_path.join("TRACK_02.flac"),
+                    album_path.join("TRACK_03.flac"),
+                ],
+            ),
+            (album_path.join("self.json"), vec![album_path.clone()]),
+        ];
+
+        for (meta_path, expected_paths) in inputs_and_expected {
+            let produced = Processor::process_impacted_item_files(
+                &meta_path,
+                &config.sourcer,
+                &config.selection,
+                &config.sorter,
+                &config.merger,
+                &config.virtual_fields,
+            )
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+            assert_eq!(
+                expected_paths,
+                produced.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>(),
+            );
+
+            for (item_path, block) in produced {
+                let expected = Processor::process_item_file_with_config(&item_path, &config)
+                .unwrap();
+                assert_eq!(expected, block);
+            }
+        }
+
+        assert!(matches!(
+            Processor::process_impacted_item_files(
+                &album_path.join("other.json"),
+                &config.sourcer,
+                &config.selection,
+                &config.sorter,
+                &config.merger,
+                &config.virtual_fields,
+            ),
+            Err(Error::UnknownMetaFile(..)),
+        ));
+    }
 }
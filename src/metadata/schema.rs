@@ -33,8 +33,8 @@ pub(crate) enum SchemaRepr {
 impl From<Anchor> for Arity {
     fn from(value: Anchor) -> Self {
         match value {
-            Anchor::Internal => Arity::Unit,
-            Anchor::External => Arity::Many,
+            Anchor::Internal | Anchor::Sidecar(..) => Arity::Unit,
+            Anchor::External | Anchor::Root => Arity::Many,
         }
     }
 }
@@ -42,8 +42,8 @@ impl From<Anchor> for Arity {
 impl<'a> From<&'a Anchor> for &'a Arity {
     fn from(value: &'a Anchor) -> Self {
         match value {
-            Anchor::Internal => &Arity::Unit,
-            Anchor::External => &Arity::Many,
+            Anchor::Internal | Anchor::Sidecar(..) => &Arity::Unit,
+            Anchor::External | Anchor::Root => &Arity::Many,
         }
     }
 }
@@ -1,5 +1,7 @@
+pub mod normalization;
 pub mod source;
 pub mod sourcer;
 
+pub use self::normalization::Normalization;
 pub use self::source::*;
 pub use self::sourcer::*;
@@ -0,0 +1,197 @@
+//! Defines how item and meta file paths are normalized before they are used.
+
+use std::borrow::Cow;
+use std::io::Result as IoResult;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::fs::{DiskFs, Fs};
+
+/// Represents all the ways that paths can be normalized. Metadata is looked up
+/// by exact path equality, so item paths that refer to the same item file
+/// need to be normalized in the same way in order to be found.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// Paths are used exactly as given. This is the default, so item paths
+    /// such as `./ALBUM/TRACK.flac`, paths containing `..`, or paths through
+    /// a symlinked directory only find their metadata after opting into
+    /// `Lexical` or `Canonical` normalization.
+    #[default]
+    None,
+
+    /// Relative paths are made absolute using the current working directory,
+    /// and `.` and `..` components are resolved without accessing the
+    /// filesystem. Symlinks are not resolved, so a `..` component following a
+    /// symlink may result in a different path than the filesystem would use.
+    /// Filesystems without a working directory, such as `MemoryFs`, leave
+    /// relative paths relative.
+    Lexical,
+
+    /// Paths are made absolute and all symlinks in their parent directories
+    /// are resolved using the filesystem. The final component is kept as
+    /// given, so that a symlinked item file still finds its meta files next
+    /// to the link, instead of next to the target. This requires that the
+    /// paths exist.
+    Canonical,
+}
+
+impl Normalization {
+    /// Normalizes a path, borrowing it if it is already normalized.
+    pub fn normalize<'p>(&self, path: &'p Path) -> IoResult<Cow<'p, Path>> {
+        self.normalize_in(&DiskFs, path)
+    }
+
+    /// Similar to `normalize`, but accesses a given filesystem.
+    pub fn normalize_in<'p>(&self, fs: &dyn Fs, path: &'p Path) -> IoResult<Cow<'p, Path>> {
+        match self {
+            Self::None => Ok(Cow::Borrowed(path)),
+            Self::Lexical => Self::lexical(fs, path),
+            Self::Canonical => Self::canonical(fs, path).map(Cow::Owned),
+        }
+    }
+
+    fn canonical(fs: &dyn Fs, path: &Path) -> IoResult<PathBuf> {
+        match Self::split_file_name(path) {
+            Some((parent, file_name)) => {
+                let canon_path = fs.canonicalize(parent)?.join(file_name);
+
+                // Make sure that the path exists, like with a full canonicalize.
+                fs.metadata(&canon_path)?;
+
+                Ok(canon_path)
+            },
+            None => fs.canonicalize(path),
+        }
+    }
+
+    /// Splits a path into its parent directory and its final component, if
+    /// it has one that is not `..`. A bare file name has `.` as its parent.
+    pub(crate) fn split_file_name(path: &Path) -> Option<(&Path, &std::ffi::OsStr)> {
+        let file_name = path.file_name()?;
+        let parent = match path.parent()? {
+            p if p.as_os_str().is_empty() => Path::new("."),
+            p => p,
+        };
+
+        Some((parent, file_name))
+    }
+
+    fn lexical<'p>(fs: &dyn Fs, path: &'p Path) -> IoResult<Cow<'p, Path>> {
+        let is_normal = path.is_absolute()
+            && path.components().all(|c| !matches!(c, Component::CurDir | Component::ParentDir));
+
+        if is_normal {
+            return Ok(Cow::Borrowed(path));
+        }
+
+        let abs_path = match fs.current_dir()? {
+            Some(current_dir) if !path.is_absolute() => Cow::Owned(current_dir.join(path)),
+            _ => Cow::Borrowed(path),
+        };
+
+        let mut normalized = PathBuf::new();
+
+        for component in abs_path.components() {
+            match component {
+                Component::CurDir => {},
+                Component::ParentDir => {
+                    // Leading `..` components of relative paths are kept.
+                    match normalized.components().next_back() {
+                        Some(Component::Normal(..)) => { normalized.pop(); },
+                        Some(Component::Prefix(..)) | Some(Component::RootDir) => {},
+                        _ => normalized.push(component),
+                    }
+                },
+                Component::Prefix(..) | Component::RootDir | Component::Normal(..) => {
+                    normalized.push(component);
+                },
+            }
+        }
+
+        Ok(Cow::Owned(normalized))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fs::MemoryFs;
+
+    #[test]
+    fn deserialization() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            normalization: Normalization,
+        }
+
+        for (text, expected) in &[
+            (r#"normalization = "none""#, Normalization::None),
+            (r#"normalization = "lexical""#, Normalization::Lexical),
+            (r#"normalization = "canonical""#, Normalization::Canonical),
+        ] {
+            let wrapper: Wrapper = toml::from_str(text).unwrap();
+            assert_eq!(wrapper.normalization, *expected);
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        let current_dir = std::env::current_dir().unwrap();
+
+        let n = Normalization::None;
+        assert_eq!(n.normalize(Path::new("./ALBUM/../TRACK.flac")).unwrap(), Path::new("./ALBUM/../TRACK.flac"));
+
+        let n = Normalization::Lexical;
+        assert!(matches!(n.normalize(Path::new("/music/ALBUM")).unwrap(), Cow::Borrowed(..)));
+        assert_eq!(n.normalize(Path::new("/music/./ALBUM/../TRACK.flac")).unwrap(), Path::new("/music/TRACK.flac"));
+        assert_eq!(n.normalize(Path::new("/../music")).unwrap(), Path::new("/music"));
+        assert_eq!(n.normalize(Path::new("./ALBUM/TRACK.flac")).unwrap(), current_dir.join("ALBUM/TRACK.flac"));
+        assert_eq!(n.normalize(Path::new("ALBUM/../TRACK.flac")).unwrap(), current_dir.join("TRACK.flac"));
+
+        // Filesystems without a working directory keep relative paths relative.
+        let fs = MemoryFs::new();
+        assert_eq!(n.normalize_in(&fs, Path::new("./ALBUM/../TRACK.flac")).unwrap(), Path::new("TRACK.flac"));
+        assert_eq!(n.normalize_in(&fs, Path::new("../ALBUM/./TRACK.flac")).unwrap(), Path::new("../ALBUM/TRACK.flac"));
+        assert_eq!(n.normalize_in(&fs, Path::new("/music/../TRACK.flac")).unwrap(), Path::new("/TRACK.flac"));
+
+        let mut fs = MemoryFs::new();
+        fs.add_file("/music/ALBUM/TRACK.flac", "").unwrap();
+
+        let n = Normalization::Canonical;
+        assert_eq!(
+            n.normalize_in(&fs, Path::new("/music/ALBUM/../ALBUM/./TRACK.flac")).unwrap(),
+            Path::new("/music/ALBUM/TRACK.flac"),
+        );
+        assert!(n.normalize_in(&fs, Path::new("/music/ALBUM/TRACK_XX.flac")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn normalize_symlinked_file() {
+        let temp_dir = tempfile::Builder::new().suffix("normalize_symlinked_file").tempdir().unwrap();
+        let path = temp_dir.path().canonicalize().unwrap();
+
+        let store_path = path.join("store");
+        let album_path = path.join("lib").join("ALBUM");
+        std::fs::create_dir_all(&store_path).unwrap();
+        std::fs::create_dir_all(&album_path).unwrap();
+        std::fs::write(store_path.join("x.flac"), "").unwrap();
+        std::os::unix::fs::symlink(store_path.join("x.flac"), album_path.join("TRACK.flac")).unwrap();
+        std::os::unix::fs::symlink(&album_path, path.join("LINK")).unwrap();
+
+        let n = Normalization::Canonical;
+
+        // The symlinked item file keeps its own name and directory.
+        assert_eq!(n.normalize(&album_path.join("TRACK.flac")).unwrap(), album_path.join("TRACK.flac"));
+
+        // Symlinks in the parent directories are still resolved.
+        assert_eq!(n.normalize(&path.join("LINK").join("TRACK.flac")).unwrap(), album_path.join("TRACK.flac"));
+        assert_eq!(
+            n.normalize(&path.join("LINK").join("..").join("ALBUM")).unwrap(),
+            album_path,
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -6,6 +7,7 @@ use std::str::FromStr;
 use thiserror::Error;
 
 use crate::config::{Format, FormatError, Selection};
+use crate::fs::{DirEntries, DiskFs, Fs, FsMetadata};
 use crate::metadata::Schema;
 use crate::util::{InvalidNameKind, Util};
 
@@ -13,6 +15,8 @@ use crate::util::{InvalidNameKind, Util};
 pub enum CreateError {
     #[error("invalid source name: {0}: {1}")]
     InvalidName(InvalidNameKind, String),
+    #[error("invalid meta directory name: {0}: {1}")]
+    InvalidMetaDir(InvalidNameKind, String),
     #[error("missing extension: {0}")]
     MissingExt(String),
     #[error("unknown extension: {0}")]
@@ -33,8 +37,16 @@ pub enum SourceError {
 
     #[error("item path does not have a parent: {}", .0.display())]
     NoItemParentDir(PathBuf),
+    #[error("item path does not have a file name: {}", .0.display())]
+    NoItemFileName(PathBuf),
     #[error("meta path does not have a parent: {}", .0.display())]
     NoMetaParentDir(PathBuf),
+    #[error("meta path is not inside a meta directory: {}", .0.display())]
+    NotInMetaDir(PathBuf),
+    #[error("item path is outside of the tree mirrored by the overlay: {}", .0.display())]
+    ItemOutsideOverlay(PathBuf),
+    #[error("meta path is outside of the overlay: {}", .0.display())]
+    MetaOutsideOverlay(PathBuf),
 
     #[error("unable to read item directory: {0}")]
     IterDir(#[source] IoError),
@@ -49,7 +61,10 @@ impl SourceError {
                 IoErrorKind::NotFound => false,
                 _ => true,
             },
-            Self::NotADir(..) | Self::NoItemParentDir(..) => false,
+            Self::NotADir(..)
+            | Self::NoItemParentDir(..)
+            | Self::NoItemFileName(..)
+            | Self::ItemOutsideOverlay(..) => false,
             _ => true,
         }
     }
@@ -57,8 +72,7 @@ impl SourceError {
 
 /// Represents a method of finding the location of a meta file given an item
 /// file path.
-#[derive(Debug, Clone, Copy)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Anchor {
     /// The meta file is located in the same directory as the item file path.
     External,
@@ -66,16 +80,82 @@ pub enum Anchor {
     /// The meta file is located inside the item file path.
     /// Implies that the the item file path is a directory.
     Internal,
+
+    /// The meta file is located in the same directory as the item file path,
+    /// and is named after the item file, with the source name appended as a
+    /// suffix. Each meta file provides metadata for only one item file.
+    Sidecar(SidecarMode),
+
+    /// The meta file is located in the nearest ancestor directory of the item
+    /// file path that contains one, and provides metadata for any number of
+    /// descendant item files, keyed by their paths relative to that directory.
+    Root,
+}
+
+/// Represents which part of an item file name is used to name its sidecar
+/// meta file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SidecarMode {
+    /// The full item file name is used, e.g. `TRACK_01.flac.yml`.
+    FullName,
+
+    /// The item file name without its extension is used, e.g. `TRACK_01.yml`.
+    /// If multiple selected item files share a stem, they share a meta file,
+    /// which results in an error when plexing.
+    Stem,
+}
+
+/// Represents a separate directory tree that mirrors the layout of a library
+/// tree, and contains meta files for the item files in that library tree.
+/// This allows providing metadata for libraries that cannot be written to.
+#[derive(Debug, Clone)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct Overlay {
+    tree_root: PathBuf,
+    overlay_root: PathBuf,
+}
+
+impl Overlay {
+    /// Creates an overlay rooted at `overlay_root`, which mirrors the tree
+    /// rooted at `tree_root`. Both paths are expected to be normalized in the
+    /// same way as the item paths that are looked up.
+    pub fn new<T: Into<PathBuf>, O: Into<PathBuf>>(tree_root: T, overlay_root: O) -> Self {
+        Self { tree_root: tree_root.into(), overlay_root: overlay_root.into() }
+    }
+
+    pub fn tree_root(&self) -> &Path {
+        &self.tree_root
+    }
+
+    pub fn overlay_root(&self) -> &Path {
+        &self.overlay_root
+    }
+
+    /// Maps a directory in the library tree to its mirror in the overlay.
+    fn to_overlay(&self, dir_path: &Path) -> Option<PathBuf> {
+        dir_path.strip_prefix(&self.tree_root).ok().map(|rel| self.overlay_root.join(rel))
+    }
+
+    /// Maps a directory in the overlay back to the library tree.
+    fn to_tree(&self, dir_path: &Path) -> Option<PathBuf> {
+        dir_path.strip_prefix(&self.overlay_root).ok().map(|rel| self.tree_root.join(rel))
+    }
 }
 
 /// Defines a meta file source, consisting of an anchor (the target directory
 /// to look in) and a file name (the meta file name in that target directory).
+/// Optionally, meta files can be kept in a dedicated subdirectory of the target
+/// directory instead of directly inside it, or in an overlay tree that mirrors
+/// the target directory.
 #[derive(Debug)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct Source {
     pub(crate) name: String,
     pub(crate) anchor: Anchor,
     pub(crate) format: Format,
+    pub(crate) meta_dir: Option<String>,
+    pub(crate) overlay: Option<Overlay>,
+    pub(crate) root: Option<PathBuf>,
 }
 
 impl Source {
@@ -96,43 +176,274 @@ impl Source {
             Err(_) => { return Err(CreateError::UnknownExt(name)); },
         };
 
-        Ok(Self { name, anchor, format, })
+        Ok(Self { name, anchor, format, meta_dir: None, overlay: None, root: None, })
+    }
+
+    /// Places the meta files of this source inside a subdirectory with the
+    /// given name, e.g. `.anagma/album.yml` instead of `album.yml`.
+    pub fn with_meta_dir(mut self, meta_dir: String) -> Result<Self, CreateError> {
+        match Util::validate_item_name(&meta_dir) {
+            Ok(()) => {},
+            Err(kind) => return Err(CreateError::InvalidMetaDir(kind, meta_dir)),
+        };
+
+        self.meta_dir = Some(meta_dir);
+        Ok(self)
+    }
+
+    pub fn meta_dir(&self) -> Option<&str> {
+        self.meta_dir.as_deref()
+    }
+
+    /// Looks for the meta files of this source in an overlay tree, instead of
+    /// in the library tree itself.
+    pub fn with_overlay(mut self, overlay: Overlay) -> Self {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    pub fn overlay(&self) -> Option<&Overlay> {
+        self.overlay.as_ref()
+    }
+
+    /// Limits the search for root meta files to directories inside of the
+    /// given root directory, such as a library root. Without a root, all
+    /// ancestor directories of an item path are searched.
+    pub fn with_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Returns the path of a meta file with a given name that belongs to a
+    /// target directory, taking the overlay and meta directory into account.
+    /// Returns `None` if the target directory is not mirrored by the overlay.
+    fn meta_file_path<N: AsRef<Path>>(&self, dir_path: &Path, meta_name: N) -> Option<PathBuf> {
+        let dir_path = match &self.overlay {
+            Some(overlay) => Cow::Owned(overlay.to_overlay(dir_path)?),
+            None => Cow::Borrowed(dir_path),
+        };
+
+        Some(match &self.meta_dir {
+            Some(meta_dir) => dir_path.join(meta_dir).join(meta_name),
+            None => dir_path.join(meta_name),
+        })
+    }
+
+    /// Returns the target directory that a meta file belongs to. This is the
+    /// parent directory of the meta file, or the parent of the meta directory
+    /// if one is used. For overlay sources, this is mapped back to the library
+    /// tree.
+    pub(crate) fn meta_target_dir<'m>(&self, meta_path: &'m Path) -> Result<Cow<'m, Path>, SourceError> {
+        let meta_parent_dir_path = meta_path
+            .parent()
+            .ok_or_else(|| SourceError::NoMetaParentDir(meta_path.into()))?;
+
+        let target_dir_path = match &self.meta_dir {
+            Some(meta_dir) => {
+                if meta_parent_dir_path.file_name() != Some(OsStr::new(meta_dir)) {
+                    return Err(SourceError::NotInMetaDir(meta_path.into()));
+                }
+
+                meta_parent_dir_path
+                    .parent()
+                    .ok_or_else(|| SourceError::NoMetaParentDir(meta_path.into()))?
+            },
+            None => meta_parent_dir_path,
+        };
+
+        match &self.overlay {
+            Some(overlay) => overlay
+                .to_tree(target_dir_path)
+                .map(Cow::Owned)
+                .ok_or_else(|| SourceError::MetaOutsideOverlay(meta_path.into())),
+            None => Ok(Cow::Borrowed(target_dir_path)),
+        }
     }
 
     /// Given a concrete item file path, returns the meta file path that would
     /// provide metadata for that item path, according to the source rules.
     pub fn meta_path(&self, item_path: &Path) -> Result<PathBuf, SourceError> {
+        self.meta_path_in(&DiskFs, item_path)
+    }
+
+    /// Similar to `meta_path`, but accesses a given filesystem.
+    pub fn meta_path_in(&self, fs: &dyn Fs, item_path: &Path) -> Result<PathBuf, SourceError> {
         // Get filesystem stat for item path.
         // This step is always done, even if the file/directory status does not
         // need to be checked, as it provides useful error information about
         // permissions and non-existence.
-        let item_fs_stat = std::fs::metadata(&item_path)
+        let item_fs_stat = fs.metadata(item_path)
+            .map_err(|io| SourceError::ItemAccess(item_path.into(), io))?;
+
+        // Root meta files need to be searched for in each ancestor directory.
+        if let Anchor::Root = self.anchor {
+            for meta_path in self.root_meta_path_candidates(item_path)? {
+                match fs.metadata(&meta_path) {
+                    Ok(meta_fs_stat) if meta_fs_stat.is_file() => return Ok(meta_path),
+                    Ok(_) => {},
+                    Err(io_err) if io_err.kind() == IoErrorKind::NotFound => {},
+                    Err(io_err) => return Err(SourceError::MetaAccess(meta_path, io_err)),
+                }
+            }
+
+            return Err(self.root_meta_path_not_found(item_path));
+        }
+
+        // Create the target meta file path.
+        let meta_path = self.target_meta_path(item_path, item_fs_stat.is_dir())?;
+
+        // Get filesystem stat for meta path.
+        let meta_fs_stat_res = fs.metadata(&meta_path);
+
+        Self::check_meta_path(meta_path, meta_fs_stat_res)
+    }
+
+    /// Async version of `meta_path`.
+    #[cfg(feature = "async")]
+    pub async fn meta_path_async(&self, item_path: &Path) -> Result<PathBuf, SourceError> {
+        let item_fs_stat = tokio::fs::metadata(&item_path)
+            .await
             .map_err(|io| SourceError::ItemAccess(item_path.into(), io))?;
 
-        // Create the path of the directory that should contain the meta file.
-        let meta_path_parent_dir = match self.anchor {
+        if let Anchor::Root = self.anchor {
+            for meta_path in self.root_meta_path_candidates(item_path)? {
+                match tokio::fs::metadata(&meta_path).await {
+                    Ok(meta_fs_stat) if meta_fs_stat.is_file() => return Ok(meta_path),
+                    Ok(_) => {},
+                    Err(io_err) if io_err.kind() == IoErrorKind::NotFound => {},
+                    Err(io_err) => return Err(SourceError::MetaAccess(meta_path, io_err)),
+                }
+            }
+
+            return Err(self.root_meta_path_not_found(item_path));
+        }
+
+        let meta_path = self.target_meta_path(item_path, item_fs_stat.is_dir())?;
+
+        let meta_fs_stat_res = tokio::fs::metadata(&meta_path).await.map(Into::into);
+
+        Self::check_meta_path(meta_path, meta_fs_stat_res)
+    }
+
+    /// Returns the possible root meta file paths for an item path, from the
+    /// nearest ancestor directory to the farthest, stopping at the root
+    /// directory of this source if one is set.
+    fn root_meta_path_candidates<'p>(
+        &'p self,
+        item_path: &'p Path,
+    ) -> Result<impl Iterator<Item = PathBuf> + 'p, SourceError> {
+        if item_path.parent().is_none() {
+            return Err(SourceError::NoItemParentDir(item_path.into()));
+        }
+
+        // Ancestor directories that are not mirrored by an overlay are skipped.
+        Ok(
+            item_path
+                .ancestors()
+                .skip(1)
+                .take_while(move |dir_path| self.root.as_ref().map_or(true, |r| dir_path.starts_with(r)))
+                .filter_map(move |dir_path| self.meta_file_path(dir_path, &self.name))
+        )
+    }
+
+    fn root_meta_path_not_found(&self, item_path: &Path) -> SourceError {
+        let nearest_meta_path = item_path
+            .parent()
+            .and_then(|p| self.meta_file_path(p, &self.name))
+            .unwrap_or_else(|| item_path.into());
+
+        let io_err = IoError::new(IoErrorKind::NotFound, "not found in any ancestor directory");
+
+        SourceError::MetaAccess(nearest_meta_path, io_err)
+    }
+
+    /// Returns the path of the meta file that would provide metadata for an
+    /// item path, without checking that it exists.
+    fn target_meta_path(&self, item_path: &Path, item_is_dir: bool) -> Result<PathBuf, SourceError> {
+        let (target_dir_path, meta_name) = match self.anchor {
             // The meta parent dir is the same as the item's parent dir.
-            Anchor::External => item_path
-                .parent()
-                .ok_or_else(|| SourceError::NoItemParentDir(item_path.into()))?,
+            Anchor::External => (
+                item_path
+                    .parent()
+                    .ok_or_else(|| SourceError::NoItemParentDir(item_path.into()))?,
+                OsString::from(&self.name),
+            ),
 
             // The meta parent dir is the item path itself, as long as it is
             // actually a dir.
             Anchor::Internal => {
-                if !item_fs_stat.is_dir() {
+                if !item_is_dir {
                     return Err(SourceError::NotADir(item_path.into()));
                 }
 
+                (item_path, OsString::from(&self.name))
+            },
+
+            // The meta file is next to the item path, and named after it.
+            Anchor::Sidecar(mode) => {
+                let item_parent_dir_path = item_path
+                    .parent()
+                    .ok_or_else(|| SourceError::NoItemParentDir(item_path.into()))?;
+
+                let item_name = match mode {
+                    SidecarMode::FullName => item_path.file_name(),
+                    SidecarMode::Stem => item_path.file_stem(),
+                }
+                .ok_or_else(|| SourceError::NoItemFileName(item_path.into()))?;
+
+                let mut meta_name = item_name.to_os_string();
+                meta_name.push(".");
+                meta_name.push(&self.name);
+
+                (item_parent_dir_path, meta_name)
+            },
+
+            // The meta file could be in any ancestor directory, which is
+            // handled by the caller.
+            Anchor::Root => (
                 item_path
-            }
+                    .parent()
+                    .ok_or_else(|| SourceError::NoItemParentDir(item_path.into()))?,
+                OsString::from(&self.name),
+            ),
         };
 
-        // Create the target meta file path.
-        let meta_path = meta_path_parent_dir.join(&self.name);
+        self.meta_file_path(target_dir_path, meta_name)
+            .ok_or_else(|| SourceError::ItemOutsideOverlay(item_path.into()))
+    }
 
-        // Get filesystem stat for meta path.
+    /// Returns true if a meta file path could belong to this source, based on
+    /// its file name and whether it is inside the meta directory.
+    pub(crate) fn is_meta_path_match(&self, meta_path: &Path) -> bool {
+        let is_name_match = match self.anchor {
+            Anchor::External | Anchor::Internal | Anchor::Root => {
+                meta_path.file_name() == Some(OsStr::new(&self.name))
+            },
+            Anchor::Sidecar(..) => self.sidecar_item_name(meta_path).is_some(),
+        };
+
+        is_name_match && self.meta_target_dir(meta_path).is_ok()
+    }
+
+    /// For sidecar sources, returns the item file name (or stem) that a meta
+    /// file is named after.
+    fn sidecar_item_name<'m>(&self, meta_path: &'m Path) -> Option<&'m str> {
+        meta_path
+            .file_name()?
+            .to_str()?
+            .strip_suffix(self.name.as_str())?
+            .strip_suffix('.')
+            .filter(|n| !n.is_empty())
+    }
+
+    /// Ensures that a meta path exists and is a file, given its stat result.
+    fn check_meta_path(meta_path: PathBuf, meta_fs_stat_res: IoResult<FsMetadata>) -> Result<PathBuf, SourceError> {
         // NOTE: Using `match` in order to avoid a clone in the error case.
-        let meta_fs_stat = match std::fs::metadata(&meta_path) {
+        let meta_fs_stat = match meta_fs_stat_res {
             Ok(o) => o,
             Err(io_err) => return Err(SourceError::MetaAccess(meta_path, io_err)),
         };
@@ -150,37 +461,59 @@ impl Source {
     /// could/should provide metadata for. Note that this does NOT parse meta
     /// files, it only uses file system locations and presence. In addition, no
     /// filtering or sorting of the returned item paths is performed.
+    /// For root sources, the item file paths are given by the keys inside the
+    /// meta file, so no item file paths are produced here.
     pub fn item_paths<'a>(&self, meta_path: &'a Path) -> Result<ItemPaths<'a>, SourceError> {
-        let meta_fs_stat = std::fs::metadata(&meta_path)
+        self.item_paths_in(&DiskFs, meta_path)
+    }
+
+    /// Similar to `item_paths`, but accesses a given filesystem.
+    pub fn item_paths_in<'a>(&self, fs: &'a dyn Fs, meta_path: &'a Path) -> Result<ItemPaths<'a>, SourceError> {
+        let meta_fs_stat = fs.metadata(meta_path)
             .map_err(|io| SourceError::MetaAccess(meta_path.into(), io))?;
 
         if !meta_fs_stat.is_file() {
             return Err(SourceError::NotAFile(meta_path.into()));
         }
 
-        // Get the parent directory of the meta file.
-        if let Some(meta_parent_dir_path) = meta_path.parent() {
-            let ipi = match self.anchor {
-                Anchor::External => {
-                    // Return all children of the parent directory of this meta file.
-                    let read_dir =
-                        std::fs::read_dir(&meta_parent_dir_path).map_err(SourceError::IterDir)?;
+        // Get the target directory of the meta file.
+        let target_dir_path = self.meta_target_dir(meta_path)?;
 
-                    ItemPathsInner::ReadDir(read_dir)
-                }
-                Anchor::Internal => {
-                    // This is just the passed-in path, just push it on unchanged.
-                    ItemPathsInner::Single(Some(meta_parent_dir_path))
+        let ipi = match self.anchor {
+            Anchor::External => {
+                // Return all children of the target directory of this meta file.
+                let read_dir = fs.read_dir(&target_dir_path).map_err(SourceError::IterDir)?;
+
+                ItemPathsInner::ReadDir(read_dir)
+            }
+            Anchor::Internal => {
+                // The target directory is the item path itself.
+                ItemPathsInner::Single(Some(target_dir_path))
+            }
+            Anchor::Sidecar(mode) => {
+                match (self.sidecar_item_name(meta_path), mode) {
+                    // Not a meta file for this source, there are no items.
+                    (None, _) => ItemPathsInner::Single(None),
+                    (Some(item_name), SidecarMode::FullName) => {
+                        // The meta file could also be a sidecar for a
+                        // different source, so the item path may not exist.
+                        let item_path = target_dir_path.join(item_name);
+                        ItemPathsInner::Single(
+                            Some(item_path).filter(|p| fs.metadata(p).is_ok()).map(Cow::Owned)
+                        )
+                    },
+                    (Some(item_stem), SidecarMode::Stem) => {
+                        // Any sibling with a matching stem is an item path.
+                        let read_dir = fs.read_dir(&target_dir_path).map_err(SourceError::IterDir)?;
+
+                        ItemPathsInner::StemMatches(read_dir, item_stem.into(), meta_path)
+                    },
                 }
-            };
+            }
+            Anchor::Root => ItemPathsInner::Single(None),
+        };
 
-            Ok(ItemPaths(ipi))
-        } else {
-            // This should never happen, since at this point we have a real meta
-            // file and thus, a real parent directory for that file, but making
-            // an error for it anyways.
-            Err(SourceError::NoMetaParentDir(meta_path.into()))
-        }
+        Ok(ItemPaths(ipi))
     }
 
     /// Similar to `item_paths`, but also performs selection filtering on the
@@ -190,17 +523,129 @@ impl Source {
         meta_path: &'a Path,
         selection: &'a Selection,
     ) -> Result<SelectedItemPaths<'a>, SourceError> {
-        Ok(SelectedItemPaths(self.item_paths(meta_path)?, selection))
+        self.selected_item_paths_in(&DiskFs, meta_path, selection)
+    }
+
+    /// Similar to `selected_item_paths`, but accesses a given filesystem.
+    pub fn selected_item_paths_in<'a>(
+        &self,
+        fs: &'a dyn Fs,
+        meta_path: &'a Path,
+        selection: &'a Selection,
+    ) -> Result<SelectedItemPaths<'a>, SourceError> {
+        self.selected_item_paths_within_in(fs, None, meta_path, selection)
+    }
+
+    /// Similar to `selected_item_paths_in`, but for a meta file found while
+    /// walking from an origin directory. Ignore files are looked for up to the
+    /// origin directory, or up to the target directory of the meta file if
+    /// there is no origin.
+    pub(crate) fn selected_item_paths_within_in<'a>(
+        &self,
+        fs: &'a dyn Fs,
+        origin_dir_path: Option<&Path>,
+        meta_path: &'a Path,
+        selection: &'a Selection,
+    ) -> Result<SelectedItemPaths<'a>, SourceError> {
+        let item_paths = self.item_paths_in(fs, meta_path)?;
+        let origin_dir_path = match origin_dir_path {
+            Some(origin_dir_path) => origin_dir_path.to_path_buf(),
+            None => self.meta_target_dir(meta_path)?.into_owned(),
+        };
+
+        Ok(SelectedItemPaths(item_paths, selection, fs, origin_dir_path))
+    }
+
+    /// Async version of `selected_item_paths`. As the item paths need to be
+    /// read eagerly, they are returned in a `Vec`.
+    #[cfg(feature = "async")]
+    pub async fn selected_item_paths_async(
+        &self,
+        meta_path: &Path,
+        selection: &Selection,
+    ) -> Result<Vec<IoResult<PathBuf>>, SourceError> {
+        let meta_fs_stat = tokio::fs::metadata(&meta_path)
+            .await
+            .map_err(|io| SourceError::MetaAccess(meta_path.into(), io))?;
+
+        if !meta_fs_stat.is_file() {
+            return Err(SourceError::NotAFile(meta_path.into()));
+        }
+
+        let target_dir_path = self.meta_target_dir(meta_path)?;
+
+        match self.anchor {
+            Anchor::External => {
+                selection
+                    .select_in_dir_async(&target_dir_path)
+                    .await
+                    .map_err(SourceError::IterDir)
+            },
+            Anchor::Internal => {
+                let item_path = target_dir_path.to_path_buf();
+
+                Ok(match selection.is_selected_async(&item_path).await {
+                    Ok(true) => vec![Ok(item_path)],
+                    Ok(false) => vec![],
+                    Err(err) => vec![Err(err)],
+                })
+            },
+            Anchor::Sidecar(mode) => {
+                let item_name = match self.sidecar_item_name(meta_path) {
+                    Some(n) => n,
+                    None => return Ok(vec![]),
+                };
+
+                Ok(match mode {
+                    SidecarMode::FullName => {
+                        let item_path = target_dir_path.join(item_name);
+
+                        match selection.is_selected_async(&item_path).await {
+                            Ok(true) => vec![Ok(item_path)],
+                            Ok(false) => vec![],
+                            Err(err) if err.kind() == IoErrorKind::NotFound => vec![],
+                            Err(err) => vec![Err(err)],
+                        }
+                    },
+                    SidecarMode::Stem => {
+                        selection
+                            .select_in_dir_async(&target_dir_path)
+                            .await
+                            .map_err(SourceError::IterDir)?
+                            .into_iter()
+                            .filter(|res| match res {
+                                Ok(p) => p != meta_path && p.file_stem() == Some(OsStr::new(item_name)),
+                                Err(_) => true,
+                            })
+                            .collect()
+                    },
+                })
+            },
+            Anchor::Root => Ok(vec![]),
+        }
     }
 
     pub fn read_schema(&self, meta_path: &Path) -> Result<Schema, FormatError> {
-        self.format.read_schema_path(meta_path, &self.anchor.into())
+        self.read_schema_in(&DiskFs, meta_path)
+    }
+
+    /// Similar to `read_schema`, but reads from a given filesystem.
+    pub fn read_schema_in(&self, fs: &dyn Fs, meta_path: &Path) -> Result<Schema, FormatError> {
+        self.format.read_schema_path_in(fs, meta_path, &self.anchor.into())
+    }
+
+    /// Async version of `read_schema`.
+    #[cfg(feature = "async")]
+    pub async fn read_schema_async(&self, meta_path: &Path) -> Result<Schema, FormatError> {
+        self.format.read_schema_path_async(meta_path, &self.anchor.into()).await
     }
 }
 
 enum ItemPathsInner<'a> {
-    ReadDir(std::fs::ReadDir),
-    Single(Option<&'a Path>),
+    ReadDir(DirEntries<'a>),
+    Single(Option<Cow<'a, Path>>),
+    // Entries in a directory that have a given file stem, besides the meta file.
+    StemMatches(DirEntries<'a>, OsString, &'a Path),
 }
 
 impl<'a> Iterator for ItemPathsInner<'a> {
@@ -208,8 +653,20 @@ impl<'a> Iterator for ItemPathsInner<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            Self::ReadDir(rd) => Some(rd.next()?.map(|e| Cow::Owned(e.path()))),
-            Self::Single(o) => o.take().map(|p| Ok(Cow::Borrowed(p))),
+            Self::ReadDir(rd) => Some(rd.next()?.map(Cow::Owned)),
+            Self::Single(o) => o.take().map(Ok),
+            Self::StemMatches(rd, stem, meta_path) => {
+                rd.find_map(|res| match res {
+                    Ok(path) => {
+                        if path != *meta_path && path.file_stem() == Some(stem.as_os_str()) {
+                            Some(Ok(Cow::Owned(path)))
+                        } else {
+                            None
+                        }
+                    },
+                    Err(err) => Some(Err(err)),
+                })
+            },
         }
     }
 }
@@ -224,7 +681,7 @@ impl<'a> Iterator for ItemPaths<'a> {
     }
 }
 
-pub struct SelectedItemPaths<'a>(ItemPaths<'a>, &'a Selection);
+pub struct SelectedItemPaths<'a>(ItemPaths<'a>, &'a Selection, &'a dyn Fs, PathBuf);
 
 impl<'a> Iterator for SelectedItemPaths<'a> {
     type Item = IoResult<Cow<'a, Path>>;
@@ -235,7 +692,7 @@ impl<'a> Iterator for SelectedItemPaths<'a> {
                 Err(err) => {
                     return Some(Err(err));
                 }
-                Ok(path) => match self.1.is_selected(&path) {
+                Ok(path) => match self.1.is_selected_within_in(self.2, &self.3, &path) {
                     Ok(true) => {
                         return Some(Ok(path));
                     }
@@ -2,54 +2,145 @@ pub mod item_paths;
 
 pub use self::item_paths::*;
 
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 
-use crate::sources::{Source, SourceError};
+use crate::fs::{DiskFs, Fs};
+use crate::sources::{Normalization, Source, SourceError};
 
 // Represents an ordered collection of `Source`s, designed to find meta files
-// for a target item path.
+// for a target item path, along with how item paths should be normalized.
 #[derive(Debug)]
-pub struct Sourcer(Vec<Source>);
+pub struct Sourcer {
+    sources: Vec<Source>,
+    normalization: Normalization,
+}
 
 impl Sourcer {
     pub fn new() -> Self {
-        Self(Vec::new())
+        Self::from(Vec::new())
     }
 
     pub fn source(&mut self, source: Source) -> &mut Self {
-        self.0.push(source);
+        self.sources.push(source);
+        self
+    }
+
+    pub fn normalize_with(&mut self, normalization: Normalization) -> &mut Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    /// Limits the search for root meta files of all sources to directories
+    /// inside of a root directory, see `Source::with_root`.
+    pub fn set_root<P: AsRef<Path>>(&mut self, root: P) -> &mut Self {
+        for source in self.sources.iter_mut() {
+            source.root = Some(root.as_ref().to_path_buf());
+        }
+
         self
     }
 
-    pub fn meta_paths<'a>(&'a self, item_path: &'a Path) -> MetaPaths<'a> {
+    /// Normalizes an item or meta file path according to the normalization
+    /// of this `Sourcer`. If the path cannot be normalized, such as when it
+    /// does not exist, it is returned as given, so that the error can be
+    /// reported when it is accessed.
+    pub fn normalize<'p>(&self, path: &'p Path) -> Cow<'p, Path> {
+        self.normalize_in(&DiskFs, path)
+    }
+
+    /// Similar to `normalize`, but accesses a given filesystem.
+    pub fn normalize_in<'p>(&self, fs: &dyn Fs, path: &'p Path) -> Cow<'p, Path> {
+        self.normalization.normalize_in(fs, path).unwrap_or(Cow::Borrowed(path))
+    }
+
+    /// Async version of `normalize`.
+    #[cfg(feature = "async")]
+    pub async fn normalize_async<'p>(&self, path: &'p Path) -> Cow<'p, Path> {
+        match self.normalization {
+            Normalization::Canonical => {
+                let canon_path = match Normalization::split_file_name(path) {
+                    Some((parent, file_name)) => match tokio::fs::canonicalize(parent).await {
+                        Ok(p) => p.join(file_name),
+                        Err(_) => return Cow::Borrowed(path),
+                    },
+                    None => match tokio::fs::canonicalize(path).await {
+                        Ok(p) => p,
+                        Err(_) => return Cow::Borrowed(path),
+                    },
+                };
+
+                match tokio::fs::metadata(&canon_path).await {
+                    Ok(_) => Cow::Owned(canon_path),
+                    Err(_) => Cow::Borrowed(path),
+                }
+            },
+            Normalization::None | Normalization::Lexical => self.normalize(path),
+        }
+    }
+
+    /// Finds the meta files that provide metadata for an item path, in source
+    /// order. The item path is used as given, without normalization.
+    pub fn meta_paths<'a, 'p>(&'a self, item_path: &'p Path) -> MetaPaths<'a, 'p> {
+        self.meta_paths_in(&DiskFs, item_path)
+    }
+
+    /// Similar to `meta_paths`, but accesses a given filesystem.
+    pub fn meta_paths_in<'a, 'p>(&'a self, fs: &'p dyn Fs, item_path: &'p Path) -> MetaPaths<'a, 'p> {
         MetaPaths {
-            iter: self.0.iter(),
+            iter: self.sources.iter(),
             item_path,
+            fs,
         }
     }
 
+    /// Async version of `meta_paths`. As each source needs to be checked in
+    /// turn, the meta paths are returned in a `Vec`.
+    #[cfg(feature = "async")]
+    pub async fn meta_paths_async<'a>(
+        &'a self,
+        item_path: &Path,
+    ) -> Vec<Result<(PathBuf, &'a Source), SourceError>> {
+        let mut meta_paths = Vec::new();
+
+        for source in self.sources.iter() {
+            match source.meta_path_async(item_path).await {
+                Ok(meta_path) => meta_paths.push(Ok((meta_path, source))),
+                Err(err) if err.is_fatal() => meta_paths.push(Err(err)),
+                Err(_) => {},
+            }
+        }
+
+        meta_paths
+    }
+
     pub fn as_sources(&self) -> &[Source] {
-        self.0.as_slice()
+        self.sources.as_slice()
     }
 }
 
 impl From<Vec<Source>> for Sourcer {
     fn from(value: Vec<Source>) -> Self {
-        Self(value)
+        Self { sources: value, normalization: Normalization::default(), }
     }
 }
 
-pub struct MetaPaths<'a> {
+pub struct MetaPaths<'a, 'p> {
     iter: std::slice::Iter<'a, Source>,
-    item_path: &'a Path,
+    item_path: &'p Path,
+    fs: &'p dyn Fs,
 }
 
-impl<'a> Iterator for MetaPaths<'a> {
+impl<'a> Iterator for MetaPaths<'a, '_> {
     type Item = Result<(PathBuf, &'a Source), SourceError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         while let Some(source) = self.iter.next() {
-            let res = source.meta_path(self.item_path);
+            let res = source.meta_path_in(self.fs, self.item_path);
 
             match res {
                 Ok(meta_path) => {
@@ -16,9 +16,11 @@ use maplit::btreemap;
 use rust_decimal_macros::dec;
 use str_macro::str;
 
+use crate::config::{Config, Merger, Selection, Sorter, VirtualFields};
+use crate::config::selection::Matcher;
 use crate::metadata::schema::Schema;
 use crate::types::{Block, BlockSeq, Sequence, Value};
-use crate::sources::Anchor;
+use crate::sources::{Anchor, Source, Sourcer};
 
 use self::entry::DEFAULT_FLAGGER;
 use self::entry::DEFAULT_LIBRARY;
@@ -60,6 +62,39 @@ impl TestUtil {
         temp_dir
     }
 
+    /// Creates a selection for the sample media test directories, which selects
+    /// all item files besides the JSON meta files.
+    pub fn sample_selection() -> Selection {
+        Selection::new(
+            Matcher::any(),
+            Matcher::build(&["*.json"]).unwrap(),
+            Matcher::any(),
+            Matcher::empty(),
+        )
+    }
+
+    /// Creates a sourcer for the `item.json` and `self.json` meta files of the
+    /// sample media test directories.
+    pub fn sample_sourcer() -> Sourcer {
+        let mut sourcer = Sourcer::new();
+        sourcer
+            .source(Source::from_name(str!("item.json"), Anchor::External).unwrap())
+            .source(Source::from_name(str!("self.json"), Anchor::Internal).unwrap());
+        sourcer
+    }
+
+    /// Creates a config for the sample media test directories, using the
+    /// defaults for everything besides selection and sourcing.
+    pub fn sample_config() -> Config {
+        Config {
+            selection: Self::sample_selection(),
+            sorter: Sorter::default(),
+            sourcer: Self::sample_sourcer(),
+            merger: Merger::default(),
+            virtual_fields: VirtualFields::default(),
+        }
+    }
+
     pub fn sample_string() -> Value {
         Value::String(str!("string"))
     }
@@ -173,6 +208,8 @@ impl TestUtil {
         let anchor_str = match anchor {
             Anchor::Internal => "self",
             Anchor::External => "item",
+            Anchor::Sidecar(..) => "sidecar",
+            Anchor::Root => "root",
         };
 
         block.insert(
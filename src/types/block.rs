@@ -14,8 +14,7 @@ use serde::{Serialize, Deserialize};
 use crate::types::Value;
 
 /// Represents a chunk of metadata for one item.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Block(pub(crate) InnerMap<String, Value>);
 
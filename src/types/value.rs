@@ -23,8 +23,7 @@ pub type Boolean = bool;
 pub type Sequence = Vec<Value>;
 
 /// Represents the types of data that can be used as metadata values.
-#[derive(Debug, Clone, Deserialize, Serialize, EnumDiscriminants)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, EnumDiscriminants)]
 #[serde(untagged)]
 #[strum_discriminants(name(ValueKind), derive(Hash, AsRefStr))]
 pub enum Value {
@@ -5,6 +5,7 @@ use std::path::Ancestors;
 use std::io::Error as IoError;
 
 use crate::config::{Selection, Sorter};
+use crate::fs::{DiskFs, Fs};
 
 /// Generic file walker that supports visiting either parent or child files of
 /// an origin path.
@@ -28,10 +29,15 @@ impl<'p> Iterator for FileWalker<'p> {
 
 impl<'p> FileWalker<'p> {
     pub fn delve(&mut self, selection: &Selection, sorter: &Sorter) -> Result<(), IoError> {
+        self.delve_in(&DiskFs, selection, sorter)
+    }
+
+    /// Similar to `delve`, but accesses a given filesystem.
+    pub fn delve_in(&mut self, fs: &dyn Fs, selection: &Selection, sorter: &Sorter) -> Result<(), IoError> {
         match self {
             // Parent walkers do not have to delve, just no-op.
             Self::Parent(..) => Ok(()),
-            Self::Child(ref mut fw) => fw.delve(selection, sorter),
+            Self::Child(ref mut fw) => fw.delve_in(fs, selection, sorter),
         }
     }
 }
@@ -50,12 +56,23 @@ impl<'p> From<ChildFileWalker<'p>> for FileWalker<'p> {
 
 /// A file walker that starts at an origin path, and walks up the directory tree.
 #[derive(Debug)]
-pub struct ParentFileWalker<'p>(Ancestors<'p>);
+pub struct ParentFileWalker<'p> {
+    ancestors: Ancestors<'p>,
+    root_path: Option<&'p Path>,
+    reached_root: bool,
+}
 
 impl<'p> ParentFileWalker<'p> {
     /// Constructs a new `ParentFileWalker` starting at a specified item path.
     pub fn new(origin_item_path: &'p Path) -> Self {
-        Self(origin_item_path.ancestors())
+        Self { ancestors: origin_item_path.ancestors(), root_path: None, reached_root: false, }
+    }
+
+    /// Constructs a new `ParentFileWalker` starting at a specified item path,
+    /// that stops after visiting a root path. If the item path is not inside
+    /// the root path, nothing is visited.
+    pub fn with_root(origin_item_path: &'p Path, root_path: &'p Path) -> Self {
+        Self { ancestors: origin_item_path.ancestors(), root_path: Some(root_path), reached_root: false, }
     }
 }
 
@@ -63,14 +80,31 @@ impl<'p> Iterator for ParentFileWalker<'p> {
     type Item = Cow<'p, Path>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(Cow::Borrowed)
+        // Once the root path is reached, there is nothing more to visit.
+        if self.reached_root {
+            return None;
+        }
+
+        let path = self.ancestors.next()?;
+
+        if let Some(root_path) = self.root_path {
+            if !path.starts_with(root_path) {
+                return None;
+            }
+
+            self.reached_root = path == root_path;
+        }
+
+        Some(Cow::Borrowed(path))
     }
 }
 
 /// A file walker that starts at an origin path, with the ability to delve
 /// recursively into its directory structure to visit its children, grandchildren, etc.
+/// Ignore files in the origin path and below apply to all of its descendants.
 #[derive(Debug)]
 pub struct ChildFileWalker<'p> {
+    origin_item_path: Cow<'p, Path>,
     frontier: VecDeque<Result<Cow<'p, Path>, IoError>>,
     last_processed_path: Option<Cow<'p, Path>>,
 }
@@ -78,29 +112,40 @@ pub struct ChildFileWalker<'p> {
 impl<'p> ChildFileWalker<'p> {
     /// Constructs a new `ChildFileWalker` starting at a specified item path.
     pub fn new(origin_item_path: &'p Path) -> Self {
+        Self::from_cow(Cow::Borrowed(origin_item_path))
+    }
+
+    /// Similar to `new`, but allows for an owned origin item path.
+    pub(crate) fn from_cow(origin_item_path: Cow<'p, Path>) -> Self {
         let mut frontier = VecDeque::with_capacity(1);
 
         // Initialize the frontier with the origin item.
-        frontier.push_back(Ok(Cow::Borrowed(origin_item_path)));
+        frontier.push_back(Ok(origin_item_path.clone()));
 
         let last_processed_path = None;
 
-        Self { frontier, last_processed_path, }
+        Self { origin_item_path, frontier, last_processed_path, }
     }
 
     /// Manually delves into a directory, and adds its subitems to the frontier.
     /// Note that this is a no-op if the most recent processed path is not a
     /// directory, and not an error.
     pub fn delve(&mut self, selection: &Selection, sorter: &Sorter) -> Result<(), IoError> {
+        self.delve_in(&DiskFs, selection, sorter)
+    }
+
+    /// Similar to `delve`, but accesses a given filesystem.
+    pub fn delve_in(&mut self, fs: &dyn Fs, selection: &Selection, sorter: &Sorter) -> Result<(), IoError> {
         // If there is a last processed path, delve into it.
         // If not, just no-op.
         if let Some(lpp) = self.last_processed_path.take() {
             // Get file info for the last processed path.
-            let file_info = std::fs::metadata(&lpp)?;
+            let file_info = fs.metadata(&lpp)?;
 
             // Only work on directories.
             if file_info.is_dir() {
-                let mut sub_item_paths = selection.select_in_dir_sorted(&lpp, sorter)?;
+                let mut sub_item_paths =
+                    selection.select_in_dir_sorted_within_in(fs, &self.origin_item_path, &lpp, sorter)?;
 
                 // NOTE: Reversing and pushing onto the front of the queue is needed.
                 for p in sub_item_paths.drain(..).rev() {
@@ -147,6 +192,25 @@ mod tests {
         assert_eq!(walker.next().unwrap(), root_dir.path());
     }
 
+    #[test]
+    fn parent_file_walker_with_root() {
+        let root_dir = TestUtil::create_plain_fanout_test_dir("parent_file_walker_with_root", 3, 3);
+
+        let start_path = root_dir.path().join("0").join("0_1").join("0_1_0");
+        let root_path = root_dir.path().join("0");
+        let mut walker = ParentFileWalker::with_root(&start_path, &root_path);
+
+        assert_eq!(walker.next().unwrap(), root_dir.path().join("0").join("0_1").join("0_1_0"));
+        assert_eq!(walker.next().unwrap(), root_dir.path().join("0").join("0_1"));
+        assert_eq!(walker.next().unwrap(), root_dir.path().join("0"));
+        assert!(walker.next().is_none());
+
+        let root_path = root_dir.path().join("1");
+        let mut walker = ParentFileWalker::with_root(&start_path, &root_path);
+
+        assert!(walker.next().is_none());
+    }
+
     #[test]
     fn child_file_walker() {
         let root_dir = TestUtil::create_plain_fanout_test_dir("child_file_walker", 3, 3);
@@ -3,10 +3,7 @@ pub(crate) mod ooms;
 
 pub use self::file_walker::FileWalker;
 
-use std::fs::Metadata;
-use std::io::Result as IoResult;
 use std::path::{Path, Component};
-use std::time::SystemTime;
 
 use thiserror::Error;
 
@@ -26,28 +23,6 @@ pub enum InvalidNameKind {
 pub(crate) struct Util;
 
 impl Util {
-    pub fn stat(path: &Path) -> IoResult<Metadata> {
-        std::fs::metadata(path)
-    }
-
-    pub fn exists(path: &Path) -> IoResult<()> {
-        Self::stat(path).map(|_| ())
-    }
-
-    pub fn is_file(path: &Path) -> IoResult<bool> {
-        Self::stat(path).map(|m| m.is_file())
-    }
-
-    pub fn is_dir(path: &Path) -> IoResult<bool> {
-        Self::stat(path).map(|m| m.is_dir())
-    }
-
-    /// Convenience method that gets the mod time of a path.
-    /// Errors are coerced to `None`.
-    pub fn mtime(abs_path: &Path) -> Option<SystemTime> {
-        abs_path.metadata().and_then(|m| m.modified()).ok()
-    }
-
     /// Tests a string to see if it would be a valid item file name.
     pub fn validate_item_name(name: &str) -> Result<(), InvalidNameKind> {
         // Re-create this name as a file path, and iterate over its components.
@@ -70,39 +45,6 @@ impl Util {
 mod tests {
     use super::*;
 
-    use std::fs::File;
-    use std::time::SystemTime;
-
-    use tempfile::Builder;
-
-    #[test]
-    // NOTE: Using `SystemTime` is not guaranteed to be monotonic, so this test might be fragile.
-    fn mtime() {
-        // Create temp directory.
-        let temp = Builder::new().suffix("mtime").tempdir().unwrap();
-        let tp = temp.path();
-
-        let time_a = SystemTime::now();
-
-        std::thread::sleep(std::time::Duration::from_millis(10));
-
-        // Create a file to get the mtime of.
-        let path = tp.join("file");
-        File::create(&path).unwrap();
-
-        std::thread::sleep(std::time::Duration::from_millis(10));
-
-        let time_b = SystemTime::now();
-
-        let file_time = Util::mtime(&path).unwrap();
-
-        assert_eq!(time_a < file_time, true);
-        assert_eq!(file_time < time_b, true);
-
-        // Test getting time of nonexistent file.
-        assert_eq!(None, Util::mtime(&tp.join("DOES_NOT_EXIST")));
-    }
-
     #[test]
     fn validate_item_name() {
         // Happy path.
@@ -2,32 +2,32 @@ use serde::Deserialize;
 
 #[derive(Deserialize)]
 #[serde(untagged)]
-pub(crate) enum Ooms {
-    One(String),
-    Many(Vec<String>),
+pub(crate) enum Ooms<T = String> {
+    One(T),
+    Many(Vec<T>),
 }
 
-impl Ooms {
-    pub(crate) fn iter(&self) -> OomsIter {
+impl<T> Ooms<T> {
+    pub(crate) fn iter(&self) -> OomsIter<'_, T> {
         match self {
-            Self::One(s) => OomsIter::One(Some(s.as_str())),
+            Self::One(s) => OomsIter::One(Some(s)),
             Self::Many(ss) => OomsIter::Many(ss.iter()),
         }
     }
 }
 
-pub(crate) enum OomsIter<'a> {
-    One(Option<&'a str>),
-    Many(std::slice::Iter<'a, String>),
+pub(crate) enum OomsIter<'a, T = String> {
+    One(Option<&'a T>),
+    Many(std::slice::Iter<'a, T>),
 }
 
-impl<'a> Iterator for OomsIter<'a> {
-    type Item = &'a str;
+impl<'a, T> Iterator for OomsIter<'a, T> {
+    type Item = &'a T;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
             Self::One(o) => o.take(),
-            Self::Many(it) => it.next().map(|s| s.as_str()),
+            Self::Many(it) => it.next(),
         }
     }
 }